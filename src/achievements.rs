@@ -0,0 +1,235 @@
+use std::collections::BTreeSet;
+
+use teloxide::prelude::*;
+
+use crate::daily;
+use crate::db::{Database, QuizStats};
+use crate::quiz::{self, declension::NounCase, kind::QuizKind, mastery::Skill};
+use crate::{is_stress_question, HandlerResult};
+
+const PERFECT_QUIZZES_GOAL: i64 = 10;
+const CORRECT_STRESS_ANSWERS_GOAL: i64 = 100;
+const STREAK_GOAL: usize = 7;
+// The shorter quizzes are too easy to make perfect
+const MIN_PERFECT_QUIZ_SIZE: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Achievement {
+    FirstQuiz,
+    PerfectQuizzes,
+    AllCasesFlawless,
+    HundredStresses,
+    WeekStreak,
+}
+
+impl Achievement {
+    pub const ALL: [Achievement; 5] = [
+        Achievement::FirstQuiz,
+        Achievement::PerfectQuizzes,
+        Achievement::AllCasesFlawless,
+        Achievement::HundredStresses,
+        Achievement::WeekStreak,
+    ];
+
+    // How the achievement is stored in the database
+    pub fn key(&self) -> &'static str {
+        match self {
+            Achievement::FirstQuiz => "first_quiz",
+            Achievement::PerfectQuizzes => "perfect_quizzes",
+            Achievement::AllCasesFlawless => "all_cases_flawless",
+            Achievement::HundredStresses => "hundred_stresses",
+            Achievement::WeekStreak => "week_streak",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Achievement::FirstQuiz => "🎉 Перший тест",
+            Achievement::PerfectQuizzes => "💯 10 ідеальних тестів",
+            Achievement::AllCasesFlawless => "🧩 Усі відмінки без помилок",
+            Achievement::HundredStresses => "🎯 100 наголосів",
+            Achievement::WeekStreak => "🔥 7 днів поспіль",
+        }
+    }
+
+    pub fn description(&self) -> String {
+        match self {
+            Achievement::FirstQuiz => "пройти перший тест".to_string(),
+            Achievement::PerfectQuizzes => format!(
+                "пройти {} тестів без жодної помилки (не коротших за {} питань)",
+                PERFECT_QUIZZES_GOAL, MIN_PERFECT_QUIZ_SIZE
+            ),
+            Achievement::AllCasesFlawless => {
+                "в одному тесті правильно поставити іменники в усі відмінки, крім називного, не помилившись у жодному".to_string()
+            }
+            Achievement::HundredStresses => format!("правильно поставити наголос {} разів", CORRECT_STRESS_ANSWERS_GOAL),
+            Achievement::WeekStreak => format!("проходити виклик дня (/daily) {} днів поспіль", STREAK_GOAL),
+        }
+    }
+}
+
+// What the finished quiz adds to the totals
+fn quiz_stats(quiz: &quiz::Quiz, score: usize) -> QuizStats {
+    let correct_stress_answers = quiz
+        .questions
+        .iter()
+        .zip(&quiz.results)
+        .filter(|(question, correct)| **correct && is_stress_question(question))
+        .count();
+    QuizStats {
+        quizzes: 1,
+        perfect_quizzes: i64::from(quiz.questions.len() >= MIN_PERFECT_QUIZ_SIZE && score == quiz.questions.len()),
+        correct_stress_answers: correct_stress_answers as i64,
+    }
+}
+
+// Every case the declension questions ask about is in the quiz and all of them are answered right
+fn all_cases_flawless(quiz: &quiz::Quiz) -> bool {
+    let mut cases = BTreeSet::new();
+    for (idx, question) in quiz.questions.iter().enumerate() {
+        if question.kind != Some(QuizKind::Declensions) {
+            continue;
+        }
+        if !quiz.results.get(idx).copied().unwrap_or_default() {
+            return false;
+        }
+        if let Some(Skill::Case(case)) = &question.skill {
+            cases.insert(case.clone());
+        }
+    }
+    NounCase::ASKED.iter().all(|case| cases.contains(case))
+}
+
+/// Counts the finished quiz in and returns the achievements earned with it.
+/// Called after the day's challenge result is saved, so the streak includes the quiz.
+pub async fn evaluate(
+    db: &Database,
+    chat_id: ChatId,
+    quiz: &quiz::Quiz,
+    score: usize,
+) -> Result<Vec<Achievement>, sqlx::Error> {
+    let stats = db.add_quiz_stats(chat_id, quiz_stats(quiz, score)).await?;
+    let streak = daily::current_streak(db, chat_id).await?;
+
+    let mut earned = Vec::new();
+    for achievement in Achievement::ALL {
+        let reached = match achievement {
+            Achievement::FirstQuiz => stats.quizzes >= 1,
+            Achievement::PerfectQuizzes => stats.perfect_quizzes >= PERFECT_QUIZZES_GOAL,
+            Achievement::AllCasesFlawless => all_cases_flawless(quiz),
+            Achievement::HundredStresses => stats.correct_stress_answers >= CORRECT_STRESS_ANSWERS_GOAL,
+            Achievement::WeekStreak => streak >= STREAK_GOAL,
+        };
+        if reached && db.award_achievement(chat_id, achievement.key()).await? {
+            earned.push(achievement);
+        }
+    }
+    Ok(earned)
+}
+
+/// Tells about the achievements just earned, if any
+pub async fn announce(bot: &Bot, chat_id: ChatId, earned: &[Achievement]) -> HandlerResult {
+    if earned.is_empty() {
+        return Ok(());
+    }
+    let lines = earned
+        .iter()
+        .map(|a| format!("{} -- {}", a.name(), a.description()))
+        .collect::<Vec<_>>();
+    bot.send_message(
+        chat_id,
+        format!(
+            "🏆 Нове досягнення!\n\n{}\n\nУсі досягнення -- /achievements",
+            lines.join("\n")
+        ),
+    )
+    .await?;
+    Ok(())
+}
+
+/// `/achievements` -- the earned ones with the dates and the ones to go for
+pub async fn list(bot: &Bot, chat_id: ChatId, db: &Database) -> HandlerResult {
+    let earned = db.achievements(chat_id).await?;
+    let lines = Achievement::ALL
+        .iter()
+        .map(|a| match earned.get(a.key()) {
+            Some(date) => format!("✅ {} ({})", a.name(), date),
+            None => format!("▫️ {} -- {}", a.name(), a.description()),
+        })
+        .collect::<Vec<_>>();
+    bot.send_message(
+        chat_id,
+        format!(
+            "Досягнення: {} з {}\n\n{}",
+            Achievement::ALL.iter().filter(|a| earned.contains_key(a.key())).count(),
+            Achievement::ALL.len(),
+            lines.join("\n")
+        ),
+    )
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quiz::{ai_helper::Personality, Answer, Question};
+
+    async fn open_db() -> Database {
+        let path = std::env::temp_dir().join(format!("achievements-{}.sqlite", rand::random::<u64>()));
+        Database::open(path.to_str().unwrap(), Personality::default()).await.unwrap()
+    }
+
+    fn declension_question(case: NounCase) -> Question {
+        Question::new("?".to_string(), vec![Answer::new("так".to_string(), true)])
+            .of_kind(QuizKind::Declensions)
+            .with_skill(Skill::Case(case))
+    }
+
+    fn quiz_of(cases: &[NounCase], results: Vec<bool>) -> quiz::Quiz {
+        let mut quiz = quiz::Quiz::new(cases.iter().cloned().map(declension_question).collect());
+        quiz.results = results;
+        quiz
+    }
+
+    #[tokio::test]
+    async fn all_the_asked_cases_without_mistakes_earn_the_achievement() {
+        let db = open_db().await;
+        let quiz = quiz_of(&NounCase::ASKED, vec![true; NounCase::ASKED.len()]);
+
+        let earned = evaluate(&db, ChatId(1), &quiz, NounCase::ASKED.len()).await.unwrap();
+        assert!(earned.contains(&Achievement::FirstQuiz));
+        assert!(earned.contains(&Achievement::AllCasesFlawless));
+
+        // The achievements are earned once
+        let earned = evaluate(&db, ChatId(1), &quiz, NounCase::ASKED.len()).await.unwrap();
+        assert!(earned.is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_mistake_or_a_missing_case_earns_nothing_for_the_cases() {
+        let db = open_db().await;
+        let mut results = vec![true; NounCase::ASKED.len()];
+        results[0] = false;
+        let with_mistake = quiz_of(&NounCase::ASKED, results);
+        let earned = evaluate(&db, ChatId(1), &with_mistake, NounCase::ASKED.len() - 1).await.unwrap();
+        assert_eq!(earned, vec![Achievement::FirstQuiz]);
+
+        let missing_case = quiz_of(&NounCase::ASKED[1..], vec![true; NounCase::ASKED.len() - 1]);
+        let earned = evaluate(&db, ChatId(1), &missing_case, NounCase::ASKED.len() - 1).await.unwrap();
+        assert!(earned.is_empty());
+    }
+
+    #[tokio::test]
+    async fn the_tenth_perfect_quiz_earns_the_achievement() {
+        let db = open_db().await;
+        let cases = vec![NounCase::Genitive; MIN_PERFECT_QUIZ_SIZE];
+        let quiz = quiz_of(&cases, vec![true; MIN_PERFECT_QUIZ_SIZE]);
+        for _ in 1..PERFECT_QUIZZES_GOAL {
+            let earned = evaluate(&db, ChatId(1), &quiz, MIN_PERFECT_QUIZ_SIZE).await.unwrap();
+            assert!(!earned.contains(&Achievement::PerfectQuizzes));
+        }
+        let earned = evaluate(&db, ChatId(1), &quiz, MIN_PERFECT_QUIZ_SIZE).await.unwrap();
+        assert_eq!(earned, vec![Achievement::PerfectQuizzes]);
+    }
+}
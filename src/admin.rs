@@ -0,0 +1,134 @@
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use teloxide::{prelude::*, RequestError};
+
+use crate::db::Database;
+use crate::quiz::{
+    ai_helper::QuizHelper,
+    kind::QuizKind,
+    registry::DataRegistry,
+};
+use crate::HandlerResult;
+
+// The chats of the admins, see `AdminConfig`; in the private chats it's the user id
+static ADMIN_CHAT_IDS: OnceLock<Vec<ChatId>> = OnceLock::new();
+
+/// Sets the admins once at startup, from the config
+pub fn set_admins(chat_ids: Vec<ChatId>) {
+    if ADMIN_CHAT_IDS.set(chat_ids).is_err() {
+        log::warn!("The admins are already set");
+    }
+}
+
+pub fn is_admin(chat_id: ChatId) -> bool {
+    ADMIN_CHAT_IDS.get().is_some_and(|ids| ids.contains(&chat_id))
+}
+
+// The usage stats are shown for the last week
+const USAGE_DAYS: u32 = 7;
+// Telegram lets the bots send about 30 messages a second
+const BROADCAST_DELAY: Duration = Duration::from_millis(50);
+
+/// Tells the user the command is for the admins only, returns whether the user is an admin
+pub async fn ensure_admin(bot: &Bot, chat_id: ChatId) -> Result<bool, RequestError> {
+    if is_admin(chat_id) {
+        return Ok(true);
+    }
+    bot.send_message(chat_id, "Ця команда лише для адміністраторів").await?;
+    Ok(false)
+}
+
+/// `/broadcast <text>` -- sends the text to all the users, in the background, since it may take a while
+pub async fn broadcast(bot: &Bot, chat_id: ChatId, text: &str, db: &Arc<Database>) -> HandlerResult {
+    if !ensure_admin(bot, chat_id).await? {
+        return Ok(());
+    }
+    let text = text.trim();
+    if text.is_empty() {
+        bot.send_message(chat_id, "Напиши текст розсилки, наприклад: /broadcast Нові тести вже в меню!")
+            .await?;
+        return Ok(());
+    }
+
+    let recipients = db.private_chats().await?;
+    bot.send_message(chat_id, format!("Розсилаю {} користувачам...", recipients.len()))
+        .await?;
+    let (bot, text) = (bot.clone(), text.to_string());
+    tokio::spawn(async move {
+        let (mut sent, mut failed) = (0, 0);
+        for recipient in recipients {
+            // e.g. the user has blocked the bot
+            match bot.send_message(recipient, text.as_str()).await {
+                Ok(_) => sent += 1,
+                Err(e) => {
+                    log::warn!("Failed to broadcast to {}: {}", recipient, e);
+                    failed += 1;
+                }
+            }
+            tokio::time::sleep(BROADCAST_DELAY).await;
+        }
+        let report = format!("Розсилку завершено: надіслано {}, не вдалося {}", sent, failed);
+        if let Err(e) = bot.send_message(chat_id, report).await {
+            log::error!("Failed to report the broadcast: {}", e);
+        }
+    });
+    Ok(())
+}
+
+/// `/usage` -- the active users, the quizzes and the AI requests
+pub async fn usage(bot: &Bot, chat_id: ChatId, ai_helper: &QuizHelper, db: &Database) -> HandlerResult {
+    if !ensure_admin(bot, chat_id).await? {
+        return Ok(());
+    }
+
+    let active_users = db
+        .active_users(USAGE_DAYS)
+        .await?
+        .into_iter()
+        .map(|(day, users)| format!("{} -- {}", day, users))
+        .collect::<Vec<_>>();
+    let quizzes = db
+        .quizzes_by_kind(USAGE_DAYS)
+        .await?
+        .into_iter()
+        .map(|(kind, count)| {
+            let name = QuizKind::from_key(&kind).map(|k| k.name().to_string());
+            format!("• {} -- {}", name.as_deref().unwrap_or("Змішаний"), count)
+        })
+        .collect::<Vec<_>>();
+    let (ai_calls, ai_failures) = ai_helper.usage();
+
+    let or_none = |lines: Vec<String>| if lines.is_empty() { "немає".to_string() } else { lines.join("\n") };
+    bot.send_message(
+        chat_id,
+        format!(
+            "Активні користувачі за днями (UTC):\n{}\n\nЗавершені тести за {} днів:\n{}\n\n\
+            Запитів до ШІ з запуску бота: {}, невдалих: {}",
+            or_none(active_users),
+            USAGE_DAYS,
+            or_none(quizzes),
+            ai_calls,
+            ai_failures
+        ),
+    )
+    .await?;
+    Ok(())
+}
+
+/// `/reload` -- reads the dictionaries anew, the dialogues and the quizzes going on aren't affected
+pub async fn reload(bot: &Bot, chat_id: ChatId, registry: &DataRegistry) -> HandlerResult {
+    if !ensure_admin(bot, chat_id).await? {
+        return Ok(());
+    }
+    bot.send_message(chat_id, "Перечитую словники...").await?;
+    let text = match registry.reload().await {
+        Ok(()) => "Словники оновлено".to_string(),
+        Err(e) => {
+            log::error!("Failed to reload the dictionaries: {}", e);
+            format!("Не вдалося оновити словники, працюють попередні: {}", e)
+        }
+    };
+    bot.send_message(chat_id, text).await?;
+    Ok(())
+}
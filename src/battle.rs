@@ -0,0 +1,334 @@
+use std::time::Duration;
+
+use teloxide::{prelude::*, types::ParseMode, RequestError};
+
+use crate::chat_lock::lock_chat;
+use crate::metrics;
+use crate::presentation::{button_answer, send_question, Presentation};
+use crate::quiz::{
+    self,
+    kind::{Datasets, QuizCode, QuizKind, MAX_QUIZ_SIZE},
+};
+use crate::{HandlerResult, QuizDialogue, State};
+
+const DEFAULT_BATTLE_SIZE: usize = 10;
+// How long the group has to answer each question
+const QUESTION_TIME: Duration = Duration::from_secs(20);
+const CORRECT_ANSWER_POINTS: usize = 1;
+// For the first correct answer to the question
+const FASTEST_ANSWER_BONUS: usize = 1;
+
+// The battle as it is now, read under the lock
+async fn current_battle(dialogue: &QuizDialogue) -> Result<Option<Battle>, Box<dyn std::error::Error + Send + Sync>> {
+    match dialogue.get().await? {
+        Some(State::Battle { battle }) => Ok(Some(battle)),
+        _ => Ok(None),
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Player {
+    // The answers are tracked by the user, since everyone answers in the same group chat
+    pub user_id: u64,
+    pub name: String,
+    pub score: usize,
+    pub correct_answers: usize,
+    pub fastest_answers: usize,
+}
+
+/// The quiz the whole group chat answers at once
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Battle {
+    pub quiz: quiz::Quiz,
+    // Who has started the battle, only they and the admins of the chat may skip the questions or cancel it
+    #[serde(default)]
+    pub starter_id: u64,
+    pub question_number: usize,
+    pub players: Vec<Player>,
+    // Who has answered the current question already
+    pub answered: Vec<u64>,
+    // The first one who has answered the current question correctly
+    pub fastest: Option<u64>,
+}
+
+enum AnswerOutcome {
+    AlreadyAnswered,
+    Wrong,
+    Correct { points: usize, fastest: bool },
+}
+
+impl Battle {
+    fn new(quiz: quiz::Quiz, starter_id: u64) -> Self {
+        Self {
+            quiz,
+            starter_id,
+            question_number: 0,
+            players: Vec::new(),
+            answered: Vec::new(),
+            fastest: None,
+        }
+    }
+
+    fn answer(&mut self, user: &teloxide::types::User, answer: &quiz::Answer) -> AnswerOutcome {
+        let user_id = user.id.0;
+        if self.answered.contains(&user_id) {
+            return AnswerOutcome::AlreadyAnswered;
+        }
+        self.answered.push(user_id);
+
+        let player = match self.players.iter().position(|p| p.user_id == user_id) {
+            Some(idx) => &mut self.players[idx],
+            None => {
+                self.players.push(Player {
+                    user_id,
+                    name: user.full_name(),
+                    score: 0,
+                    correct_answers: 0,
+                    fastest_answers: 0,
+                });
+                self.players.last_mut().unwrap()
+            }
+        };
+
+        if !answer.is_correct {
+            return AnswerOutcome::Wrong;
+        }
+        let fastest = self.fastest.is_none();
+        let points = if fastest {
+            self.fastest = Some(user_id);
+            player.fastest_answers += 1;
+            CORRECT_ANSWER_POINTS + FASTEST_ANSWER_BONUS
+        } else {
+            CORRECT_ANSWER_POINTS
+        };
+        player.correct_answers += 1;
+        player.score += points;
+        AnswerOutcome::Correct { points, fastest }
+    }
+
+    fn leaderboard(&self) -> String {
+        if self.players.is_empty() {
+            return "Ніхто не відповів на жодне питання 🤷".to_string();
+        }
+
+        let mut players = self.players.iter().collect::<Vec<_>>();
+        players.sort_by(|a, b| b.score.cmp(&a.score).then(b.correct_answers.cmp(&a.correct_answers)));
+        let lines = players
+            .iter()
+            .enumerate()
+            .map(|(place, player)| {
+                let medal = match place {
+                    0 => "🥇",
+                    1 => "🥈",
+                    2 => "🥉",
+                    _ => "•",
+                };
+                format!(
+                    "{} {} -- {} (правильних відповідей: {}, найшвидших: {})",
+                    medal, player.name, player.score, player.correct_answers, player.fastest_answers
+                )
+            })
+            .collect::<Vec<_>>();
+        format!("Результати батлу:\n\n{}", lines.join("\n"))
+    }
+}
+
+/// `/battle stress 10` in a group chat
+pub async fn start_battle(
+    bot: &Bot,
+    dialogue: &QuizDialogue,
+    msg: &Message,
+    datasets: &Datasets,
+    args: &str,
+) -> HandlerResult {
+    if msg.chat.is_private() {
+        bot.send_message(msg.chat.id, "Батли проводяться в групових чатах. Додай мене в групу і напиши там /battle")
+            .await?;
+        return Ok(());
+    }
+    let _lock = lock_chat(dialogue.chat_id()).await;
+    if current_battle(dialogue).await?.is_some() {
+        bot.send_message(msg.chat.id, "Батл уже йде! Скасувати його -- /cancel").await?;
+        return Ok(());
+    }
+
+    let mut args = args.split_whitespace();
+    let kind = args.next().map(QuizKind::from_key);
+    let size = args.next().map(|size| size.parse::<usize>().ok().filter(|s| (1..=MAX_QUIZ_SIZE).contains(s)));
+    let (Some(kind), Some(size)) = (kind.unwrap_or(Some(QuizKind::Stress)), size.unwrap_or(Some(DEFAULT_BATTLE_SIZE)))
+    else {
+        bot.send_message(
+            msg.chat.id,
+            format!(
+                "Напиши, наприклад: /battle stress 10\nТести: {}\nПитань -- від 1 до {}",
+                QuizKind::ALL.iter().map(|k| k.key()).collect::<Vec<_>>().join(", "),
+                MAX_QUIZ_SIZE
+            ),
+        )
+        .await?;
+        return Ok(());
+    };
+
+    let Some(questions) = datasets.generate_quiz(&QuizCode::random(kind, size, None)) else {
+        bot.send_message(msg.chat.id, "На жаль, цей тест зараз недоступний").await?;
+        return Ok(());
+    };
+
+    bot.send_message(
+        msg.chat.id,
+        format!(
+            "Батл починається! {}, {} питань, {} секунд на кожне.\n\
+            Відповідати можуть усі: за правильну відповідь -- {} бал, найшвидший отримує ще {}.",
+            kind.name(),
+            questions.len(),
+            QUESTION_TIME.as_secs(),
+            CORRECT_ANSWER_POINTS,
+            FASTEST_ANSWER_BONUS
+        ),
+    )
+    .await?;
+
+    let starter_id = msg.from().map_or(0, |user| user.id.0);
+    ask_question(bot, dialogue, Battle::new(quiz::Quiz::new(questions), starter_id)).await
+}
+
+async fn ask_question(bot: &Bot, dialogue: &QuizDialogue, mut battle: Battle) -> HandlerResult {
+    let question_number = battle.question_number;
+    let text = format!(
+        "Питання №{} з {}:\n{}",
+        question_number + 1,
+        battle.quiz.questions.len(),
+        battle.quiz.questions[question_number].text
+    );
+    // Only the buttons, the poll answers don't tell which chat they are from
+    send_question(bot, dialogue.chat_id(), &mut battle.quiz, question_number, text, Presentation::Buttons).await?;
+
+    let quiz_id = battle.quiz.id;
+    dialogue.update(State::Battle { battle }).await?;
+
+    schedule_close(bot.clone(), dialogue.clone(), quiz_id, question_number);
+    Ok(())
+}
+
+// The question is closed when the time is up, unless it's closed already (e.g. with /skip).
+// It's a plain function, since the task asks the next question, which schedules the task again.
+fn schedule_close(bot: Bot, dialogue: QuizDialogue, quiz_id: u64, question_number: usize) {
+    tokio::spawn(async move {
+        tokio::time::sleep(QUESTION_TIME).await;
+        let _lock = lock_chat(dialogue.chat_id()).await;
+        let result = match current_battle(&dialogue).await {
+            Ok(Some(battle)) if battle.quiz.id == quiz_id && battle.question_number == question_number => {
+                close_question(&bot, &dialogue, battle).await
+            }
+            Ok(_) => Ok(()),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            log::error!("Failed to close the battle question: {}", e);
+        }
+    });
+}
+
+// Whether the sender of the command has started the battle or is an admin of the chat
+async fn may_control(bot: &Bot, msg: &Message, battle: &Battle) -> Result<bool, RequestError> {
+    let Some(user) = msg.from() else {
+        return Ok(false);
+    };
+    if user.id.0 == battle.starter_id {
+        return Ok(true);
+    }
+    Ok(bot.get_chat_member(msg.chat.id, user.id).await?.is_privileged())
+}
+
+/// `/skip` -- closes the current question without waiting for the time to be up
+pub async fn skip_battle_question(bot: &Bot, dialogue: &QuizDialogue, msg: &Message) -> HandlerResult {
+    let _lock = lock_chat(dialogue.chat_id()).await;
+    let Some(battle) = current_battle(dialogue).await? else {
+        return Ok(());
+    };
+    if !may_control(bot, msg, &battle).await? {
+        bot.send_message(msg.chat.id, "Пропустити питання може лише той, хто почав батл, або адміністратор чату")
+            .await?;
+        return Ok(());
+    }
+    close_question(bot, dialogue, battle).await
+}
+
+/// `/cancel` -- ends the battle before all the questions are asked
+pub async fn cancel_battle(bot: &Bot, dialogue: &QuizDialogue, msg: &Message) -> HandlerResult {
+    let _lock = lock_chat(dialogue.chat_id()).await;
+    let Some(battle) = current_battle(dialogue).await? else {
+        bot.send_message(msg.chat.id, "Зараз немає батлу, який можна скасувати").await?;
+        return Ok(());
+    };
+    if !may_control(bot, msg, &battle).await? {
+        bot.send_message(msg.chat.id, "Скасувати батл може лише той, хто його почав, або адміністратор чату")
+            .await?;
+        return Ok(());
+    }
+    bot.send_message(msg.chat.id, format!("Батл скасовано.\n\n{}", battle.leaderboard()))
+        .await?;
+    dialogue.update(State::Start).await?;
+    Ok(())
+}
+
+// Shows the correct answer and goes on with the next question or the leaderboard
+async fn close_question(bot: &Bot, dialogue: &QuizDialogue, mut battle: Battle) -> HandlerResult {
+    let question = &battle.quiz.questions[battle.question_number];
+    if let Some(correct_answer) = question.answers.iter().find(|a| a.is_correct) {
+        let fastest = battle
+            .fastest
+            .and_then(|user_id| battle.players.iter().find(|p| p.user_id == user_id))
+            .map(|p| format!("\nНайшвидше правильно відповів(-ла): {}", p.name))
+            .unwrap_or_default();
+        bot.send_message(
+            dialogue.chat_id(),
+            format!("Правильна відповідь -- <b>{}</b>{}", correct_answer.text, fastest),
+        )
+        .parse_mode(ParseMode::Html)
+        .await?;
+    }
+
+    battle.question_number += 1;
+    battle.answered.clear();
+    battle.fastest = None;
+    if battle.question_number < battle.quiz.questions.len() {
+        return ask_question(bot, dialogue, battle).await;
+    }
+
+    bot.send_message(dialogue.chat_id(), battle.leaderboard()).await?;
+    metrics::quiz_finished(battle.quiz.kind_key());
+    dialogue.update(State::Start).await?;
+    Ok(())
+}
+
+pub async fn receive_battle_answer(bot: Bot, dialogue: QuizDialogue, q: CallbackQuery) -> HandlerResult {
+    let _lock = lock_chat(dialogue.chat_id()).await;
+    let battle = current_battle(&dialogue).await?;
+    let Some((mut battle, answer)) = battle.and_then(|battle| {
+        let (_, answer) = button_answer(&q, &battle.quiz, battle.question_number)?;
+        Some((battle, answer))
+    }) else {
+        bot.answer_callback_query(q.id)
+            .text("Це питання вже неактуальне")
+            .await?;
+        return Ok(());
+    };
+
+    let outcome = battle.answer(&q.from, &answer);
+    if !matches!(outcome, AnswerOutcome::AlreadyAnswered) {
+        let kind = battle.quiz.questions[battle.question_number].kind.map_or("unknown", |kind| kind.key());
+        metrics::answer(kind, answer.is_correct);
+    }
+    let text = match outcome {
+        AnswerOutcome::AlreadyAnswered => "Ти вже відповів(-ла) на це питання".to_string(),
+        AnswerOutcome::Wrong => "Неправильно!".to_string(),
+        AnswerOutcome::Correct { points, fastest: true } => format!("Правильно, і найшвидше! +{}", points),
+        AnswerOutcome::Correct { points, fastest: false } => format!("Правильно! +{}", points),
+    };
+    // Only the one who has answered sees the result, so the others can't copy the answer
+    bot.answer_callback_query(q.id).text(text).await?;
+
+    dialogue.update(State::Battle { battle }).await?;
+    Ok(())
+}
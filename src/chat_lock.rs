@@ -0,0 +1,14 @@
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, Mutex};
+
+use teloxide::types::ChatId;
+
+// The dispatcher handles the updates of a chat one by one, but the question timers run on their own.
+// The updates and the timers changing the same dialogue state take turns with this lock.
+// There is a lock per chat, they are tiny, so they are never removed.
+static CHAT_LOCKS: LazyLock<Mutex<HashMap<ChatId, Arc<tokio::sync::Mutex<()>>>>> = LazyLock::new(Default::default);
+
+pub async fn lock_chat(chat_id: ChatId) -> tokio::sync::OwnedMutexGuard<()> {
+    let lock = CHAT_LOCKS.lock().unwrap().entry(chat_id).or_default().clone();
+    lock.lock_owned().await
+}
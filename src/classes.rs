@@ -0,0 +1,309 @@
+use std::sync::OnceLock;
+
+use chrono::{NaiveDate, Utc};
+use rand::Rng;
+use teloxide::{
+    prelude::*,
+    types::{InlineKeyboardButton, InlineKeyboardMarkup},
+};
+
+use crate::db::{Assignment, Database};
+use crate::quiz::kind::{Difficulty, QuizKind, MAX_QUIZ_SIZE};
+use crate::HandlerResult;
+
+pub const HOMEWORK_CALLBACK_PREFIX: &str = "homework:";
+
+// Without the look-alike characters (0 and O, 1 and I), since the students type the code by hand
+const JOIN_CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const JOIN_CODE_LENGTH: usize = 6;
+const MAX_JOIN_CODE_ATTEMPTS: usize = 10;
+
+// Who may create the classes, see `ClassesConfig`
+static TEACHER_CHAT_IDS: OnceLock<Vec<ChatId>> = OnceLock::new();
+
+/// Sets the teachers once at startup, from the config
+pub fn set_teachers(chat_ids: Vec<ChatId>) {
+    if TEACHER_CHAT_IDS.set(chat_ids).is_err() {
+        log::warn!("The teachers are already set");
+    }
+}
+
+fn is_teacher(chat_id: ChatId) -> bool {
+    TEACHER_CHAT_IDS.get().is_some_and(|ids| ids.contains(&chat_id))
+}
+
+fn generate_join_code() -> String {
+    let mut rng = rand::thread_rng();
+    (0..JOIN_CODE_LENGTH)
+        .map(|_| JOIN_CODE_ALPHABET[rng.gen_range(0..JOIN_CODE_ALPHABET.len())] as char)
+        .collect()
+}
+
+/// `/newclass Назва` -- the one who creates the class becomes its teacher
+pub async fn create_class(bot: &Bot, msg: &Message, db: &Database, name: &str) -> HandlerResult {
+    if !is_teacher(msg.chat.id) {
+        bot.send_message(
+            msg.chat.id,
+            "Створювати класи можуть лише вчителі. Попроси адміністратора бота додати тебе до них",
+        )
+        .await?;
+        return Ok(());
+    }
+    let name = name.trim();
+    if name.is_empty() {
+        bot.send_message(msg.chat.id, "Вкажи назву класу, наприклад: /newclass 7-Б")
+            .await?;
+        return Ok(());
+    }
+
+    for _ in 0..MAX_JOIN_CODE_ATTEMPTS {
+        let join_code = generate_join_code();
+        if db.create_class(msg.chat.id, name, &join_code).await?.is_some() {
+            bot.send_message(
+                msg.chat.id,
+                format!(
+                    "Клас \"{}\" створено! Учні можуть приєднатися до нього командою:\n/join {}\n\n\
+                    Задати домашнє завдання -- /assign, переглянути результати -- /results {}",
+                    name, join_code, join_code
+                ),
+            )
+            .await?;
+            return Ok(());
+        }
+    }
+
+    log::error!("Failed to find a free join code in {} attempts", MAX_JOIN_CODE_ATTEMPTS);
+    bot.send_message(msg.chat.id, "Не вдалося створити клас, спробуй ще раз")
+        .await?;
+    Ok(())
+}
+
+/// `/classes` -- the classes the user teaches and studies in
+pub async fn list_classes(bot: &Bot, msg: &Message, db: &Database) -> HandlerResult {
+    let taught = db.teacher_classes(msg.chat.id).await?;
+    let studied = db.student_classes(msg.chat.id).await?;
+
+    if taught.is_empty() && studied.is_empty() {
+        bot.send_message(
+            msg.chat.id,
+            "Ти ще не в жодному класі. Приєднатися до класу -- /join КОД, створити свій -- /newclass Назва",
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let mut text = String::new();
+    if !taught.is_empty() {
+        text.push_str("Твої класи:\n");
+        for class in &taught {
+            text.push_str(&format!(
+                "• {} -- код {}, учнів: {}\n",
+                class.name, class.join_code, class.members_count
+            ));
+        }
+    }
+    if !studied.is_empty() {
+        if !text.is_empty() {
+            text.push('\n');
+        }
+        text.push_str("Ти навчаєшся в класах:\n");
+        for class in &studied {
+            text.push_str(&format!("• {}\n", class.name));
+        }
+    }
+    bot.send_message(msg.chat.id, text).await?;
+    Ok(())
+}
+
+/// `/join КОД`
+pub async fn join_class(bot: &Bot, msg: &Message, db: &Database, join_code: &str) -> HandlerResult {
+    let join_code = join_code.trim().to_uppercase();
+    let Some(class) = db.find_class(&join_code).await? else {
+        bot.send_message(msg.chat.id, "Класу з таким кодом немає. Перевір код і спробуй ще раз: /join КОД")
+            .await?;
+        return Ok(());
+    };
+    if class.teacher_chat_id == msg.chat.id {
+        bot.send_message(msg.chat.id, "Це твій клас, ти в ньому вчитель").await?;
+        return Ok(());
+    }
+
+    let text = if db.join_class(class.id, msg.chat.id).await? {
+        format!("Ти приєднався до класу \"{}\"! Домашні завдання з'являться в меню", class.name)
+    } else {
+        format!("Ти вже в класі \"{}\"", class.name)
+    };
+    bot.send_message(msg.chat.id, text).await?;
+    Ok(())
+}
+
+fn assign_usage() -> String {
+    format!(
+        "Щоб задати домашнє завдання, напиши:\n/assign КОД тест кількість складність дата\n\n\
+        Наприклад: /assign ABC234 stress 10 easy 25.10.2026\n\n\
+        Тести: {}\nСкладність: {}\nДата -- останній день, коли можна виконати завдання",
+        QuizKind::ALL
+            .iter()
+            .map(|k| format!("{} ({})", k.key(), k.name()))
+            .collect::<Vec<_>>()
+            .join(", "),
+        Difficulty::ALL
+            .iter()
+            .map(|d| format!("{} ({})", d.key(), d.name()))
+            .collect::<Vec<_>>()
+            .join(", "),
+    )
+}
+
+/// Accepts both 25.10.2026 and 2026-10-25
+fn parse_date(date: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(date, "%d.%m.%Y")
+        .or_else(|_| NaiveDate::parse_from_str(date, "%Y-%m-%d"))
+        .ok()
+}
+
+/// `/assign КОД тест кількість складність дата`
+pub async fn create_assignment(bot: &Bot, msg: &Message, db: &Database, args: &str) -> HandlerResult {
+    let args = args.split_whitespace().collect::<Vec<_>>();
+    let [join_code, kind, size, difficulty, deadline] = args.as_slice() else {
+        bot.send_message(msg.chat.id, assign_usage()).await?;
+        return Ok(());
+    };
+
+    let class = db
+        .find_class(&join_code.to_uppercase())
+        .await?
+        .filter(|class| class.teacher_chat_id == msg.chat.id);
+    let Some(class) = class else {
+        bot.send_message(msg.chat.id, "У тебе немає класу з таким кодом. Твої класи -- /classes")
+            .await?;
+        return Ok(());
+    };
+
+    let kind = QuizKind::from_key(kind);
+    let size = size.parse::<usize>().ok().filter(|s| (1..=MAX_QUIZ_SIZE).contains(s));
+    let difficulty = Difficulty::from_key(difficulty);
+    let deadline = parse_date(deadline);
+    let (Some(kind), Some(size), Some(difficulty), Some(deadline)) = (kind, size, difficulty, deadline) else {
+        bot.send_message(
+            msg.chat.id,
+            format!("Не вдалося розібрати завдання (кількість питань -- від 1 до {}).\n\n{}", MAX_QUIZ_SIZE, assign_usage()),
+        )
+        .await?;
+        return Ok(());
+    };
+    // The dates of the database are UTC too
+    if deadline < Utc::now().date_naive() {
+        bot.send_message(msg.chat.id, "Ця дата вже минула, обери іншу").await?;
+        return Ok(());
+    }
+
+    let deadline = deadline.format("%Y-%m-%d").to_string();
+    db.create_assignment(class.id, kind, size, difficulty, &deadline)
+        .await?;
+    bot.send_message(
+        msg.chat.id,
+        format!(
+            "Завдання для класу \"{}\" задано: {}, {} питань, {} рівень, до {} включно",
+            class.name,
+            kind.name(),
+            size,
+            difficulty.name(),
+            deadline
+        ),
+    )
+    .await?;
+    Ok(())
+}
+
+/// `/results КОД` -- how each student of the class has done each homework
+pub async fn show_results(bot: &Bot, msg: &Message, db: &Database, join_code: &str) -> HandlerResult {
+    let class = db
+        .find_class(&join_code.trim().to_uppercase())
+        .await?
+        .filter(|class| class.teacher_chat_id == msg.chat.id);
+    let Some(class) = class else {
+        bot.send_message(msg.chat.id, "У тебе немає класу з таким кодом. Твої класи -- /classes")
+            .await?;
+        return Ok(());
+    };
+
+    let assignments = db.class_assignments(class.id).await?;
+    if assignments.is_empty() {
+        bot.send_message(
+            msg.chat.id,
+            format!("Клас \"{}\" ще не має завдань. Задати завдання -- /assign", class.name),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    // One message per assignment, so the long classes don't hit the message length limit
+    for assignment in assignments {
+        let mut text = format!("{}\n", describe_assignment(&assignment));
+        for result in db.assignment_results(&assignment).await? {
+            let name = result
+                .name
+                .unwrap_or_else(|| format!("Учень {}", result.chat_id));
+            let score = match (result.score, result.total) {
+                (Some(score), Some(total)) => format!("{}/{}", score, total),
+                _ => "не виконано".to_string(),
+            };
+            text.push_str(&format!("• {}: {}\n", name, score));
+        }
+        bot.send_message(msg.chat.id, text).await?;
+    }
+    Ok(())
+}
+
+pub fn describe_assignment(assignment: &Assignment) -> String {
+    format!(
+        "{}: {}, {} питань, {} рівень, до {}",
+        assignment.class_name,
+        assignment.kind.name(),
+        assignment.size,
+        assignment.difficulty.name(),
+        assignment.deadline
+    )
+}
+
+/// The student's homework with the buttons to start it
+pub async fn send_pending_assignments(bot: &Bot, chat_id: ChatId, db: &Database) -> HandlerResult {
+    let assignments = db.pending_assignments(chat_id).await?;
+    if assignments.is_empty() {
+        bot.send_message(chat_id, "Невиконаних домашніх завдань немає").await?;
+        return Ok(());
+    }
+
+    let keyboard = InlineKeyboardMarkup::new(assignments.iter().map(|assignment| {
+        vec![InlineKeyboardButton::callback(
+            describe_assignment(assignment),
+            format!("{}{}", HOMEWORK_CALLBACK_PREFIX, assignment.id),
+        )]
+    }));
+    bot.send_message(chat_id, "Твої домашні завдання. Результат першої спроби побачить вчитель")
+        .reply_markup(keyboard)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates_are_parsed() {
+        let date = NaiveDate::from_ymd_opt(2026, 10, 25);
+        assert_eq!(parse_date("25.10.2026"), date);
+        assert_eq!(parse_date("2026-10-25"), date);
+        assert_eq!(parse_date("5.3.2027"), NaiveDate::from_ymd_opt(2027, 3, 5));
+    }
+
+    #[test]
+    fn invalid_dates_are_rejected() {
+        for date in ["31.02.2026", "29.02.2027", "25.13.2026", "25.10", "завтра", "2026-10-25-1", ""] {
+            assert_eq!(parse_date(date), None, "{}", date);
+        }
+        assert!(parse_date("29.02.2028").is_some());
+    }
+}
@@ -0,0 +1,533 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use chatgpt::config::ChatGPTEngine;
+use serde::Deserialize;
+use teloxide::types::ChatId;
+
+use crate::quiz::{ai_helper::Personality, kind::MAX_QUIZ_SIZE};
+
+// The file is looked for in the working directory unless `CONFIG` points to another one
+const DEFAULT_CONFIG_PATH: &str = "config.toml";
+
+/// The settings of the bot: read from the TOML file, then overridden with the environment variables
+/// (e.g. `DATABASE_PATH` or `QUIZ_SIZES=5,10,20`), see `config.example.toml`.
+/// Everything has a default, apart from the tokens.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub telegram: TelegramConfig,
+    pub paths: PathsConfig,
+    pub ai: AiConfig,
+    pub quiz: QuizConfig,
+    pub admin: AdminConfig,
+    pub classes: ClassesConfig,
+    pub webhook: WebhookConfig,
+    pub metrics: MetricsConfig,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TelegramConfig {
+    pub token: String,
+}
+
+/// Where the database, the dictionaries and the cache are
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PathsConfig {
+    pub database: String,
+    pub stress: String,
+    pub treebank: String,
+    pub declensions: String,
+    // Optional, the dictionary forms' stress is taken from `stress` anyway
+    pub noun_forms_stress: String,
+    pub ai_cache: String,
+}
+
+impl Default for PathsConfig {
+    fn default() -> Self {
+        Self {
+            database: "db.sqlite".to_string(),
+            stress: "stress.txt".to_string(),
+            treebank: "uk_iu-ud-dev.conllu".to_string(),
+            declensions: "words_with_declensions.json".to_string(),
+            noun_forms_stress: "noun_forms_stress.txt".to_string(),
+            ai_cache: "ai_cache.json".to_string(),
+        }
+    }
+}
+
+impl PathsConfig {
+    /// The dictionary files, a change of any of them reloads all of them
+    pub fn dictionaries(&self) -> [&str; 4] {
+        [&self.stress, &self.treebank, &self.declensions, &self.noun_forms_stress]
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AiConfig {
+    pub api_key: String,
+    pub engine: String,
+    pub timeout_secs: u64,
+    // The stress explanations come from the offline rules catalogue,
+    // the AI is only used to retell them if it's explicitly enabled
+    pub stress_explanations: bool,
+}
+
+impl Default for AiConfig {
+    fn default() -> Self {
+        Self {
+            api_key: String::new(),
+            engine: "gpt-3.5-turbo".to_string(),
+            timeout_secs: 15,
+            stress_explanations: false,
+        }
+    }
+}
+
+impl AiConfig {
+    pub fn engine(&self) -> ChatGPTEngine {
+        match self.engine.as_str() {
+            "gpt-3.5-turbo" => ChatGPTEngine::Gpt35Turbo,
+            "gpt-3.5-turbo-0301" => ChatGPTEngine::Gpt35Turbo_0301,
+            "gpt-4" => ChatGPTEngine::Gpt4,
+            "gpt-4-32k" => ChatGPTEngine::Gpt4_32k,
+            "gpt-4-0314" => ChatGPTEngine::Gpt4_0314,
+            "gpt-4-32k-0314" => ChatGPTEngine::Gpt4_32k_0314,
+            // The engine is created once at startup, so the leak is only the name
+            other => ChatGPTEngine::Custom(Box::leak(other.to_string().into_boxed_str())),
+        }
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_secs)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuizConfig {
+    // The amounts of questions offered on the keyboard
+    pub sizes: Vec<usize>,
+    // The personality of the users who haven't chosen one, see `Personality::key`
+    pub default_personality: String,
+}
+
+impl Default for QuizConfig {
+    fn default() -> Self {
+        Self {
+            sizes: vec![5, 10, 15],
+            default_personality: Personality::default().key().to_string(),
+        }
+    }
+}
+
+impl QuizConfig {
+    pub fn default_personality(&self) -> Personality {
+        // It's checked at startup
+        Personality::from_key(&self.default_personality).unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminConfig {
+    // In the private chats it's the user id
+    pub chat_ids: Vec<i64>,
+}
+
+impl AdminConfig {
+    pub fn chat_ids(&self) -> Vec<ChatId> {
+        self.chat_ids.iter().copied().map(ChatId).collect()
+    }
+}
+
+/// Who may create the classes with `/newclass`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClassesConfig {
+    // Nobody may create the classes unless listed
+    pub teacher_chat_ids: Vec<i64>,
+}
+
+impl ClassesConfig {
+    pub fn teacher_chat_ids(&self) -> Vec<ChatId> {
+        self.teacher_chat_ids.iter().copied().map(ChatId).collect()
+    }
+}
+
+/// Without the public URL the bot polls Telegram for the updates, with it Telegram sends them to the bot
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhookConfig {
+    // Where the reverse proxy sends the updates to, the `/healthz` is there too
+    pub listen_address: SocketAddr,
+    // The address Telegram sends the updates to, e.g. https://example.com/bot
+    pub url: String,
+    // Telegram sends it with every update, so the updates can't be forged; generated if it's empty
+    pub secret_token: String,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            listen_address: SocketAddr::from(([127, 0, 0, 1], 8080)),
+            url: String::new(),
+            secret_token: String::new(),
+        }
+    }
+}
+
+impl WebhookConfig {
+    // It's checked at startup
+    pub fn url(&self) -> Option<url::Url> {
+        url::Url::parse(&self.url).ok()
+    }
+}
+
+/// The Prometheus metrics are served at `/metrics` if the address is set
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    pub listen_address: Option<SocketAddr>,
+}
+
+/// Everything wrong with the settings, so it can all be fixed at once
+#[derive(Debug)]
+pub struct ConfigError(Vec<String>);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Invalid configuration:")?;
+        for error in &self.0 {
+            writeln!(f, "  - {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// Reads the file (if there is one), applies the environment overrides and checks the result
+    pub fn load() -> Result<Self, ConfigError> {
+        let mut errors = Vec::new();
+
+        let mut config = match std::env::var("CONFIG") {
+            Ok(path) => Self::read(&path, true, &mut errors),
+            Err(_) => Self::read(DEFAULT_CONFIG_PATH, false, &mut errors),
+        };
+        config.apply_env(&|name| std::env::var(name).ok(), &mut errors);
+        config.validate(&mut errors);
+
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(ConfigError(errors))
+        }
+    }
+
+    // The default file may be missing, the one given explicitly may not
+    fn read(path: &str, required: bool, errors: &mut Vec<String>) -> Self {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !required => return Self::default(),
+            Err(e) => {
+                errors.push(format!("failed to read the config file '{}': {}", path, e));
+                return Self::default();
+            }
+        };
+        toml::from_str(&text).unwrap_or_else(|e| {
+            errors.push(format!("failed to parse the config file '{}': {}", path, e));
+            Self::default()
+        })
+    }
+
+    fn apply_env(&mut self, env: Env, errors: &mut Vec<String>) {
+        override_string(env, "TELOXIDE_TOKEN", &mut self.telegram.token);
+
+        override_string(env, "DATABASE_PATH", &mut self.paths.database);
+        override_string(env, "STRESS_PATH", &mut self.paths.stress);
+        override_string(env, "TREEBANK_PATH", &mut self.paths.treebank);
+        override_string(env, "DECLENSIONS_PATH", &mut self.paths.declensions);
+        override_string(env, "NOUN_FORMS_STRESS_PATH", &mut self.paths.noun_forms_stress);
+        override_string(env, "AI_CACHE_PATH", &mut self.paths.ai_cache);
+
+        override_string(env, "CHATGPT_API_KEY", &mut self.ai.api_key);
+        override_string(env, "GPT_ENGINE", &mut self.ai.engine);
+        override_parsed(env, "GPT_TIMEOUT_SECS", &mut self.ai.timeout_secs, errors);
+        if let Some(value) = env("AI_STRESS_EXPLANATIONS") {
+            self.ai.stress_explanations = value == "1" || value == "true";
+        }
+
+        override_list(env, "QUIZ_SIZES", &mut self.quiz.sizes, errors);
+        override_string(env, "DEFAULT_PERSONALITY", &mut self.quiz.default_personality);
+
+        override_list(env, "ADMIN_CHAT_IDS", &mut self.admin.chat_ids, errors);
+        override_list(env, "TEACHER_CHAT_IDS", &mut self.classes.teacher_chat_ids, errors);
+
+        override_parsed(env, "WEBHOOK_LISTEN_ADDRESS", &mut self.webhook.listen_address, errors);
+        override_string(env, "WEBHOOK_URL", &mut self.webhook.url);
+        override_string(env, "WEBHOOK_SECRET_TOKEN", &mut self.webhook.secret_token);
+
+        // The empty value turns the metrics off
+        match env("METRICS_LISTEN_ADDRESS") {
+            Some(value) if value.is_empty() => self.metrics.listen_address = None,
+            Some(value) => match value.trim().parse() {
+                Ok(address) => self.metrics.listen_address = Some(address),
+                Err(_) => errors.push(format!("invalid value of METRICS_LISTEN_ADDRESS: '{}'", value)),
+            },
+            None => {}
+        }
+    }
+
+    fn validate(&self, errors: &mut Vec<String>) {
+        if self.telegram.token.is_empty() {
+            errors.push("the bot token is not set (telegram.token or TELOXIDE_TOKEN)".to_string());
+        }
+        if self.ai.api_key.is_empty() {
+            errors.push("the ChatGPT API key is not set (ai.api_key or CHATGPT_API_KEY)".to_string());
+        }
+        if self.ai.engine.is_empty() {
+            errors.push("the GPT engine is empty (ai.engine or GPT_ENGINE)".to_string());
+        }
+        if self.ai.timeout_secs == 0 {
+            errors.push("the GPT timeout must be at least a second (ai.timeout_secs or GPT_TIMEOUT_SECS)".to_string());
+        }
+
+        let required = [
+            ("paths.stress", &self.paths.stress),
+            ("paths.treebank", &self.paths.treebank),
+            ("paths.declensions", &self.paths.declensions),
+        ];
+        for (key, path) in required {
+            if !Path::new(path).is_file() {
+                errors.push(format!("the file '{}' ({}) doesn't exist", path, key));
+            }
+        }
+
+        if self.quiz.sizes.is_empty() {
+            errors.push("at least one quiz size is needed (quiz.sizes or QUIZ_SIZES)".to_string());
+        }
+        for size in &self.quiz.sizes {
+            if !(1..=MAX_QUIZ_SIZE).contains(size) {
+                errors.push(format!("the quiz size {} is not between 1 and {}", size, MAX_QUIZ_SIZE));
+            }
+        }
+        if Personality::from_key(&self.quiz.default_personality).is_none() {
+            let keys: Vec<&str> = Personality::ALL.iter().map(|p| p.key()).collect();
+            errors.push(format!(
+                "unknown personality '{}', expected one of: {}",
+                self.quiz.default_personality,
+                keys.join(", ")
+            ));
+        }
+
+        if !self.webhook.url.is_empty() {
+            match url::Url::parse(&self.webhook.url) {
+                Ok(url) if url.scheme() == "https" => {}
+                Ok(_) => errors.push(format!("the webhook URL '{}' must be https", self.webhook.url)),
+                Err(e) => errors.push(format!("invalid webhook URL '{}': {}", self.webhook.url, e)),
+            }
+        }
+        // See `secret_token` of https://core.telegram.org/bots/api#setwebhook
+        let secret_token = &self.webhook.secret_token;
+        if secret_token.len() > 256
+            || !secret_token.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            errors.push(
+                "the webhook secret token must be up to 256 characters: A-Z, a-z, 0-9, _ and -".to_string(),
+            );
+        }
+    }
+}
+
+// The environment variable by the name, `std::env::var` apart from the tests
+type Env<'a> = &'a dyn Fn(&str) -> Option<String>;
+
+fn override_string(env: Env, name: &str, value: &mut String) {
+    if let Some(env_value) = env(name) {
+        *value = env_value;
+    }
+}
+
+fn override_parsed<T: FromStr>(env: Env, name: &str, value: &mut T, errors: &mut Vec<String>) {
+    if let Some(env_value) = env(name) {
+        match env_value.trim().parse() {
+            Ok(parsed) => *value = parsed,
+            Err(_) => errors.push(format!("invalid value of {}: '{}'", name, env_value)),
+        }
+    }
+}
+
+// The lists are comma-separated, e.g. `QUIZ_SIZES=5,10,15`
+fn override_list<T: FromStr>(env: Env, name: &str, values: &mut Vec<T>, errors: &mut Vec<String>) {
+    if let Some(env_value) = env(name) {
+        let parsed: Result<Vec<T>, _> = env_value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(str::parse)
+            .collect();
+        match parsed {
+            Ok(parsed) => *values = parsed,
+            Err(_) => errors.push(format!("invalid value of {}: '{}'", name, env_value)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    // Only the existence of the dictionaries is checked, so the treebank of the repo stands in for all of them
+    fn valid() -> Config {
+        let mut config = Config::default();
+        config.telegram.token = "123:token".to_string();
+        config.ai.api_key = "key".to_string();
+        config.paths.stress = "uk_iu-ud-dev.conllu".to_string();
+        config.paths.declensions = "uk_iu-ud-dev.conllu".to_string();
+        config
+    }
+
+    fn errors(config: &Config) -> Vec<String> {
+        let mut errors = Vec::new();
+        config.validate(&mut errors);
+        errors
+    }
+
+    fn with_env(config: &mut Config, vars: &[(&str, &str)]) -> Vec<String> {
+        let vars: HashMap<String, String> = vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        let mut errors = Vec::new();
+        config.apply_env(&|name| vars.get(name).cloned(), &mut errors);
+        errors
+    }
+
+    #[test]
+    fn valid_config_has_no_errors() {
+        assert!(errors(&valid()).is_empty());
+    }
+
+    #[test]
+    fn tokens_and_files_are_required() {
+        let mut config = Config::default();
+        config.paths.stress = "missing.txt".to_string();
+        config.paths.treebank = "missing.conllu".to_string();
+        config.paths.declensions = "missing.json".to_string();
+        let errors = errors(&config);
+        assert!(errors.iter().any(|e| e.contains("TELOXIDE_TOKEN")));
+        assert!(errors.iter().any(|e| e.contains("CHATGPT_API_KEY")));
+        assert_eq!(errors.iter().filter(|e| e.contains("doesn't exist")).count(), 3);
+    }
+
+    #[test]
+    fn invalid_values_are_reported() {
+        let mut config = valid();
+        config.ai.timeout_secs = 0;
+        config.quiz.sizes = vec![0, 5, MAX_QUIZ_SIZE + 1];
+        config.quiz.default_personality = "nobody".to_string();
+        assert_eq!(errors(&config).len(), 4);
+
+        let mut config = valid();
+        config.quiz.sizes.clear();
+        assert_eq!(errors(&config).len(), 1);
+    }
+
+    #[test]
+    fn webhook_is_checked() {
+        let mut config = valid();
+        config.webhook.url = "https://example.com/bot".to_string();
+        config.webhook.secret_token = "Secret_token-1".to_string();
+        assert!(errors(&config).is_empty());
+        assert!(config.webhook.url().is_some());
+
+        config.webhook.url = "http://example.com/bot".to_string();
+        assert_eq!(errors(&config).len(), 1);
+        config.webhook.url = "not a url".to_string();
+        assert_eq!(errors(&config).len(), 1);
+
+        let mut config = valid();
+        config.webhook.secret_token = "with spaces".to_string();
+        assert_eq!(errors(&config).len(), 1);
+        config.webhook.secret_token = "a".repeat(257);
+        assert_eq!(errors(&config).len(), 1);
+    }
+
+    #[test]
+    fn env_overrides_the_file() {
+        let mut config = valid();
+        config.metrics.listen_address = Some(SocketAddr::from(([127, 0, 0, 1], 9000)));
+        let errors = with_env(
+            &mut config,
+            &[
+                ("TELOXIDE_TOKEN", "456:other"),
+                ("QUIZ_SIZES", "5, 20,"),
+                ("ADMIN_CHAT_IDS", "1,-100200"),
+                ("TEACHER_CHAT_IDS", "7"),
+                ("GPT_TIMEOUT_SECS", "30"),
+                ("AI_STRESS_EXPLANATIONS", "true"),
+                ("METRICS_LISTEN_ADDRESS", ""),
+            ],
+        );
+        assert!(errors.is_empty());
+        assert_eq!(config.telegram.token, "456:other");
+        assert_eq!(config.quiz.sizes, vec![5, 20]);
+        assert_eq!(config.admin.chat_ids, vec![1, -100200]);
+        assert_eq!(config.classes.teacher_chat_ids(), vec![ChatId(7)]);
+        assert_eq!(config.ai.timeout(), Duration::from_secs(30));
+        assert!(config.ai.stress_explanations);
+        assert_eq!(config.metrics.listen_address, None);
+
+        let errors = with_env(&mut config, &[("METRICS_LISTEN_ADDRESS", "0.0.0.0:9100")]);
+        assert!(errors.is_empty());
+        assert_eq!(config.metrics.listen_address, Some(SocketAddr::from(([0, 0, 0, 0], 9100))));
+    }
+
+    #[test]
+    fn invalid_env_values_are_reported() {
+        let mut config = valid();
+        let errors = with_env(
+            &mut config,
+            &[
+                ("GPT_TIMEOUT_SECS", "soon"),
+                ("QUIZ_SIZES", "5,many"),
+                ("WEBHOOK_LISTEN_ADDRESS", "localhost"),
+                ("METRICS_LISTEN_ADDRESS", ":9100"),
+            ],
+        );
+        assert_eq!(errors.len(), 4);
+        // The invalid values leave the previous ones
+        assert_eq!(config.ai.timeout_secs, 15);
+        assert_eq!(config.quiz.sizes, vec![5, 10, 15]);
+    }
+
+    #[test]
+    fn file_is_parsed() {
+        let config: Config = toml::from_str(
+            r#"
+            [telegram]
+            token = "123:token"
+
+            [quiz]
+            sizes = [10, 25]
+
+            [metrics]
+            listen_address = "127.0.0.1:9100"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.telegram.token, "123:token");
+        assert_eq!(config.quiz.sizes, vec![10, 25]);
+        assert_eq!(config.paths, PathsConfig::default());
+        assert!(config.metrics.listen_address.is_some());
+
+        assert!(toml::from_str::<Config>("[quiz]\nsize = [10]\n").is_err());
+    }
+}
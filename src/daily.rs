@@ -0,0 +1,166 @@
+use std::sync::Arc;
+
+use teloxide::{prelude::*, types::KeyboardRemove};
+
+use crate::db::Database;
+use crate::quiz::{self, ai_helper::QuizHelper, kind::Datasets};
+use crate::{schedule_question_timeout, send_first_question, HandlerResult, QuizDialogue};
+
+const DAILY_QUIZ_SIZE: usize = 10;
+
+/// The days in a row the challenge is done, now and at most ever
+struct Streak {
+    current: usize,
+    best: usize,
+}
+
+// `days` are the days from today in order, see `Database::daily_days`.
+// The streak isn't broken until the end of today, so it may end yesterday too.
+fn streak(days: &[i64]) -> Streak {
+    let mut best = 0;
+    let mut run = 0;
+    let mut previous = None;
+    for day in days {
+        run = if previous == Some(day - 1) { run + 1 } else { 1 };
+        best = best.max(run);
+        previous = Some(*day);
+    }
+
+    let current = match previous {
+        Some(0) | Some(-1) => run,
+        _ => 0,
+    };
+    Streak { current, best }
+}
+
+fn days_word(days: usize) -> &'static str {
+    match (days % 10, days % 100) {
+        (1, n) if n != 11 => "день",
+        (2..=4, n) if !(12..=14).contains(&n) => "дні",
+        _ => "днів",
+    }
+}
+
+fn streak_text(streak: &Streak) -> String {
+    format!(
+        "🔥 Серія: {} {} поспіль (рекорд: {} {})",
+        streak.current,
+        days_word(streak.current),
+        streak.best,
+        days_word(streak.best)
+    )
+}
+
+/// `/daily` -- the mixed quiz of the day, the same for everyone
+pub async fn start_daily(
+    bot: &Bot,
+    dialogue: &QuizDialogue,
+    ai_helper: &Arc<QuizHelper>,
+    datasets: &Datasets,
+    db: &Arc<Database>,
+) -> HandlerResult {
+    let chat_id = dialogue.chat_id();
+    let day = db.today().await?;
+
+    if let Some((score, total)) = db.daily_result(chat_id, &day).await? {
+        let streak = streak(&db.daily_days(chat_id).await?);
+        bot.send_message(
+            chat_id,
+            format!(
+                "Виклик дня вже пройдено: {} з {}\n{}\nНовий виклик -- завтра!",
+                score,
+                total,
+                streak_text(&streak)
+            ),
+        )
+        .await?;
+        return Ok(());
+    }
+    if dialogue.get_or_default().await?.is_quiz() {
+        bot.send_message(chat_id, "Спершу закінчи тест або скасуй його (/cancel)")
+            .await?;
+        return Ok(());
+    }
+    let Some(questions) = datasets.generate_daily_quiz(&day, DAILY_QUIZ_SIZE) else {
+        bot.send_message(chat_id, "На жаль, виклик дня зараз недоступний").await?;
+        return Ok(());
+    };
+
+    bot.send_message(
+        chat_id,
+        format!(
+            "Виклик дня! {} питань, однакових для всіх. Зараховується перша спроба.\n\
+            Проходь виклик щодня, щоб не переривати серію 🔥",
+            questions.len()
+        ),
+    )
+    .reply_markup(KeyboardRemove::new())
+    .await?;
+
+    let profile = db.get_profile(chat_id).await?;
+    let quiz = quiz::Quiz::new(questions).for_daily(day);
+    let state = send_first_question(bot, chat_id, ai_helper, &datasets.declension, &profile, quiz).await?;
+    schedule_question_timeout(bot, dialogue, ai_helper, &datasets.declension, db, &state);
+    dialogue.update(state).await?;
+    Ok(())
+}
+
+/// The days in a row the challenge is done up to today (or yesterday, if it isn't done today yet)
+pub async fn current_streak(db: &Database, chat_id: ChatId) -> Result<usize, sqlx::Error> {
+    Ok(streak(&db.daily_days(chat_id).await?).current)
+}
+
+/// Saves the result of the day's challenge and tells how the streak goes
+pub async fn finish_daily(
+    db: &Database,
+    chat_id: ChatId,
+    day: &str,
+    score: usize,
+    total: usize,
+) -> Result<String, sqlx::Error> {
+    db.save_daily_result(chat_id, day, score, total).await?;
+    let streak = streak(&db.daily_days(chat_id).await?);
+    Ok(streak_text(&streak))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn current_and_best(days: &[i64]) -> (usize, usize) {
+        let streak = streak(days);
+        (streak.current, streak.best)
+    }
+
+    #[test]
+    fn no_days_no_streak() {
+        assert_eq!(current_and_best(&[]), (0, 0));
+    }
+
+    #[test]
+    fn the_days_in_a_row_up_to_today() {
+        assert_eq!(current_and_best(&[-2, -1, 0]), (3, 3));
+    }
+
+    #[test]
+    fn the_streak_goes_on_until_today_is_over() {
+        // Not done today yet, but done yesterday
+        assert_eq!(current_and_best(&[-3, -2, -1]), (3, 3));
+    }
+
+    #[test]
+    fn a_missed_day_breaks_the_streak() {
+        assert_eq!(current_and_best(&[-6, -5, -4, -3, -1, 0]), (2, 4));
+        // The last time is the day before yesterday
+        assert_eq!(current_and_best(&[-4, -3, -2]), (0, 3));
+    }
+
+    #[test]
+    fn days_are_pluralized() {
+        let words = [0, 1, 2, 5, 11, 12, 21, 22, 25, 111, 112, 101].map(days_word);
+        assert_eq!(
+            words,
+            ["днів", "день", "дні", "днів", "днів", "днів", "день", "дні", "днів", "днів", "днів", "день"]
+        );
+    }
+}
@@ -0,0 +1,1059 @@
+use std::collections::HashMap;
+
+use sqlx::{
+    sqlite::{Sqlite, SqlitePool},
+    types::Json,
+};
+use teloxide::types::ChatId;
+
+use crate::presentation::Presentation;
+use crate::quiz::{
+    self,
+    kind::{Difficulty, QuizCode, QuizKind, QuizMix},
+    mastery::{Mastery, SkillRating},
+};
+use crate::quiz::ai_helper::Personality;
+
+// Every migration is applied once, in order; the number of applied ones is kept in `PRAGMA user_version`.
+// Never edit the applied migrations, add a new one instead.
+const MIGRATIONS: &[&str] = &[
+    "
+    CREATE TABLE user_settings (
+        chat_id     INTEGER PRIMARY KEY NOT NULL,
+        personality TEXT    NOT NULL
+    );
+    ",
+    "
+    ALTER TABLE user_settings ADD COLUMN presentation TEXT NOT NULL DEFAULT 'buttons';
+    ",
+    "
+    CREATE TABLE profiles (
+        chat_id      INTEGER PRIMARY KEY NOT NULL,
+        name         TEXT,
+        grade        INTEGER,
+        quiz_size    INTEGER,
+        personality  TEXT    NOT NULL DEFAULT 'shevchenko',
+        presentation TEXT    NOT NULL DEFAULT 'buttons',
+        language     TEXT    NOT NULL DEFAULT 'uk',
+        created_at   INTEGER NOT NULL DEFAULT (CAST(strftime('%s', 'now') AS INTEGER))
+    );
+    INSERT INTO profiles (chat_id, personality, presentation)
+        SELECT chat_id, personality, presentation FROM user_settings;
+    DROP TABLE user_settings;
+    ",
+    "
+    CREATE TABLE classes (
+        id              INTEGER PRIMARY KEY AUTOINCREMENT,
+        teacher_chat_id INTEGER NOT NULL,
+        name            TEXT    NOT NULL,
+        join_code       TEXT    NOT NULL UNIQUE,
+        created_at      INTEGER NOT NULL DEFAULT (CAST(strftime('%s', 'now') AS INTEGER))
+    );
+    CREATE TABLE class_members (
+        class_id  INTEGER NOT NULL REFERENCES classes (id),
+        chat_id   INTEGER NOT NULL,
+        joined_at INTEGER NOT NULL DEFAULT (CAST(strftime('%s', 'now') AS INTEGER)),
+        PRIMARY KEY (class_id, chat_id)
+    );
+    CREATE TABLE assignments (
+        id         INTEGER PRIMARY KEY AUTOINCREMENT,
+        class_id   INTEGER NOT NULL REFERENCES classes (id),
+        quiz_kind  TEXT    NOT NULL,
+        size       INTEGER NOT NULL,
+        difficulty TEXT    NOT NULL,
+        -- The last day (YYYY-MM-DD, UTC) the homework can be done
+        deadline   TEXT    NOT NULL,
+        created_at INTEGER NOT NULL DEFAULT (CAST(strftime('%s', 'now') AS INTEGER))
+    );
+    CREATE TABLE assignment_results (
+        assignment_id INTEGER NOT NULL REFERENCES assignments (id),
+        chat_id       INTEGER NOT NULL,
+        score         INTEGER NOT NULL,
+        total         INTEGER NOT NULL,
+        finished_at   INTEGER NOT NULL DEFAULT (CAST(strftime('%s', 'now') AS INTEGER)),
+        PRIMARY KEY (assignment_id, chat_id)
+    );
+    ",
+    "
+    -- The whole class gets the same questions, see `QuizCode`
+    ALTER TABLE assignments ADD COLUMN seed INTEGER NOT NULL DEFAULT 0;
+    ",
+    "
+    -- Seconds per question in the timed mode, NULL if the mode is off
+    ALTER TABLE profiles ADD COLUMN time_limit INTEGER;
+    ",
+    "
+    -- The proportions of the mixed quiz, see `QuizMix`; NULL for all the kinds equally
+    ALTER TABLE profiles ADD COLUMN quiz_mix TEXT;
+    ",
+    "
+    -- The questions of the last quiz the learner has got wrong, to redo them
+    CREATE TABLE mistakes (
+        chat_id   INTEGER PRIMARY KEY NOT NULL,
+        -- The quiz id is u64, it's stored with the same bits
+        quiz_id   INTEGER NOT NULL,
+        -- The JSON array of `quiz::Question`
+        questions TEXT    NOT NULL
+    );
+    ",
+    "
+    -- The learner's mastery of each skill, see `quiz::mastery`
+    CREATE TABLE skill_ratings (
+        chat_id INTEGER NOT NULL,
+        skill   TEXT    NOT NULL,
+        rating  REAL    NOT NULL,
+        answers INTEGER NOT NULL,
+        PRIMARY KEY (chat_id, skill)
+    );
+    -- Whether the questions are picked for the learner's mastery or at random
+    ALTER TABLE profiles ADD COLUMN adaptive INTEGER NOT NULL DEFAULT 1;
+    ",
+    "
+    -- The first attempt of each day's challenge, the consecutive days make the streak
+    CREATE TABLE daily_results (
+        chat_id     INTEGER NOT NULL,
+        -- YYYY-MM-DD, UTC
+        day         TEXT    NOT NULL,
+        score       INTEGER NOT NULL,
+        total       INTEGER NOT NULL,
+        finished_at INTEGER NOT NULL DEFAULT (CAST(strftime('%s', 'now') AS INTEGER)),
+        PRIMARY KEY (chat_id, day)
+    );
+    ",
+    "
+    -- The time of the practice reminder in minutes from the local midnight, `NULL` if it's off
+    ALTER TABLE profiles ADD COLUMN remind_at INTEGER;
+    -- The IANA name of the learner's time zone, `NULL` for the default one
+    ALTER TABLE profiles ADD COLUMN time_zone TEXT;
+    -- The local date (YYYY-MM-DD) the reminder was sent on last, so it's sent once a day
+    ALTER TABLE profiles ADD COLUMN reminded_on TEXT;
+    ",
+    "
+    -- The totals of the finished quizzes the achievements are counted from
+    CREATE TABLE quiz_stats (
+        chat_id                INTEGER PRIMARY KEY NOT NULL,
+        quizzes                INTEGER NOT NULL DEFAULT 0,
+        perfect_quizzes        INTEGER NOT NULL DEFAULT 0,
+        correct_stress_answers INTEGER NOT NULL DEFAULT 0
+    );
+    CREATE TABLE achievements (
+        chat_id     INTEGER NOT NULL,
+        -- `achievements::Achievement::key`
+        achievement TEXT    NOT NULL,
+        earned_at   INTEGER NOT NULL DEFAULT (CAST(strftime('%s', 'now') AS INTEGER)),
+        PRIMARY KEY (chat_id, achievement)
+    );
+    ",
+    "
+    -- The questions the users have found wrong, for the admins to review
+    CREATE TABLE reports (
+        id                INTEGER PRIMARY KEY AUTOINCREMENT,
+        chat_id           INTEGER NOT NULL,
+        -- The JSON of `quiz::Question`
+        question          TEXT    NOT NULL,
+        -- `quiz::QuestionSource::key`, `NULL` if the question doesn't tell where it's from
+        source            TEXT,
+        comment           TEXT,
+        -- The message the user is asked to reply to with the comment
+        prompt_message_id INTEGER,
+        -- 'open', 'dismissed' or 'blacklisted'
+        status            TEXT    NOT NULL DEFAULT 'open',
+        created_at        INTEGER NOT NULL DEFAULT (CAST(strftime('%s', 'now') AS INTEGER))
+    );
+    -- The dictionary items no questions are made of, see `quiz::blacklist`
+    CREATE TABLE blacklist (
+        source     TEXT    PRIMARY KEY NOT NULL,
+        report_id  INTEGER REFERENCES reports (id),
+        created_at INTEGER NOT NULL DEFAULT (CAST(strftime('%s', 'now') AS INTEGER))
+    );
+    ",
+    "
+    -- Who has used the bot on which day, for the usage stats
+    CREATE TABLE activity (
+        -- YYYY-MM-DD, UTC
+        day     TEXT    NOT NULL,
+        user_id INTEGER NOT NULL,
+        PRIMARY KEY (day, user_id)
+    );
+    -- Every finished quiz, for the usage stats
+    CREATE TABLE quiz_log (
+        chat_id     INTEGER NOT NULL,
+        -- `QuizKind::key` or 'mixed' for the quizzes of more than one kind
+        kind        TEXT    NOT NULL,
+        score       INTEGER NOT NULL,
+        total       INTEGER NOT NULL,
+        finished_at INTEGER NOT NULL DEFAULT (CAST(strftime('%s', 'now') AS INTEGER))
+    );
+    CREATE INDEX quiz_log_finished_at ON quiz_log (finished_at);
+    ",
+];
+
+/// What we know about the learner, including the bot settings they have chosen
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    // As the learner has introduced themselves
+    pub name: Option<String>,
+    // The school grade (1-11)
+    pub grade: Option<u8>,
+    // The amount of questions offered first
+    pub quiz_size: Option<usize>,
+    pub personality: Personality,
+    pub presentation: Presentation,
+    // Seconds to answer each question in, `None` if the quizzes aren't timed
+    pub time_limit: Option<u64>,
+    // How many questions of each kind the mixed quiz has
+    pub quiz_mix: QuizMix,
+    // Whether the questions are picked for the learner's mastery, otherwise they are random
+    // and the quiz can be shared with its code
+    pub adaptive: bool,
+    // The time of the practice reminder in minutes from the local midnight, `None` if it's off
+    pub remind_at: Option<u32>,
+    // The IANA name, e.g. Europe/Kyiv, `None` for the default one (see `reminders`)
+    pub time_zone: Option<String>,
+    // The interface is only in Ukrainian so far, the language is kept for the translations to come
+    pub language: String,
+    // The date (YYYY-MM-DD, UTC) the profile was created, `None` for the learners we don't know yet
+    pub created_on: Option<String>,
+}
+
+/// The totals of the learner's finished quizzes
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QuizStats {
+    pub quizzes: i64,
+    pub perfect_quizzes: i64,
+    pub correct_stress_answers: i64,
+}
+
+/// The question the user has reported as wrong
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub id: i64,
+    pub chat_id: ChatId,
+    pub question: quiz::Question,
+    pub comment: Option<String>,
+    // YYYY-MM-DD, UTC
+    pub created_on: String,
+}
+
+/// The reminder the scheduler checks every minute
+#[derive(Debug, Clone)]
+pub struct Reminder {
+    pub chat_id: ChatId,
+    pub remind_at: u32,
+    pub time_zone: Option<String>,
+    pub reminded_on: Option<String>,
+}
+
+/// A class of the teacher, the students join it with the code
+#[derive(Debug, Clone)]
+pub struct Class {
+    pub id: i64,
+    pub teacher_chat_id: ChatId,
+    pub name: String,
+    pub join_code: String,
+    pub members_count: i64,
+}
+
+/// The quiz the teacher has assigned to the class
+#[derive(Debug, Clone)]
+pub struct Assignment {
+    pub id: i64,
+    pub class_id: i64,
+    pub class_name: String,
+    pub kind: QuizKind,
+    pub size: usize,
+    pub difficulty: Difficulty,
+    // YYYY-MM-DD, the homework can be done until the end of the day (UTC)
+    pub deadline: String,
+    pub seed: u32,
+}
+impl Assignment {
+    pub fn quiz_code(&self) -> QuizCode {
+        QuizCode {
+            kind: self.kind,
+            size: self.size,
+            difficulty: Some(self.difficulty),
+            seed: self.seed,
+        }
+    }
+}
+
+/// How the student has done the homework, `score` and `total` are `None` if it isn't done yet
+#[derive(Debug, Clone)]
+pub struct StudentResult {
+    pub chat_id: ChatId,
+    pub name: Option<String>,
+    pub score: Option<i64>,
+    pub total: Option<i64>,
+}
+
+type ProfileRow = (
+    Option<String>,
+    Option<i64>,
+    Option<i64>,
+    String,
+    String,
+    Option<i64>,
+    Option<String>,
+    bool,
+    Option<i64>,
+    Option<String>,
+    String,
+    String,
+);
+type ReportRow = (i64, i64, Json<quiz::Question>, Option<String>, String);
+type StudentResultRow = (i64, Option<String>, Option<i64>, Option<i64>);
+type ClassRow = (i64, i64, String, String, i64);
+type AssignmentRow = (i64, i64, String, String, i64, String, String, i64);
+
+fn class_from_row((id, teacher_chat_id, name, join_code, members_count): ClassRow) -> Class {
+    Class {
+        id,
+        teacher_chat_id: ChatId(teacher_chat_id),
+        name,
+        join_code,
+        members_count,
+    }
+}
+
+// The assignments with an unknown kind or difficulty (e.g. from a newer version of the bot) are skipped
+fn assignment_from_row(
+    (id, class_id, class_name, kind, size, difficulty, deadline, seed): AssignmentRow,
+) -> Option<Assignment> {
+    Some(Assignment {
+        id,
+        class_id,
+        class_name,
+        kind: QuizKind::from_key(&kind)?,
+        size: usize::try_from(size).ok()?,
+        difficulty: Difficulty::from_key(&difficulty)?,
+        deadline,
+        seed: u32::try_from(seed).ok()?,
+    })
+}
+
+const CLASS_COLUMNS: &str = "classes.id, classes.teacher_chat_id, classes.name, classes.join_code,
+    (SELECT COUNT(*) FROM class_members WHERE class_members.class_id = classes.id)";
+const ASSIGNMENT_COLUMNS: &str = "assignments.id, assignments.class_id, classes.name, assignments.quiz_kind,
+    assignments.size, assignments.difficulty, assignments.deadline, assignments.seed";
+
+/// Everything we keep about the users apart from the dialogue state.
+/// It lives in the same SQLite file as the dialogues storage.
+pub struct Database {
+    pool: SqlitePool,
+    // The personality of the users who haven't chosen one
+    default_personality: Personality,
+}
+
+impl Database {
+    pub async fn open(path: &str, default_personality: Personality) -> Result<Self, sqlx::Error> {
+        let pool = SqlitePool::connect(format!("sqlite:{path}?mode=rwc").as_str()).await?;
+        let database = Self {
+            pool,
+            default_personality,
+        };
+        database.migrate().await?;
+        Ok(database)
+    }
+
+    /// Checks the database answers, for the health check
+    pub async fn ping(&self) -> Result<(), sqlx::Error> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn migrate(&self) -> Result<(), sqlx::Error> {
+        let applied: i64 = sqlx::query_scalar("PRAGMA user_version")
+            .fetch_one(&self.pool)
+            .await?;
+
+        for (version, migration) in MIGRATIONS.iter().enumerate().skip(applied as usize) {
+            let mut transaction = self.pool.begin().await?;
+            sqlx::query(migration).execute(&mut transaction).await?;
+            // PRAGMA doesn't support binding the parameters
+            sqlx::query(&format!("PRAGMA user_version = {}", version + 1))
+                .execute(&mut transaction)
+                .await?;
+            transaction.commit().await?;
+        }
+        Ok(())
+    }
+
+    pub async fn get_profile(&self, chat_id: ChatId) -> Result<Profile, sqlx::Error> {
+        let profile: Option<ProfileRow> = sqlx::query_as(
+            "SELECT name, grade, quiz_size, personality, presentation, time_limit, quiz_mix, adaptive, remind_at,
+                time_zone, language, date(created_at, 'unixepoch')
+            FROM profiles WHERE chat_id = ?",
+        )
+        .bind(chat_id.0)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(profile
+            .map(
+                |(
+                    name,
+                    grade,
+                    quiz_size,
+                    personality,
+                    presentation,
+                    time_limit,
+                    quiz_mix,
+                    adaptive,
+                    remind_at,
+                    time_zone,
+                    language,
+                    created_on,
+                )| Profile {
+                    name,
+                    grade: grade.and_then(|g| u8::try_from(g).ok()),
+                    quiz_size: quiz_size.and_then(|s| usize::try_from(s).ok()),
+                    personality: Personality::from_key(&personality).unwrap_or_default(),
+                    presentation: Presentation::from_key(&presentation).unwrap_or_default(),
+                    time_limit: time_limit.and_then(|t| u64::try_from(t).ok()),
+                    quiz_mix: quiz_mix.as_deref().and_then(QuizMix::parse).unwrap_or_default(),
+                    adaptive,
+                    remind_at: remind_at.and_then(|r| u32::try_from(r).ok()),
+                    time_zone,
+                    language,
+                    created_on: Some(created_on),
+                },
+            )
+            .unwrap_or_else(|| Profile {
+                personality: self.default_personality,
+                adaptive: true,
+                language: "uk".to_string(),
+                ..Default::default()
+            }))
+    }
+
+    pub async fn set_name(&self, chat_id: ChatId, name: &str) -> Result<(), sqlx::Error> {
+        self.set_profile_field(chat_id, "name", name.to_string()).await
+    }
+
+    pub async fn set_grade(&self, chat_id: ChatId, grade: Option<u8>) -> Result<(), sqlx::Error> {
+        self.set_profile_field(chat_id, "grade", grade).await
+    }
+
+    pub async fn set_quiz_size(&self, chat_id: ChatId, quiz_size: Option<usize>) -> Result<(), sqlx::Error> {
+        self.set_profile_field(chat_id, "quiz_size", quiz_size.map(|s| s as i64)).await
+    }
+
+    pub async fn set_personality(
+        &self,
+        chat_id: ChatId,
+        personality: Personality,
+    ) -> Result<(), sqlx::Error> {
+        self.set_profile_field(chat_id, "personality", personality.key().to_string()).await
+    }
+
+    pub async fn set_presentation(
+        &self,
+        chat_id: ChatId,
+        presentation: Presentation,
+    ) -> Result<(), sqlx::Error> {
+        self.set_profile_field(chat_id, "presentation", presentation.key().to_string()).await
+    }
+
+    pub async fn set_time_limit(&self, chat_id: ChatId, time_limit: Option<u64>) -> Result<(), sqlx::Error> {
+        self.set_profile_field(chat_id, "time_limit", time_limit.map(|t| t as i64)).await
+    }
+
+    pub async fn set_quiz_mix(&self, chat_id: ChatId, quiz_mix: &QuizMix) -> Result<(), sqlx::Error> {
+        self.set_profile_field(chat_id, "quiz_mix", quiz_mix.to_string()).await
+    }
+
+    pub async fn set_adaptive(&self, chat_id: ChatId, adaptive: bool) -> Result<(), sqlx::Error> {
+        self.set_profile_field(chat_id, "adaptive", adaptive).await
+    }
+
+    pub async fn set_remind_at(&self, chat_id: ChatId, remind_at: Option<u32>) -> Result<(), sqlx::Error> {
+        self.set_profile_field(chat_id, "remind_at", remind_at.map(i64::from)).await
+    }
+
+    pub async fn set_time_zone(&self, chat_id: ChatId, time_zone: &str) -> Result<(), sqlx::Error> {
+        self.set_profile_field(chat_id, "time_zone", time_zone.to_string()).await
+    }
+
+    /// All the reminders that are on
+    pub async fn reminders(&self) -> Result<Vec<Reminder>, sqlx::Error> {
+        let rows: Vec<(i64, i64, Option<String>, Option<String>)> = sqlx::query_as(
+            "SELECT chat_id, remind_at, time_zone, reminded_on FROM profiles WHERE remind_at IS NOT NULL",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .filter_map(|(chat_id, remind_at, time_zone, reminded_on)| {
+                Some(Reminder {
+                    chat_id: ChatId(chat_id),
+                    remind_at: u32::try_from(remind_at).ok()?,
+                    time_zone,
+                    reminded_on,
+                })
+            })
+            .collect())
+    }
+
+    pub async fn set_reminded_on(&self, chat_id: ChatId, day: &str) -> Result<(), sqlx::Error> {
+        self.set_profile_field(chat_id, "reminded_on", day.to_string()).await
+    }
+
+    // Creates the profile if there is none yet, the rest of the fields get their defaults
+    async fn set_profile_field<T>(
+        &self,
+        chat_id: ChatId,
+        column: &'static str,
+        value: T,
+    ) -> Result<(), sqlx::Error>
+    where
+        T: 'static + Send + for<'q> sqlx::Encode<'q, Sqlite> + sqlx::Type<Sqlite>,
+    {
+        // The column is one of ours, never the user's input, so it's fine to format it into the query.
+        // The new profiles get the default personality, unless it's the personality being set
+        if column == "personality" {
+            let query = "INSERT INTO profiles (chat_id, personality) VALUES (?, ?)
+                ON CONFLICT (chat_id) DO UPDATE SET personality = excluded.personality";
+            sqlx::query(query).bind(chat_id.0).bind(value).execute(&self.pool).await?;
+            return Ok(());
+        }
+        let query = format!(
+            "INSERT INTO profiles (chat_id, personality, {column}) VALUES (?, ?, ?)
+            ON CONFLICT (chat_id) DO UPDATE SET {column} = excluded.{column}"
+        );
+        sqlx::query(&query)
+            .bind(chat_id.0)
+            .bind(self.default_personality.key())
+            .bind(value)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Today's date (YYYY-MM-DD, UTC) as the database sees it, to compare with the stored dates
+    pub async fn today(&self) -> Result<String, sqlx::Error> {
+        sqlx::query_scalar("SELECT date('now')").fetch_one(&self.pool).await
+    }
+
+    /// Creates the class with the given join code, returns `None` if the code is taken already
+    pub async fn create_class(
+        &self,
+        teacher_chat_id: ChatId,
+        name: &str,
+        join_code: &str,
+    ) -> Result<Option<i64>, sqlx::Error> {
+        let id = sqlx::query_scalar(
+            "INSERT INTO classes (teacher_chat_id, name, join_code) VALUES (?, ?, ?)
+            ON CONFLICT (join_code) DO NOTHING
+            RETURNING id",
+        )
+        .bind(teacher_chat_id.0)
+        .bind(name)
+        .bind(join_code)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(id)
+    }
+
+    pub async fn find_class(&self, join_code: &str) -> Result<Option<Class>, sqlx::Error> {
+        let row: Option<ClassRow> = sqlx::query_as(&format!(
+            "SELECT {CLASS_COLUMNS} FROM classes WHERE join_code = ?"
+        ))
+        .bind(join_code)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.map(class_from_row))
+    }
+
+    pub async fn teacher_classes(&self, teacher_chat_id: ChatId) -> Result<Vec<Class>, sqlx::Error> {
+        let rows: Vec<ClassRow> = sqlx::query_as(&format!(
+            "SELECT {CLASS_COLUMNS} FROM classes WHERE teacher_chat_id = ? ORDER BY classes.id"
+        ))
+        .bind(teacher_chat_id.0)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().map(class_from_row).collect())
+    }
+
+    pub async fn student_classes(&self, chat_id: ChatId) -> Result<Vec<Class>, sqlx::Error> {
+        let rows: Vec<ClassRow> = sqlx::query_as(&format!(
+            "SELECT {CLASS_COLUMNS} FROM classes
+            JOIN class_members ON class_members.class_id = classes.id
+            WHERE class_members.chat_id = ? ORDER BY classes.id"
+        ))
+        .bind(chat_id.0)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().map(class_from_row).collect())
+    }
+
+    /// Returns `false` if the student is in the class already
+    pub async fn join_class(&self, class_id: i64, chat_id: ChatId) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(
+            "INSERT INTO class_members (class_id, chat_id) VALUES (?, ?)
+            ON CONFLICT (class_id, chat_id) DO NOTHING",
+        )
+        .bind(class_id)
+        .bind(chat_id.0)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// The assignment is generated from a random seed, so the whole class gets the same questions
+    pub async fn create_assignment(
+        &self,
+        class_id: i64,
+        kind: QuizKind,
+        size: usize,
+        difficulty: Difficulty,
+        deadline: &str,
+    ) -> Result<i64, sqlx::Error> {
+        let code = QuizCode::random(kind, size, Some(difficulty));
+        sqlx::query_scalar(
+            "INSERT INTO assignments (class_id, quiz_kind, size, difficulty, deadline, seed) VALUES (?, ?, ?, ?, ?, ?)
+            RETURNING id",
+        )
+        .bind(class_id)
+        .bind(kind.key())
+        .bind(size as i64)
+        .bind(difficulty.key())
+        .bind(deadline)
+        .bind(i64::from(code.seed))
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    /// The homework of the student's classes which isn't done yet and whose deadline hasn't passed
+    pub async fn pending_assignments(&self, chat_id: ChatId) -> Result<Vec<Assignment>, sqlx::Error> {
+        let rows: Vec<AssignmentRow> = sqlx::query_as(&format!(
+            "SELECT {ASSIGNMENT_COLUMNS} FROM assignments
+            JOIN classes ON classes.id = assignments.class_id
+            JOIN class_members ON class_members.class_id = assignments.class_id AND class_members.chat_id = ?1
+            WHERE assignments.deadline >= date('now')
+                AND NOT EXISTS (
+                    SELECT 1 FROM assignment_results
+                    WHERE assignment_results.assignment_id = assignments.id AND assignment_results.chat_id = ?1
+                )
+            ORDER BY assignments.deadline, assignments.id"
+        ))
+        .bind(chat_id.0)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().filter_map(assignment_from_row).collect())
+    }
+
+    pub async fn class_assignments(&self, class_id: i64) -> Result<Vec<Assignment>, sqlx::Error> {
+        let rows: Vec<AssignmentRow> = sqlx::query_as(&format!(
+            "SELECT {ASSIGNMENT_COLUMNS} FROM assignments
+            JOIN classes ON classes.id = assignments.class_id
+            WHERE assignments.class_id = ?
+            ORDER BY assignments.deadline, assignments.id"
+        ))
+        .bind(class_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().filter_map(assignment_from_row).collect())
+    }
+
+    /// Only the first attempt counts, the homework can't be redone for a better score
+    pub async fn save_assignment_result(
+        &self,
+        assignment_id: i64,
+        chat_id: ChatId,
+        score: usize,
+        total: usize,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO assignment_results (assignment_id, chat_id, score, total) VALUES (?, ?, ?, ?)
+            ON CONFLICT (assignment_id, chat_id) DO NOTHING",
+        )
+        .bind(assignment_id)
+        .bind(chat_id.0)
+        .bind(score as i64)
+        .bind(total as i64)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// The results of every student of the class for the assignment
+    pub async fn assignment_results(&self, assignment: &Assignment) -> Result<Vec<StudentResult>, sqlx::Error> {
+        let rows: Vec<StudentResultRow> = sqlx::query_as(
+            "SELECT class_members.chat_id, profiles.name, assignment_results.score, assignment_results.total
+            FROM class_members
+            LEFT JOIN profiles ON profiles.chat_id = class_members.chat_id
+            LEFT JOIN assignment_results
+                ON assignment_results.chat_id = class_members.chat_id AND assignment_results.assignment_id = ?
+            WHERE class_members.class_id = ?
+            ORDER BY profiles.name, class_members.chat_id",
+        )
+        .bind(assignment.id)
+        .bind(assignment.class_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|(chat_id, name, score, total)| StudentResult {
+                chat_id: ChatId(chat_id),
+                name,
+                score,
+                total,
+            })
+            .collect())
+    }
+
+    /// Keeps the questions of the quiz the user has got wrong, instead of the ones of the previous quiz
+    pub async fn save_mistakes(
+        &self,
+        chat_id: ChatId,
+        quiz_id: u64,
+        questions: Vec<quiz::Question>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO mistakes (chat_id, quiz_id, questions) VALUES (?, ?, ?)
+            ON CONFLICT (chat_id) DO UPDATE SET quiz_id = excluded.quiz_id, questions = excluded.questions",
+        )
+        .bind(chat_id.0)
+        .bind(quiz_id as i64)
+        .bind(Json(questions))
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// The last quiz with mistakes and how many questions there are to redo, `None` if there is nothing to redo
+    pub async fn pending_mistakes(&self, chat_id: ChatId) -> Result<Option<(u64, usize)>, sqlx::Error> {
+        let row: Option<(i64, i64)> =
+            sqlx::query_as("SELECT quiz_id, json_array_length(questions) FROM mistakes WHERE chat_id = ?")
+                .bind(chat_id.0)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(row.map(|(quiz_id, count)| (quiz_id as u64, usize::try_from(count).unwrap_or_default())))
+    }
+
+    // After a quiz without mistakes there is nothing to redo
+    pub async fn clear_mistakes(&self, chat_id: ChatId) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM mistakes WHERE chat_id = ?")
+            .bind(chat_id.0)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// The questions the user has got wrong in the quiz, `None` if it isn't the last quiz with mistakes
+    pub async fn get_mistakes(&self, chat_id: ChatId, quiz_id: u64) -> Result<Option<Vec<quiz::Question>>, sqlx::Error> {
+        let questions: Option<Json<Vec<quiz::Question>>> =
+            sqlx::query_scalar("SELECT questions FROM mistakes WHERE chat_id = ? AND quiz_id = ?")
+                .bind(chat_id.0)
+                .bind(quiz_id as i64)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(questions.map(|Json(questions)| questions))
+    }
+
+    pub async fn mastery(&self, chat_id: ChatId) -> Result<Mastery, sqlx::Error> {
+        let ratings: Vec<(String, f64, i64)> =
+            sqlx::query_as("SELECT skill, rating, answers FROM skill_ratings WHERE chat_id = ?")
+                .bind(chat_id.0)
+                .fetch_all(&self.pool)
+                .await?;
+        Ok(Mastery::new(
+            ratings
+                .into_iter()
+                .map(|(skill, rating, answers)| {
+                    let answers = u32::try_from(answers).unwrap_or_default();
+                    (skill, SkillRating { rating, answers })
+                })
+                .collect::<HashMap<_, _>>(),
+        ))
+    }
+
+    /// Updates the learner's mastery of the question's skill with the answer
+    pub async fn record_skill_answer(
+        &self,
+        chat_id: ChatId,
+        question: &quiz::Question,
+        correct: bool,
+    ) -> Result<(), sqlx::Error> {
+        let Some(skill) = question.skill.as_ref().map(|skill| skill.key()) else {
+            return Ok(());
+        };
+        let current: Option<(f64, i64)> =
+            sqlx::query_as("SELECT rating, answers FROM skill_ratings WHERE chat_id = ? AND skill = ?")
+                .bind(chat_id.0)
+                .bind(&skill)
+                .fetch_optional(&self.pool)
+                .await?;
+        let current = current
+            .map(|(rating, answers)| SkillRating {
+                rating,
+                answers: u32::try_from(answers).unwrap_or_default(),
+            })
+            .unwrap_or_default();
+
+        let updated = current.updated(question, correct);
+        sqlx::query(
+            "INSERT INTO skill_ratings (chat_id, skill, rating, answers) VALUES (?, ?, ?, ?)
+            ON CONFLICT (chat_id, skill) DO UPDATE SET rating = excluded.rating, answers = excluded.answers",
+        )
+        .bind(chat_id.0)
+        .bind(skill)
+        .bind(updated.rating)
+        .bind(i64::from(updated.answers))
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// The score and the total of the user's challenge of the day, `None` if it isn't done
+    pub async fn daily_result(&self, chat_id: ChatId, day: &str) -> Result<Option<(i64, i64)>, sqlx::Error> {
+        sqlx::query_as("SELECT score, total FROM daily_results WHERE chat_id = ? AND day = ?")
+            .bind(chat_id.0)
+            .bind(day)
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    /// Only the first attempt counts, the later ones are ignored
+    pub async fn save_daily_result(
+        &self,
+        chat_id: ChatId,
+        day: &str,
+        score: usize,
+        total: usize,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO daily_results (chat_id, day, score, total) VALUES (?, ?, ?, ?)
+            ON CONFLICT (chat_id, day) DO NOTHING",
+        )
+        .bind(chat_id.0)
+        .bind(day)
+        .bind(score as i64)
+        .bind(total as i64)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn create_report(&self, chat_id: ChatId, question: &quiz::Question) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar("INSERT INTO reports (chat_id, question, source) VALUES (?, ?, ?) RETURNING id")
+            .bind(chat_id.0)
+            .bind(Json(question))
+            .bind(question.source.as_ref().map(|source| source.key()))
+            .fetch_one(&self.pool)
+            .await
+    }
+
+    pub async fn set_report_prompt(&self, report_id: i64, message_id: i32) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE reports SET prompt_message_id = ? WHERE id = ?")
+            .bind(message_id)
+            .bind(report_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// The report the message asks to comment on, if the message is such a prompt
+    pub async fn report_by_prompt(&self, chat_id: ChatId, message_id: i32) -> Result<Option<i64>, sqlx::Error> {
+        sqlx::query_scalar("SELECT id FROM reports WHERE chat_id = ? AND prompt_message_id = ?")
+            .bind(chat_id.0)
+            .bind(message_id)
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    pub async fn set_report_comment(&self, report_id: i64, comment: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE reports SET comment = ? WHERE id = ?")
+            .bind(comment)
+            .bind(report_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// The oldest reports not reviewed yet and how many of them there are in total
+    pub async fn open_reports(&self, limit: usize) -> Result<(Vec<Report>, i64), sqlx::Error> {
+        let rows: Vec<ReportRow> = sqlx::query_as(
+            "SELECT id, chat_id, question, comment, date(created_at, 'unixepoch') FROM reports
+            WHERE status = 'open' ORDER BY id LIMIT ?",
+        )
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        let total = sqlx::query_scalar("SELECT COUNT(*) FROM reports WHERE status = 'open'")
+            .fetch_one(&self.pool)
+            .await?;
+        let reports = rows
+            .into_iter()
+            .map(|(id, chat_id, Json(question), comment, created_on)| Report {
+                id,
+                chat_id: ChatId(chat_id),
+                question,
+                comment,
+                created_on,
+            })
+            .collect();
+        Ok((reports, total))
+    }
+
+    /// Closes the report as not a mistake, returns `false` if it's reviewed already
+    pub async fn dismiss_report(&self, report_id: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("UPDATE reports SET status = 'dismissed' WHERE id = ? AND status = 'open'")
+            .bind(report_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Blacklists the item the reported question is made of and closes all the open reports of it.
+    /// Returns the blacklisted item, `None` if the report is reviewed already or the question doesn't tell its item.
+    pub async fn blacklist_report(&self, report_id: i64) -> Result<Option<String>, sqlx::Error> {
+        let mut transaction = self.pool.begin().await?;
+        let source: Option<String> =
+            sqlx::query_scalar("SELECT source FROM reports WHERE id = ? AND status = 'open' AND source IS NOT NULL")
+                .bind(report_id)
+                .fetch_optional(&mut transaction)
+                .await?;
+        let Some(source) = source else {
+            return Ok(None);
+        };
+        sqlx::query("INSERT INTO blacklist (source, report_id) VALUES (?, ?) ON CONFLICT (source) DO NOTHING")
+            .bind(&source)
+            .bind(report_id)
+            .execute(&mut transaction)
+            .await?;
+        sqlx::query("UPDATE reports SET status = 'blacklisted' WHERE source = ? AND status = 'open'")
+            .bind(&source)
+            .execute(&mut transaction)
+            .await?;
+        transaction.commit().await?;
+        Ok(Some(source))
+    }
+
+    pub async fn blacklist(&self) -> Result<Vec<String>, sqlx::Error> {
+        sqlx::query_scalar("SELECT source FROM blacklist").fetch_all(&self.pool).await
+    }
+
+    pub async fn record_activity(&self, user_id: u64) -> Result<(), sqlx::Error> {
+        sqlx::query("INSERT INTO activity (day, user_id) VALUES (date('now'), ?) ON CONFLICT DO NOTHING")
+            .bind(user_id as i64)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn log_quiz(&self, chat_id: ChatId, kind: &str, score: usize, total: usize) -> Result<(), sqlx::Error> {
+        sqlx::query("INSERT INTO quiz_log (chat_id, kind, score, total) VALUES (?, ?, ?, ?)")
+            .bind(chat_id.0)
+            .bind(kind)
+            .bind(score as i64)
+            .bind(total as i64)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// How many users have used the bot each of the last `days` days (UTC), the latest first
+    pub async fn active_users(&self, days: u32) -> Result<Vec<(String, i64)>, sqlx::Error> {
+        sqlx::query_as(
+            "SELECT day, COUNT(*) FROM activity WHERE day > date('now', ?)
+            GROUP BY day ORDER BY day DESC",
+        )
+        .bind(format!("-{} days", days))
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// How many different users have written to the bot in the last `days` days, today included
+    pub async fn active_user_count(&self, days: u32) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar("SELECT COUNT(DISTINCT user_id) FROM activity WHERE day > date('now', ?)")
+            .bind(format!("-{} days", days))
+            .fetch_one(&self.pool)
+            .await
+    }
+
+    /// How many quizzes of each kind have been finished in the last `days` days, the most popular first
+    pub async fn quizzes_by_kind(&self, days: u32) -> Result<Vec<(String, i64)>, sqlx::Error> {
+        sqlx::query_as(
+            "SELECT kind, COUNT(*) FROM quiz_log
+            WHERE finished_at > CAST(strftime('%s', 'now', ?) AS INTEGER)
+            GROUP BY kind ORDER BY COUNT(*) DESC",
+        )
+        .bind(format!("-{} days", days))
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// All the private chats the bot knows, to broadcast to
+    pub async fn private_chats(&self) -> Result<Vec<ChatId>, sqlx::Error> {
+        // The dialogues are kept by teloxide in the same file, they have the users who have never changed the profile.
+        // The group chats have the negative ids.
+        let chat_ids: Vec<i64> = sqlx::query_scalar(
+            "SELECT chat_id FROM profiles WHERE chat_id > 0
+            UNION SELECT user_id FROM activity
+            UNION SELECT chat_id FROM teloxide_dialogues WHERE chat_id > 0",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(chat_ids.into_iter().map(ChatId).collect())
+    }
+
+    /// Adds the finished quiz to the totals, returns the new totals
+    pub async fn add_quiz_stats(&self, chat_id: ChatId, quiz: QuizStats) -> Result<QuizStats, sqlx::Error> {
+        let (quizzes, perfect_quizzes, correct_stress_answers) = sqlx::query_as(
+            "INSERT INTO quiz_stats (chat_id, quizzes, perfect_quizzes, correct_stress_answers) VALUES (?, ?, ?, ?)
+            ON CONFLICT (chat_id) DO UPDATE SET
+                quizzes = quizzes + excluded.quizzes,
+                perfect_quizzes = perfect_quizzes + excluded.perfect_quizzes,
+                correct_stress_answers = correct_stress_answers + excluded.correct_stress_answers
+            RETURNING quizzes, perfect_quizzes, correct_stress_answers",
+        )
+        .bind(chat_id.0)
+        .bind(quiz.quizzes)
+        .bind(quiz.perfect_quizzes)
+        .bind(quiz.correct_stress_answers)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(QuizStats {
+            quizzes,
+            perfect_quizzes,
+            correct_stress_answers,
+        })
+    }
+
+    /// Returns `false` if the achievement has been earned already
+    pub async fn award_achievement(&self, chat_id: ChatId, achievement: &str) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(
+            "INSERT INTO achievements (chat_id, achievement) VALUES (?, ?)
+            ON CONFLICT (chat_id, achievement) DO NOTHING",
+        )
+        .bind(chat_id.0)
+        .bind(achievement)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// The keys of the earned achievements with the dates (YYYY-MM-DD, UTC) they are earned on
+    pub async fn achievements(&self, chat_id: ChatId) -> Result<HashMap<String, String>, sqlx::Error> {
+        let rows: Vec<(String, String)> = sqlx::query_as(
+            "SELECT achievement, date(earned_at, 'unixepoch') FROM achievements WHERE chat_id = ?",
+        )
+        .bind(chat_id.0)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().collect())
+    }
+
+    /// The days the user has done the challenge on, as the days from today (0 is today, -1 is yesterday), in order
+    pub async fn daily_days(&self, chat_id: ChatId) -> Result<Vec<i64>, sqlx::Error> {
+        sqlx::query_scalar(
+            "SELECT CAST(julianday(day) - julianday(date('now')) AS INTEGER) FROM daily_results
+            WHERE chat_id = ? ORDER BY day",
+        )
+        .bind(chat_id.0)
+        .fetch_all(&self.pool)
+        .await
+    }
+}
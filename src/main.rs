@@ -1,221 +1,1077 @@
+mod achievements;
+mod admin;
+mod battle;
+mod chat_lock;
+mod classes;
+mod config;
+mod daily;
+mod db;
+mod metrics;
+mod mistakes;
+mod presentation;
+mod reminders;
+mod reports;
 mod quiz;
+mod webhook;
 
-use std::{fs::File, sync::Arc};
+use std::{sync::Arc, time::Duration};
 
-use chatgpt::{client::ChatGPT, config::ChatGPTEngine};
+use chatgpt::client::ChatGPT;
+use config::Config;
+use db::{Database, Profile};
+use metrics::TimedStorage;
 use dotenv::dotenv;
-use log::debug;
-use quiz::{ai_helper::QuizHelper, declension::{self, Declension}, parts::PartsSentences};
+use quiz::{ai_cache::AiCache, ai_helper::{Personality, QuizHelper}, blacklist::Blacklist, declension::Declension, kind::{Datasets, QuizCode, QuizKind, QuizMix, MAX_QUIZ_SIZE}, registry::DataRegistry, stress, stress_rules};
+use classes::HOMEWORK_CALLBACK_PREFIX;
+use mistakes::REDO_CALLBACK_PREFIX;
+use reports::{REPORT_CALLBACK_PREFIX, REVIEW_CALLBACK_PREFIX};
+use presentation::{receive_answer, send_question, AnswerUpdate, Presentation, ANSWER_CALLBACK_PREFIX};
 use teloxide::{
     dispatching::dialogue::{serializer::Json, ErasedStorage, SqliteStorage, Storage},
     prelude::*,
-    types::{ChatAction, KeyboardButton, KeyboardMarkup, ParseMode, True},
+    types::{ChatAction, InlineKeyboardButton, InlineKeyboardMarkup, KeyboardButton, KeyboardMarkup, KeyboardRemove, ParseMode},
+    utils::command::BotCommands,
 };
 
 type QuizDialogue = Dialogue<State, ErasedStorage<State>>;
 type HandlerResult = Result<(), Box<dyn std::error::Error + Send + Sync>>;
 
 #[derive(Clone, Default, serde::Serialize, serde::Deserialize)]
+#[serde(from = "SavedState")]
 pub enum State {
     #[default]
     Start,
     ReceiveFullName,
     RecieveGameChoice,
+    // `None` is the mixed quiz, see `QuizMix`
+    RecieveAmountOfQuestions {
+        kind: Option<QuizKind>,
+    },
+    // Each question is asked and explained the way its kind is, so it's the same for all the quizzes
+    Quiz {
+        quiz: quiz::Quiz,
+        question_number: usize,
+        score: usize,
+    },
+    // The group chat's quiz everyone answers, see `battle`
+    Battle {
+        battle: battle::Battle,
+    },
+}
+
+// The states the dialogues may be saved in, the ones of the older versions too.
+// A state which can't be read would drop all the updates of the chat, `/start` included.
+#[derive(serde::Deserialize)]
+enum SavedState {
+    Start,
+    ReceiveFullName,
+    RecieveGameChoice,
+    RecieveAmountOfQuestions {
+        kind: Option<QuizKind>,
+    },
+    Quiz {
+        quiz: quiz::Quiz,
+        question_number: usize,
+        score: usize,
+    },
+    Battle {
+        battle: battle::Battle,
+    },
+    // Each kind of the quiz had its own states
     StressedWordsQuizRecieveAmountOfQuestions,
+    PartsOfSpeechRecieveAmountOfQuestions,
+    DeclensionsRecieveAmountOfQuestions,
+    NounFormsStressRecieveAmountOfQuestions,
+    MixedQuizRecieveAmountOfQuestions,
     StressedWordsQuiz {
         quiz: quiz::Quiz,
         question_number: usize,
         score: usize,
     },
-    PartsOfSpeechRecieveAmountOfQuestions,
     PartsOfSpeechQuiz {
         quiz: quiz::Quiz,
         question_number: usize,
         score: usize,
     },
-    DeclensionsRecieveAmountOfQuestions,
     DeclensionsQuiz {
         quiz: quiz::Quiz,
         question_number: usize,
         score: usize,
     },
+    MixedQuiz {
+        quiz: quiz::Quiz,
+        question_number: usize,
+        score: usize,
+    },
+}
+
+impl From<SavedState> for State {
+    fn from(state: SavedState) -> Self {
+        // The questions of the older quizzes of one kind may not be tagged with it
+        let of_kind = |mut quiz: quiz::Quiz, kind: QuizKind| {
+            for question in &mut quiz.questions {
+                question.kind.get_or_insert(kind);
+            }
+            quiz
+        };
+        match state {
+            SavedState::Start => State::Start,
+            SavedState::ReceiveFullName => State::ReceiveFullName,
+            SavedState::RecieveGameChoice => State::RecieveGameChoice,
+            SavedState::RecieveAmountOfQuestions { kind } => State::RecieveAmountOfQuestions { kind },
+            SavedState::Quiz { quiz, question_number, score } | SavedState::MixedQuiz { quiz, question_number, score } => {
+                State::Quiz { quiz, question_number, score }
+            }
+            SavedState::Battle { battle } => State::Battle { battle },
+            SavedState::StressedWordsQuizRecieveAmountOfQuestions => {
+                State::RecieveAmountOfQuestions { kind: Some(QuizKind::Stress) }
+            }
+            SavedState::PartsOfSpeechRecieveAmountOfQuestions => {
+                State::RecieveAmountOfQuestions { kind: Some(QuizKind::PartsOfSpeech) }
+            }
+            SavedState::DeclensionsRecieveAmountOfQuestions => {
+                State::RecieveAmountOfQuestions { kind: Some(QuizKind::Declensions) }
+            }
+            SavedState::NounFormsStressRecieveAmountOfQuestions => {
+                State::RecieveAmountOfQuestions { kind: Some(QuizKind::NounFormsStress) }
+            }
+            SavedState::MixedQuizRecieveAmountOfQuestions => State::RecieveAmountOfQuestions { kind: None },
+            // The noun forms' stress questions were asked in the stressed words quiz too, they are explained the same
+            SavedState::StressedWordsQuiz { quiz, question_number, score } => State::Quiz {
+                quiz: of_kind(quiz, QuizKind::Stress),
+                question_number,
+                score,
+            },
+            SavedState::PartsOfSpeechQuiz { quiz, question_number, score } => State::Quiz {
+                quiz: of_kind(quiz, QuizKind::PartsOfSpeech),
+                question_number,
+                score,
+            },
+            SavedState::DeclensionsQuiz { quiz, question_number, score } => State::Quiz {
+                quiz: of_kind(quiz, QuizKind::Declensions),
+                question_number,
+                score,
+            },
+        }
+    }
+}
+
+impl State {
+    // The quiz in progress, the number of the current question and the score
+    fn quiz_progress(&self) -> Option<(&quiz::Quiz, usize, usize)> {
+        match self {
+            State::Quiz { quiz, question_number, score } => Some((quiz, *question_number, *score)),
+            _ => None,
+        }
+    }
+
+    fn is_quiz(&self) -> bool {
+        self.quiz_progress().is_some()
+    }
 }
 
 type UserInfoStorage = std::sync::Arc<ErasedStorage<State>>;
 
+#[derive(BotCommands, Clone)]
+#[command(rename_rule = "lowercase", description = "Доступні команди:")]
+enum Command {
+    #[command(description = "почати спочатку")]
+    Start,
+    #[command(description = "показати меню тестів")]
+    Menu,
+    #[command(description = "скасувати тест і повернутися до меню")]
+    Cancel,
+    #[command(description = "пропустити питання")]
+    Skip,
+    #[command(description = "розповісти про тести і команди")]
+    Help,
+    #[command(description = "пройти виклик дня")]
+    Daily,
+    #[command(description = "пройти тест за кодом, яким з тобою поділились: /quiz КОД")]
+    Quiz(String),
+    #[command(description = "почати батл у груповому чаті: /battle stress 10")]
+    Battle(String),
+    #[command(description = "створити клас, наприклад: /newclass 7-Б")]
+    NewClass(String),
+    #[command(description = "показати свої класи")]
+    Classes,
+    #[command(description = "задати класу домашнє завдання")]
+    Assign(String),
+    #[command(description = "переглянути результати класу: /results КОД")]
+    Results(String),
+    #[command(description = "приєднатися до класу: /join КОД")]
+    Join(String),
+    #[command(description = "показати домашні завдання")]
+    Homework,
+    #[command(description = "переглянути і змінити профіль")]
+    Profile,
+    #[command(description = "обрати, хто пояснюватиме помилки")]
+    Personality,
+    #[command(description = "обрати, як показувати питання: кнопками чи вікторинами Telegram")]
+    Presentation,
+    #[command(description = "змінити пропорції змішаного тесту: /mix stress 2 parts 1 declensions 1")]
+    Mix(String),
+    #[command(description = "нагадувати про тренування: /remind 19:00 або /remind off")]
+    Remind(String),
+    #[command(description = "змінити часовий пояс нагадувань: /timezone Europe/Kyiv")]
+    Timezone(String),
+    #[command(description = "показати досягнення")]
+    Achievements,
+    // The admin commands aren't shown to everyone
+    #[command(description = "off")]
+    Reports,
+    #[command(description = "off")]
+    Broadcast(String),
+    #[command(description = "off")]
+    Usage,
+    #[command(description = "off")]
+    Reload,
+}
+
+// The startup errors are for the one running the bot, so they're printed plainly instead of a panic
+fn exit_with_error(error: impl std::fmt::Display) -> ! {
+    eprintln!("{}", error);
+    std::process::exit(1)
+}
+
 #[tokio::main]
 async fn main() {
-    dotenv().expect("Failed to load .env file");
-    let CHATGPT_API_KEY = std::env::var("CHATGPT_API_KEY").expect("CHATGPT_API_KEY is not set");
+    // The settings may come from the environment only, so the .env file is optional
+    dotenv().ok();
 
     pretty_env_logger::init();
 
-    let bot = Bot::from_env();
+    let config = match Config::load() {
+        Ok(config) => Arc::new(config),
+        Err(e) => exit_with_error(e),
+    };
+    admin::set_admins(config.admin.chat_ids());
+    classes::set_teachers(config.classes.teacher_chat_ids());
+
+    let bot = Bot::new(&config.telegram.token);
 
     print!("Establishing connection to the database... ");
-    let storage: UserInfoStorage = SqliteStorage::open("db.sqlite", Json)
-        .await
-        .unwrap()
-        .erase();
+    let storage: UserInfoStorage = match SqliteStorage::open(&config.paths.database, Json).await {
+        Ok(storage) => Arc::new(TimedStorage(storage)).erase(),
+        Err(e) => exit_with_error(format!("Failed to open the dialogues storage '{}': {}", config.paths.database, e)),
+    };
+    let db = match Database::open(&config.paths.database, config.quiz.default_personality()).await {
+        Ok(db) => Arc::new(db),
+        Err(e) => exit_with_error(format!("Failed to open the database '{}': {}", config.paths.database, e)),
+    };
     println!("ESTEBLISHED");
 
-    // Load the dictionary of stressed words
-    print!("Loading the dictionary of stressed words... ");
-    let stressed_words_dictionary = Arc::new(quiz::stress::StressWords::new(
-        File::open("stress.txt").expect("Failed to open file 'stress.txt'"),
-    ));
-    println!("LOADED");
-
-    // Load the conllu file w/ the Ukrainian treebank
-    // TODO? Implement a way to use the Ukrainian treebank to generate questions
-
-    print!("Loading the conllu file... ");
-    let conllu_file = File::open("uk_iu-ud-dev.conllu").expect("Failed to open conllu file");
-    let conllu_doc = PartsSentences::new(conllu_file);
-    let conllu_doc = Arc::new(conllu_doc);
-    println!("LOADED");
-
-    print!("Loading the declension dictionary... ");
-    let declension_file = File::open("words_with_declensions.json").expect("Failed to open declension file");
-    let declension_file = quiz::declension::Declension::new(declension_file);
-    let declension_file = Arc::new(declension_file);
-    println!("LOADED");
+    let blacklist = match db.blacklist().await {
+        Ok(blacklist) => Arc::new(Blacklist::new(blacklist)),
+        Err(e) => exit_with_error(format!("Failed to load the blacklist: {}", e)),
+    };
+    let datasets = match Datasets::load(&config.paths, blacklist) {
+        Ok(datasets) => datasets,
+        Err(e) => exit_with_error(e),
+    };
+    let registry = Arc::new(DataRegistry::new(datasets, config.paths.clone()));
+    registry.clone().watch_files();
 
     print!("Creating the ChatGPT instance... ");
 
     let gpt = {
-        let mut gpt = ChatGPT::new(CHATGPT_API_KEY).expect("Unable to connect with ChatGPT");
+        let mut gpt = match ChatGPT::new(&config.ai.api_key) {
+            Ok(gpt) => gpt,
+            Err(e) => exit_with_error(format!("Unable to connect with ChatGPT: {}", e)),
+        };
 
-        gpt.config.engine = ChatGPTEngine::Gpt35Turbo;
-        gpt.config.timeout = std::time::Duration::from_secs(15);
+        gpt.config.engine = config.ai.engine();
+        gpt.config.timeout = config.ai.timeout();
 
         gpt
     };
 
     let quiz_helper = Arc::new(QuizHelper::new(
         gpt,
-        quiz::ai_helper::Personality::Shevchenko,
+        AiCache::open(&config.paths.ai_cache),
+        config.ai.stress_explanations,
     ));
-    let quiz_helper_for_parts = quiz_helper.clone();
-    let quiz_helper_for_declensions = quiz_helper.clone();
     println!("CREATED");
 
-    Dispatcher::builder(
-        bot,
-        Update::filter_message()
-            .enter_dialogue::<Message, ErasedStorage<State>, State>()
-            .branch(dptree::case![State::Start].endpoint(start))
-            .branch(dptree::case![State::ReceiveFullName].endpoint(receive_full_name))
-            .branch(dptree::case![State::RecieveGameChoice].endpoint(receive_game_choice))
-            .branch(
-                dptree::case![State::StressedWordsQuizRecieveAmountOfQuestions].endpoint(
-                    move |bot: Bot, dialogue: QuizDialogue, msg: Message| {
-                        receive_amount_of_questions(
-                            stressed_words_dictionary.clone(),
-                            bot,
-                            dialogue,
-                            msg,
-                        )
-                    },
-                ),
+    if let Some(address) = config.metrics.listen_address {
+        if let Err(e) = metrics::serve(address, db.clone()) {
+            exit_with_error(format!("Failed to serve the metrics on {}: {}", address, e));
+        }
+        log::info!("Serving the metrics on http://{}/metrics", address);
+    }
+
+    // The commands are shown in the menu of the Telegram clients, the bot works without them too
+    if let Err(e) = bot.set_my_commands(Command::bot_commands()).await {
+        log::warn!("Failed to register the commands: {}", e);
+    }
+
+    let message_handler = Update::filter_message()
+        .enter_dialogue::<Message, ErasedStorage<State>, State>()
+        // The commands work in any state, so they go before the dialogue branches
+        .branch({
+            let ai_helper = quiz_helper.clone();
+            dptree::entry().filter_command::<Command>().endpoint(
+                move |bot: Bot,
+                      dialogue: QuizDialogue,
+                      msg: Message,
+                      cmd: Command,
+                      db: Arc<Database>,
+                      registry: Arc<DataRegistry>| {
+                    receive_command(ai_helper.clone(), registry, bot, dialogue, msg, cmd, db)
+                },
             )
-            .branch(
-                dptree::case![State::StressedWordsQuiz {
-                    quiz,
-                    question_number,
-                    score
-                }]
-                .endpoint(
-                    move |bot: Bot,
-                          dialogue: QuizDialogue,
-                          (quiz, question_number, score): (quiz::Quiz, usize, usize),
-                          msg: Message| {
-                        stressed_quiz(
-                            quiz_helper.clone(),
-                            bot,
-                            dialogue,
-                            (quiz.clone(), question_number, score),
-                            msg,
-                        )
-                    },
-                ),
+        })
+        // The comments to the reported questions are the replies, in any state
+        .branch(
+            dptree::filter_async(|msg: Message, db: Arc<Database>| async move {
+                reports::is_report_comment(&msg, &db).await
+            })
+            .endpoint(reports::receive_report_comment),
+        )
+        // The group chats only talk to the bot with the commands, the rest is the members' own conversation
+        .branch(dptree::filter(|msg: Message| !msg.chat.is_private()).endpoint(|| async { HandlerResult::Ok(()) }))
+        .branch(dptree::case![State::Start].endpoint(start))
+        .branch(dptree::case![State::ReceiveFullName].endpoint(receive_full_name))
+        .branch(dptree::case![State::RecieveGameChoice].endpoint(receive_game_choice))
+        .branch({
+            let ai_helper = quiz_helper.clone();
+            dptree::case![State::RecieveAmountOfQuestions { kind }].endpoint(
+                move |kind: Option<QuizKind>,
+                      bot: Bot,
+                      dialogue: QuizDialogue,
+                      msg: Message,
+                      db: Arc<Database>,
+                      registry: Arc<DataRegistry>| {
+                    receive_amount_of_questions(kind, registry.datasets(), ai_helper.clone(), bot, dialogue, msg, db)
+                },
             )
-            .branch(
-                dptree::case![State::PartsOfSpeechRecieveAmountOfQuestions].endpoint(
-                    move |bot: Bot, dialogue: QuizDialogue, msg: Message| {
-                        receive_amount_of_questions_parts_of_speech(
-                            conllu_doc.clone(),
-                            bot,
-                            dialogue,
-                            msg,
-                        )
-                    },
-                ),
+        })
+        // The answers come with the buttons, any message in the middle of the quiz is a mistake
+        .branch(
+            dptree::filter(|state: State| state.is_quiz()).endpoint(quiz_expects_button),
+        );
+
+    // The answers come either with the buttons or in the polls, but are handled the same way
+    let answer_handler = dptree::entry()
+        .enter_dialogue::<AnswerUpdate, ErasedStorage<State>, State>()
+        .branch({
+            let ai_helper = quiz_helper.clone();
+            dptree::case![State::Quiz {
+                quiz,
+                question_number,
+                score
+            }]
+            .endpoint(
+                move |bot: Bot,
+                      dialogue: QuizDialogue,
+                      (quiz, question_number, score): (quiz::Quiz, usize, usize),
+                      update: AnswerUpdate,
+                      db: Arc<Database>,
+                      registry: Arc<DataRegistry>| {
+                    receive_quiz_answer(
+                        ai_helper.clone(),
+                        registry.datasets().declension,
+                        bot,
+                        dialogue,
+                        (quiz.clone(), question_number, score),
+                        update,
+                        db,
+                    )
+                },
             )
-            .branch(
-                dptree::case![State::PartsOfSpeechQuiz {
-                    quiz,
-                    question_number,
-                    score
-                }]
-                .endpoint(
-                    move |bot: Bot,
-                          dialogue: QuizDialogue,
-                          (quiz, question_number, score): (quiz::Quiz, usize, usize),
-                          msg: Message| {
-                        parts_of_speech_quiz(
-                            quiz_helper_for_parts.clone(),
-                            bot,
-                            dialogue,
-                            (quiz.clone(), question_number, score),
-                            msg,
-                        )
-                    },
-                ),
+        })
+        .branch(dptree::case![State::Battle { battle }].endpoint(
+            |bot: Bot, dialogue: QuizDialogue, update: AnswerUpdate| async move {
+                match update {
+                    AnswerUpdate::Button(q) => battle::receive_battle_answer(bot, dialogue, *q).await,
+                    // The battles are only answered with the buttons
+                    AnswerUpdate::Poll(_) => Ok(()),
+                }
+            },
+        ))
+        // The quiz is over or the user is doing something else already
+        .endpoint(|bot: Bot, update: AnswerUpdate| async move {
+            if let AnswerUpdate::Button(q) = update {
+                bot.answer_callback_query(q.id)
+                    .text("Це питання вже неактуальне")
+                    .await?;
+            }
+            HandlerResult::Ok(())
+        });
+
+    let callback_query_handler = Update::filter_callback_query()
+        .branch({
+            let ai_helper = quiz_helper.clone();
+            dptree::filter(|q: CallbackQuery| {
+                q.data.as_deref().is_some_and(|d| d.starts_with(HOMEWORK_CALLBACK_PREFIX))
+            })
+            .enter_dialogue::<CallbackQuery, ErasedStorage<State>, State>()
+            .endpoint(
+                move |bot: Bot, dialogue: QuizDialogue, q: CallbackQuery, db: Arc<Database>, registry: Arc<DataRegistry>| {
+                    start_homework(registry.datasets(), ai_helper.clone(), bot, dialogue, q, db)
+                },
             )
-            .branch(
-                dptree::case![State::DeclensionsRecieveAmountOfQuestions].endpoint(
-                    move |bot: Bot, dialogue: QuizDialogue, msg: Message| {
-                        receive_amount_of_questions_declensions(
-                            declension_file.clone(),
-                            bot,
-                            dialogue,
-                            msg,
-                        )
-                    },
-                ),
+        })
+        .branch({
+            let ai_helper = quiz_helper.clone();
+            dptree::filter(|q: CallbackQuery| {
+                q.data.as_deref().is_some_and(|d| d.starts_with(REDO_CALLBACK_PREFIX))
+            })
+            .enter_dialogue::<CallbackQuery, ErasedStorage<State>, State>()
+            .endpoint(
+                move |bot: Bot, dialogue: QuizDialogue, q: CallbackQuery, db: Arc<Database>, registry: Arc<DataRegistry>| {
+                    mistakes::redo_mistakes(ai_helper.clone(), registry.datasets().declension, bot, dialogue, q, db)
+                },
             )
-            .branch(
-                dptree::case![State::DeclensionsQuiz {
-                    quiz,
-                    question_number,
-                    score
-                }]
-                .endpoint(
-                    move |bot: Bot,
-                          dialogue: QuizDialogue,
-                          (quiz, question_number, score): (quiz::Quiz, usize, usize),
-                          msg: Message| {
-                        declensions_quiz(
-                            quiz_helper_for_declensions.clone(),
-                            bot,
-                            dialogue,
-                            (quiz.clone(), question_number, score),
-                            msg,
-                        )
-                    },
-                )
-            ),
+        })
+        .branch(
+            dptree::filter(|q: CallbackQuery| {
+                q.data.as_deref().is_some_and(|d| d.starts_with(REPORT_CALLBACK_PREFIX))
+            })
+            .enter_dialogue::<CallbackQuery, ErasedStorage<State>, State>()
+            .endpoint(reports::report_question),
+        )
+        .branch(
+            dptree::filter(|q: CallbackQuery| {
+                q.data.as_deref().is_some_and(|d| d.starts_with(REVIEW_CALLBACK_PREFIX))
+            })
+            .endpoint(reports::review_report),
+        )
+        .branch(
+            dptree::filter(|q: CallbackQuery| {
+                q.data.as_deref().is_some_and(|d| d.starts_with(PROFILE_CALLBACK_PREFIX))
+            })
+            .enter_dialogue::<CallbackQuery, ErasedStorage<State>, State>()
+            .endpoint(receive_profile_choice),
+        )
+        .branch(
+            dptree::filter(|q: CallbackQuery| {
+                q.data.as_deref().is_some_and(|d| d.starts_with(PERSONALITY_CALLBACK_PREFIX))
+            })
+            .endpoint(receive_personality_choice),
+        )
+        .branch(
+            dptree::filter(|q: CallbackQuery| {
+                q.data.as_deref().is_some_and(|d| d.starts_with(PRESENTATION_CALLBACK_PREFIX))
+            })
+            .endpoint(receive_presentation_choice),
+        )
+        .branch(
+            dptree::filter(|q: CallbackQuery| {
+                q.data.as_deref().is_some_and(|d| d.starts_with(ANSWER_CALLBACK_PREFIX))
+            })
+            .map(|q: CallbackQuery| AnswerUpdate::Button(Box::new(q)))
+            .chain(answer_handler.clone()),
+        );
+
+    let poll_answer_handler = Update::filter_poll_answer()
+        .map(AnswerUpdate::Poll)
+        .chain(answer_handler);
+
+    reminders::spawn_scheduler(bot.clone(), db.clone());
+
+    let mut dispatcher = Dispatcher::builder(
+        bot.clone(),
+        dptree::entry()
+            // Whoever sends anything is an active user of the day
+            .inspect_async(|update: Update, db: Arc<Database>| async move {
+                if let Some(user) = update.user() {
+                    if let Err(e) = db.record_activity(user.id.0).await {
+                        log::error!("Failed to record the activity: {}", e);
+                    }
+                }
+            })
+            .branch(message_handler)
+            .branch(callback_query_handler)
+            .branch(poll_answer_handler),
     )
-    .dependencies(dptree::deps![storage])
+    .dependencies(dptree::deps![storage, db.clone(), registry, config.clone()])
     .enable_ctrlc_handler()
-    .build()
-    .dispatch()
-    .await;
+    .build();
+
+    // Behind a reverse proxy Telegram sends the updates to the bot, otherwise the bot polls for them
+    match config.webhook.url() {
+        Some(url) => {
+            let listener = match webhook::listen(bot, &config.webhook, url, db).await {
+                Ok(listener) => listener,
+                Err(e) => exit_with_error(e),
+            };
+            log::info!("Listening for the webhook updates on {}", config.webhook.listen_address);
+            dispatcher
+                .dispatch_with_listener(listener, LoggingErrorHandler::with_custom_text("An error from the update listener"))
+                .await;
+        }
+        None => dispatcher.dispatch().await,
+    }
+}
+
+async fn receive_command(
+    ai_helper: Arc<QuizHelper>,
+    registry: Arc<DataRegistry>,
+    bot: Bot,
+    dialogue: QuizDialogue,
+    msg: Message,
+    cmd: Command,
+    db: Arc<Database>,
+) -> HandlerResult {
+    let datasets = registry.datasets();
+    // The group chats only have the battles, everything else is personal
+    if !msg.chat.is_private() {
+        match cmd {
+            Command::Battle(args) => battle::start_battle(&bot, &dialogue, &msg, &datasets, &args).await?,
+            Command::Skip => battle::skip_battle_question(&bot, &dialogue, &msg).await?,
+            Command::Cancel => battle::cancel_battle(&bot, &dialogue, &msg).await?,
+            Command::Help => {
+                bot.send_message(msg.chat.id, format!("{}\n\n{}", HELP_TEXT, Command::descriptions()))
+                    .await?;
+            }
+            _ => {
+                bot.send_message(msg.chat.id, "Ця команда працює лише в особистому чаті зі мною")
+                    .await?;
+            }
+        }
+        return Ok(());
+    }
+
+    match cmd {
+        Command::Battle(args) => battle::start_battle(&bot, &dialogue, &msg, &datasets, &args).await?,
+        Command::Start => start(bot, dialogue, msg, db).await?,
+        Command::Menu => {
+            let pending = db.pending_assignments(msg.chat.id).await?.len();
+            let text = if pending > 0 {
+                format!("Що б ти хотів зробити? Невиконаних домашніх завдань: {}", pending)
+            } else {
+                "Що б ти хотів зробити?".to_string()
+            };
+            bot.send_message(msg.chat.id, text)
+                .reply_markup(game_choice_keyboard())
+                .await?;
+            dialogue.update(State::RecieveGameChoice).await?;
+        }
+        Command::Cancel => {
+            // Under the lock, so the timeout of the question can't bring the quiz back
+            let _lock = chat_lock::lock_chat(msg.chat.id).await;
+            let state = dialogue.get_or_default().await?;
+            if matches!(state, State::Start | State::ReceiveFullName | State::RecieveGameChoice) {
+                bot.send_message(msg.chat.id, "Зараз немає чого скасовувати").await?;
+                return Ok(());
+            }
+            bot.send_message(msg.chat.id, "Тест скасовано. Що б ти хотів зробити?")
+                .reply_markup(game_choice_keyboard())
+                .await?;
+            dialogue.update(State::RecieveGameChoice).await?;
+        }
+        Command::Skip => {
+            let _lock = chat_lock::lock_chat(msg.chat.id).await;
+            let state = dialogue.get_or_default().await?;
+            skip_question(&bot, &dialogue, &ai_helper, &datasets.declension, &db, state, false).await?;
+        }
+        Command::Help => {
+            bot.send_message(msg.chat.id, format!("{}\n\n{}", HELP_TEXT, Command::descriptions()))
+                .await?;
+        }
+        Command::Daily => daily::start_daily(&bot, &dialogue, &ai_helper, &datasets, &db).await?,
+        Command::Quiz(code) => {
+            let Some(code) = QuizCode::parse(&code) else {
+                bot.send_message(
+                    msg.chat.id,
+                    "Не вдалося розібрати код тесту. Він виглядає так: /quiz stress-10-any-5f3a9c21",
+                )
+                .await?;
+                return Ok(());
+            };
+            if dialogue.get_or_default().await?.is_quiz() {
+                bot.send_message(msg.chat.id, "Спершу закінчи тест або скасуй його (/cancel)")
+                    .await?;
+                return Ok(());
+            }
+            let Some(questions) = datasets.generate_quiz(&code) else {
+                bot.send_message(msg.chat.id, "На жаль, цей тест зараз недоступний").await?;
+                return Ok(());
+            };
+
+            bot.send_message(msg.chat.id, format!("Тест за кодом {}. Почнемо!", code))
+                .reply_markup(KeyboardRemove::new())
+                .await?;
+            let profile = db.get_profile(msg.chat.id).await?;
+            let state = send_first_question(
+                &bot,
+                msg.chat.id,
+                &ai_helper,
+                &datasets.declension,
+                &profile,
+                quiz::Quiz::new(questions),
+            )
+            .await?;
+            schedule_question_timeout(&bot, &dialogue, &ai_helper, &datasets.declension, &db, &state);
+            dialogue.update(state).await?;
+        }
+        Command::NewClass(name) => classes::create_class(&bot, &msg, &db, &name).await?,
+        Command::Classes => classes::list_classes(&bot, &msg, &db).await?,
+        Command::Assign(args) => classes::create_assignment(&bot, &msg, &db, &args).await?,
+        Command::Results(join_code) => classes::show_results(&bot, &msg, &db, &join_code).await?,
+        Command::Join(join_code) => classes::join_class(&bot, &msg, &db, &join_code).await?,
+        Command::Homework => classes::send_pending_assignments(&bot, msg.chat.id, &db).await?,
+        Command::Profile => {
+            let profile = db.get_profile(msg.chat.id).await?;
+            bot.send_message(msg.chat.id, profile_text(&profile))
+                .reply_markup(profile_keyboard())
+                .await?;
+        }
+        Command::Personality => {
+            let current = db.get_profile(msg.chat.id).await?.personality;
+            let keyboard = InlineKeyboardMarkup::new(Personality::ALL.iter().map(|p| {
+                let text = if *p == current { format!("✅ {}", p.name()) } else { p.name().to_string() };
+                vec![InlineKeyboardButton::callback(
+                    text,
+                    format!("{}{}", PERSONALITY_CALLBACK_PREFIX, p.key()),
+                )]
+            }));
+            bot.send_message(msg.chat.id, "Хто пояснюватиме твої помилки?")
+                .reply_markup(keyboard)
+                .await?;
+        }
+        Command::Presentation => {
+            let current = db.get_profile(msg.chat.id).await?.presentation;
+            let keyboard = InlineKeyboardMarkup::new(Presentation::ALL.iter().map(|p| {
+                let text = if *p == current { format!("✅ {}", p.name()) } else { p.name().to_string() };
+                vec![InlineKeyboardButton::callback(
+                    text,
+                    format!("{}{}", PRESENTATION_CALLBACK_PREFIX, p.key()),
+                )]
+            }));
+            bot.send_message(msg.chat.id, "Як показувати питання? Зміни діятимуть з наступного питання")
+                .reply_markup(keyboard)
+                .await?;
+        }
+        Command::Mix(mix) => {
+            let text = if mix.trim().is_empty() {
+                format!(
+                    "Зараз у змішаному тесті: {}\n\n{}",
+                    db.get_profile(msg.chat.id).await?.quiz_mix.describe(),
+                    mix_usage()
+                )
+            } else if let Some(mix) = QuizMix::parse(&mix) {
+                db.set_quiz_mix(msg.chat.id, &mix).await?;
+                format!("Тепер у змішаному тесті: {}", mix.describe())
+            } else {
+                format!("Не вдалося розібрати пропорції.\n\n{}", mix_usage())
+            };
+            bot.send_message(msg.chat.id, text).await?;
+        }
+        Command::Remind(args) => reminders::set_reminder(&bot, msg.chat.id, &args, &db).await?,
+        Command::Timezone(args) => reminders::set_time_zone(&bot, msg.chat.id, &args, &db).await?,
+        Command::Achievements => achievements::list(&bot, msg.chat.id, &db).await?,
+        Command::Reports => reports::list_reports(&bot, msg.chat.id, &db).await?,
+        Command::Broadcast(text) => admin::broadcast(&bot, msg.chat.id, &text, &db).await?,
+        Command::Usage => admin::usage(&bot, msg.chat.id, &ai_helper, &db).await?,
+        Command::Reload => admin::reload(&bot, msg.chat.id, &registry).await?,
+    }
+    Ok(())
+}
+
+fn mix_usage() -> String {
+    format!(
+        "Щоб змінити пропорції, напиши тести і скільки частин питань кожного з них, наприклад:\n\
+        /mix stress 2 parts 1 declensions 1\n\n\
+        Тести: {}\nТести, яких немає в списку, не питатимуться",
+        QuizKind::ALL
+            .iter()
+            .map(|k| format!("{} ({})", k.key(), k.name()))
+            .collect::<Vec<_>>()
+            .join(", "),
+    )
+}
+
+const HELP_TEXT: &str = "Я допомагаю вивчати українську мову за допомогою тестів:
+
+• Наголос -- обери правильно наголошене слово.
+• Частини мови -- визнач, якою частиною мови є виділене слово в реченні.
+• Відмінювання -- визнач відмінок і число форми іменника.
+• Наголос у формах іменників -- обери правильний наголос у певній формі іменника.
+• Змішаний тест -- питання всіх видів разом, пропорції можна змінити командою /mix.
+
+Щодня є виклик дня (/daily) -- однаковий для всіх змішаний тест. Проходь його день у день, щоб збільшувати серію.
+
+Питання я підбираю під твій рівень: частіше питаю про те, що поки вдається гірше. Після неправильної відповіді я поясню, у чому помилка, а наприкінці тесту покажу всі помилки і запропоную їх повторити. Обрати тест можна в меню (/menu).
+
+У режимі на час на кожне питання є лише кілька секунд, а в кінці я покажу середній час відповіді. Увімкнути його можна в профілі (/profile).
+
+Якщо питання здається неправильним, натисни під ним «⚠️ Помилка в питанні» -- я передам його на перевірку.
+
+За успіхи в тестах я даю досягнення, переглянути їх можна командою /achievements.
+
+Щоб не забувати тренуватися, я можу нагадувати про це щодня в обраний час: /remind 19:00.
+
+У груповому чаті можна влаштувати батл (/battle): відповідають усі, а найшвидший отримує бонус.
+
+Вчителі можуть створити клас (/newclass) і задавати йому домашні завдання (/assign), а учні -- приєднатися до класу (/join) і виконувати їх (/homework).";
+
+/// Tells the user the correct answer and goes on with the next question as if the question was answered wrongly.
+/// `time_is_up` is for the question of the timed quiz left without an answer, otherwise the user has skipped it.
+async fn skip_question(
+    bot: &Bot,
+    dialogue: &QuizDialogue,
+    ai_helper: &Arc<QuizHelper>,
+    words: &Arc<Declension>,
+    db: &Arc<Database>,
+    state: State,
+    time_is_up: bool,
+) -> HandlerResult {
+    let chat_id = dialogue.chat_id();
+    let Some((quiz, question_number, score)) = state.quiz_progress() else {
+        bot.send_message(chat_id, "Зараз немає питання, яке можна пропустити").await?;
+        return Ok(());
+    };
+    let mut quiz = quiz.clone();
+    record_answer(db, chat_id, &mut quiz, question_number, None).await?;
+
+    if let Some(correct_answer) = quiz.questions[question_number].answers.iter().find(|a| a.is_correct) {
+        let reason = if time_is_up { "⏱ Час вийшов!" } else { "Питання пропущено." };
+        bot.send_message(
+            chat_id,
+            format!("{} Правильна відповідь -- <b>{}</b>", reason, correct_answer.text),
+        )
+        .parse_mode(ParseMode::Html)
+        .await?;
+    }
+
+    let profile = db.get_profile(chat_id).await?;
+    let question_number = question_number + 1;
+    if question_number >= quiz.questions.len() {
+        return finish_quiz(bot, dialogue, chat_id, &quiz, score, &profile, db).await;
+    }
+
+    send_quiz_question(bot, chat_id, ai_helper, words, &profile, &mut quiz, question_number).await?;
+    let next_state = State::Quiz { quiz, question_number, score };
+    schedule_question_timeout(bot, dialogue, ai_helper, words, db, &next_state);
+    dialogue.update(next_state).await?;
+    Ok(())
+}
+
+/// Remembers the answer in the quiz and in the learner's mastery of the skill, `None` if there is no answer
+async fn record_answer(
+    db: &Database,
+    chat_id: ChatId,
+    quiz: &mut quiz::Quiz,
+    question_number: usize,
+    answer: Option<&quiz::Answer>,
+) -> Result<(), sqlx::Error> {
+    quiz.record_answer(question_number, answer);
+    let correct = answer.is_some_and(|a| a.is_correct);
+    let kind = quiz.questions[question_number].kind.map_or("unknown", |kind| kind.key());
+    metrics::answer(kind, correct);
+    db.record_skill_answer(chat_id, &quiz.questions[question_number], correct).await
+}
+
+// In the timed quiz the question left without an answer is closed when the time is up, as if it was skipped.
+// It's a plain function, since the task asks the next question, which schedules the task again.
+fn schedule_question_timeout(
+    bot: &Bot,
+    dialogue: &QuizDialogue,
+    ai_helper: &Arc<QuizHelper>,
+    words: &Arc<Declension>,
+    db: &Arc<Database>,
+    state: &State,
+) {
+    let Some((quiz, question_number, _)) = state.quiz_progress() else {
+        return;
+    };
+    let Some(time_limit) = quiz.time_limit else {
+        return;
+    };
+    let quiz_id = quiz.id;
+    let (bot, dialogue, ai_helper, words, db) =
+        (bot.clone(), dialogue.clone(), ai_helper.clone(), words.clone(), db.clone());
+
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_secs(time_limit)).await;
+        let _lock = chat_lock::lock_chat(dialogue.chat_id()).await;
+        // Unless the question is answered, skipped or the quiz is over already
+        let result = match dialogue.get().await {
+            Ok(Some(state))
+                if state
+                    .quiz_progress()
+                    .is_some_and(|(quiz, number, _)| quiz.id == quiz_id && number == question_number) =>
+            {
+                skip_question(&bot, &dialogue, &ai_helper, &words, &db, state, true).await
+            }
+            Ok(_) => Ok(()),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            log::error!("Failed to close the timed out question: {}", e);
+        }
+    });
+}
+
+/// Like `receive_answer`, but also checks that the question is still asked: the time may be up
+/// while the answer is on its way. Must be called under the chat lock.
+async fn receive_timely_answer(
+    bot: &Bot,
+    dialogue: &QuizDialogue,
+    update: &AnswerUpdate,
+    quiz: &quiz::Quiz,
+    question_number: usize,
+) -> Result<Option<(ChatId, quiz::Answer)>, Box<dyn std::error::Error + Send + Sync>> {
+    let still_asked = dialogue
+        .get()
+        .await?
+        .as_ref()
+        .and_then(State::quiz_progress)
+        .is_some_and(|(current, number, _)| current.id == quiz.id && number == question_number);
+    if !still_asked {
+        if let AnswerUpdate::Button(q) = update {
+            bot.answer_callback_query(q.id.clone())
+                .text("Час на це питання вже вийшов")
+                .await?;
+        }
+        return Ok(None);
+    }
+    Ok(receive_answer(bot, update, quiz, question_number).await?)
+}
+
+const PROFILE_CALLBACK_PREFIX: &str = "profile:";
+const MAX_GRADE: u8 = 11;
+const PROFILE_QUIZ_SIZES: [usize; 5] = [5, 10, 15, 20, 30];
+// Seconds per question in the timed mode
+const TIME_LIMITS: [u64; 4] = [10, 15, 30, 60];
+
+fn profile_text(profile: &Profile) -> String {
+    let not_set = "не вказано".to_string();
+    format!(
+        "Твій профіль:\n\nІм'я: {}\nКлас: {}\nКількість питань: {}\nХто пояснює помилки: {}\nЯк показувати питання: {}\nРежим на час: {}\nЗмішаний тест: {}\nПідбір питань: {}\nНагадування: {}\nМова: {}\nУ боті з: {}",
+        profile.name.clone().unwrap_or_else(|| not_set.clone()),
+        profile.grade.map(|g| g.to_string()).unwrap_or_else(|| not_set.clone()),
+        profile.quiz_size.map(|s| s.to_string()).unwrap_or_else(|| not_set.clone()),
+        profile.personality.name(),
+        profile.presentation.name(),
+        profile
+            .time_limit
+            .map(|t| format!("{} с на питання", t))
+            .unwrap_or_else(|| "вимкнено".to_string()),
+        profile.quiz_mix.describe(),
+        if profile.adaptive { "під твій рівень" } else { "випадковий, тестом можна поділитися" },
+        reminders::describe(profile),
+        // The only language of the interface so far
+        if profile.language == "uk" { "українська" } else { profile.language.as_str() },
+        profile.created_on.clone().unwrap_or_else(|| "сьогодні".to_string()),
+    )
+}
+
+fn profile_keyboard() -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(vec![
+        vec![InlineKeyboardButton::callback("Змінити ім'я", format!("{}name", PROFILE_CALLBACK_PREFIX))],
+        vec![InlineKeyboardButton::callback("Змінити клас", format!("{}grade", PROFILE_CALLBACK_PREFIX))],
+        vec![InlineKeyboardButton::callback(
+            "Змінити кількість питань",
+            format!("{}size", PROFILE_CALLBACK_PREFIX),
+        )],
+        vec![InlineKeyboardButton::callback(
+            "Змінити режим на час",
+            format!("{}time", PROFILE_CALLBACK_PREFIX),
+        )],
+        vec![InlineKeyboardButton::callback(
+            "Змінити підбір питань",
+            format!("{}adaptive", PROFILE_CALLBACK_PREFIX),
+        )],
+    ])
+}
+
+// The options for the profile field, the last one (`clear_text`) is to clear the field
+fn profile_options_keyboard(
+    field: &str,
+    options: impl Iterator<Item = String>,
+    clear_text: &str,
+) -> InlineKeyboardMarkup {
+    let mut rows = options
+        .collect::<Vec<_>>()
+        .chunks(4)
+        .map(|chunk| {
+            chunk
+                .iter()
+                .map(|o| InlineKeyboardButton::callback(o.clone(), format!("{}{}:{}", PROFILE_CALLBACK_PREFIX, field, o)))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    rows.push(vec![InlineKeyboardButton::callback(
+        clear_text.to_string(),
+        format!("{}{}:none", PROFILE_CALLBACK_PREFIX, field),
+    )]);
+    InlineKeyboardMarkup::new(rows)
+}
+
+async fn receive_profile_choice(bot: Bot, dialogue: QuizDialogue, q: CallbackQuery, db: Arc<Database>) -> HandlerResult {
+    let (Some(data), Some(message)) = (
+        q.data.as_deref().and_then(|d| d.strip_prefix(PROFILE_CALLBACK_PREFIX)),
+        q.message,
+    ) else {
+        bot.answer_callback_query(q.id)
+            .text("Ця кнопка більше не працює, спробуй /profile ще раз")
+            .await?;
+        return Ok(());
+    };
+    let chat_id = message.chat.id;
+
+    match data.split_once(':') {
+        None if data == "name" => {
+            // The name is asked the same way as when we've just met, which would break the quiz
+            if !matches!(
+                dialogue.get_or_default().await?,
+                State::Start | State::ReceiveFullName | State::RecieveGameChoice
+            ) {
+                bot.answer_callback_query(q.id)
+                    .text("Спершу закінчи тест або скасуй його (/cancel)")
+                    .await?;
+                return Ok(());
+            }
+            bot.answer_callback_query(q.id).await?;
+            bot.send_message(chat_id, "Як тебе звати?")
+                .reply_markup(KeyboardRemove::new())
+                .await?;
+            dialogue.update(State::ReceiveFullName).await?;
+            return Ok(());
+        }
+        None if data == "grade" => {
+            bot.answer_callback_query(q.id).await?;
+            bot.edit_message_text(chat_id, message.id, "У якому ти класі?")
+                .reply_markup(profile_options_keyboard(
+                    "grade",
+                    (1..=MAX_GRADE).map(|g| g.to_string()),
+                    "Не вказувати",
+                ))
+                .await?;
+            return Ok(());
+        }
+        None if data == "size" => {
+            bot.answer_callback_query(q.id).await?;
+            bot.edit_message_text(chat_id, message.id, "Скільки питань пропонувати першим?")
+                .reply_markup(profile_options_keyboard(
+                    "size",
+                    PROFILE_QUIZ_SIZES.iter().map(|s| s.to_string()),
+                    "Не вказувати",
+                ))
+                .await?;
+            return Ok(());
+        }
+        None if data == "time" => {
+            bot.answer_callback_query(q.id).await?;
+            bot.edit_message_text(
+                chat_id,
+                message.id,
+                "Скільки секунд давати на кожне питання? Зміни діятимуть з наступного тесту",
+            )
+            .reply_markup(profile_options_keyboard(
+                "time",
+                TIME_LIMITS.iter().map(|t| t.to_string()),
+                "Вимкнути режим на час",
+            ))
+            .await?;
+            return Ok(());
+        }
+        // There are only two options, so the button switches between them
+        None if data == "adaptive" => {
+            let adaptive = db.get_profile(chat_id).await?.adaptive;
+            db.set_adaptive(chat_id, !adaptive).await?;
+        }
+        Some(("grade", value)) => {
+            let grade = value.parse::<u8>().ok().filter(|g| (1..=MAX_GRADE).contains(g));
+            db.set_grade(chat_id, grade).await?;
+        }
+        Some(("size", value)) => {
+            let size = value.parse::<usize>().ok().filter(|s| *s > 0);
+            db.set_quiz_size(chat_id, size).await?;
+        }
+        Some(("time", value)) => {
+            let time_limit = value.parse::<u64>().ok().filter(|t| *t > 0);
+            db.set_time_limit(chat_id, time_limit).await?;
+        }
+        // The button is from some old version of the bot
+        _ => {
+            bot.answer_callback_query(q.id)
+                .text("Ця кнопка більше не працює, спробуй /profile ще раз")
+                .await?;
+            return Ok(());
+        }
+    }
+
+    bot.answer_callback_query(q.id).await?;
+    let profile = db.get_profile(chat_id).await?;
+    bot.edit_message_text(chat_id, message.id, profile_text(&profile))
+        .reply_markup(profile_keyboard())
+        .await?;
+    Ok(())
+}
+
+const PERSONALITY_CALLBACK_PREFIX: &str = "personality:";
+async fn receive_personality_choice(bot: Bot, q: CallbackQuery, db: Arc<Database>) -> HandlerResult {
+    let personality = q
+        .data
+        .as_deref()
+        .and_then(|d| d.strip_prefix(PERSONALITY_CALLBACK_PREFIX))
+        .and_then(Personality::from_key);
+
+    match (personality, q.message) {
+        (Some(personality), Some(message)) => {
+            db.set_personality(message.chat.id, personality).await?;
+            bot.answer_callback_query(q.id).await?;
+            bot.edit_message_text(
+                message.chat.id,
+                message.id,
+                format!("Тепер твої помилки пояснюватиме: {}", personality.name()),
+            )
+            .await?;
+        }
+        // The button is from some old version of the bot or the message is too old
+        _ => {
+            bot.answer_callback_query(q.id)
+                .text("Ця кнопка більше не працює, спробуй /personality ще раз")
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+const PRESENTATION_CALLBACK_PREFIX: &str = "presentation:";
+async fn receive_presentation_choice(bot: Bot, q: CallbackQuery, db: Arc<Database>) -> HandlerResult {
+    let presentation = q
+        .data
+        .as_deref()
+        .and_then(|d| d.strip_prefix(PRESENTATION_CALLBACK_PREFIX))
+        .and_then(Presentation::from_key);
+
+    match (presentation, q.message) {
+        (Some(presentation), Some(message)) => {
+            db.set_presentation(message.chat.id, presentation).await?;
+            bot.answer_callback_query(q.id).await?;
+            bot.edit_message_text(
+                message.chat.id,
+                message.id,
+                format!("Тепер питання показуватимуться так: {}", presentation.name()),
+            )
+            .await?;
+        }
+        // The button is from some old version of the bot or the message is too old
+        _ => {
+            bot.answer_callback_query(q.id)
+                .text("Ця кнопка більше не працює, спробуй /presentation ще раз")
+                .await?;
+        }
+    }
+    Ok(())
 }
 
 const GREETING_TEXT: &str = "Привіт! Я -- морфологічний бот. Я допоможу тобі вивчити українську мову! Давай познайомимося! Як тебе звати?";
-async fn start(bot: Bot, dialogue: QuizDialogue, msg: Message) -> HandlerResult {
+async fn start(bot: Bot, dialogue: QuizDialogue, msg: Message, db: Arc<Database>) -> HandlerResult {
+    // We already know each other, so there is no need to ask the name again
+    if let Some(name) = db.get_profile(msg.chat.id).await?.name {
+        bot.send_message(msg.chat.id, format!("З поверненням, {}! Що б ти хотів зробити?", name))
+            .reply_markup(game_choice_keyboard())
+            .await?;
+        dialogue.update(State::RecieveGameChoice).await?;
+        return Ok(());
+    }
+
     bot.send_message(msg.chat.id, GREETING_TEXT).await?;
 
     dialogue.update(State::ReceiveFullName).await?;
@@ -225,9 +1081,34 @@ async fn start(bot: Bot, dialogue: QuizDialogue, msg: Message) -> HandlerResult
 const STRESSED_WORDS_GAME: &str = "Почати тест на наголос";
 const PARTS_OF_SPEECH_GAME: &str = "Почати тест на частини мови";
 const DECLENSION_GAME: &str = "Почати тест на відмінювання";
-async fn receive_full_name(bot: Bot, dialogue: QuizDialogue, msg: Message) -> HandlerResult {
-    match msg.text() {
+const NOUN_FORMS_STRESS_GAME: &str = "Почати тест на наголос у формах іменників";
+const MIXED_GAME: &str = "Почати змішаний тест";
+const HOMEWORK_BUTTON: &str = "Домашні завдання";
+
+fn game_choice_keyboard() -> KeyboardMarkup {
+    KeyboardMarkup::new(vec![vec![
+        KeyboardButton::new(STRESSED_WORDS_GAME),
+        KeyboardButton::new(PARTS_OF_SPEECH_GAME),
+    ], vec![
+        KeyboardButton::new(DECLENSION_GAME),
+        KeyboardButton::new(NOUN_FORMS_STRESS_GAME),
+    ], vec![
+        KeyboardButton::new(MIXED_GAME),
+        KeyboardButton::new(HOMEWORK_BUTTON),
+    ]])
+}
+
+// The preferred amount of questions from the profile goes first, then the ones of the config
+fn amount_keyboard(preferred: Option<usize>, quiz_sizes: &[usize]) -> KeyboardMarkup {
+    let mut sizes = preferred.into_iter().collect::<Vec<_>>();
+    sizes.extend(quiz_sizes.iter().copied().filter(|size| Some(*size) != preferred));
+    KeyboardMarkup::new(sizes.into_iter().map(|size| vec![KeyboardButton::new(size.to_string())]))
+}
+
+async fn receive_full_name(bot: Bot, dialogue: QuizDialogue, msg: Message, db: Arc<Database>) -> HandlerResult {
+    match msg.text().map(str::trim).filter(|name| !name.is_empty()) {
         Some(full_name) => {
+            db.set_name(msg.chat.id, full_name).await?;
             bot.send_message(
                 msg.chat.id,
                 format!("Приємно познайомитися, {}!", full_name),
@@ -241,62 +1122,31 @@ async fn receive_full_name(bot: Bot, dialogue: QuizDialogue, msg: Message) -> Ha
         }
     }
 
-    let keyboard = KeyboardMarkup::new(vec![vec![
-        KeyboardButton::new(STRESSED_WORDS_GAME),
-        KeyboardButton::new(PARTS_OF_SPEECH_GAME),
-    ], vec![KeyboardButton::new(DECLENSION_GAME),]]);
+    let keyboard = game_choice_keyboard();
 
     bot.send_message(msg.chat.id, "Що б ти хотів зробити?")
         .reply_markup(keyboard)
         .await?;
 
     dialogue.update(State::RecieveGameChoice).await?;
-    return Ok(());
-}
-
-async fn receive_game_choice(bot: Bot, dialogue: QuizDialogue, msg: Message) -> HandlerResult {
-    match msg.text() {
-        Some(STRESSED_WORDS_GAME) => {
-            let keyboard = KeyboardMarkup::new(vec![
-                vec![KeyboardButton::new("5")],
-                vec![KeyboardButton::new("10")],
-                vec![KeyboardButton::new("15")],
-            ]);
-            bot.send_message(msg.chat.id, "Обери кількість питань")
-                .reply_markup(keyboard)
-                .await?;
-            dialogue
-                .update(State::StressedWordsQuizRecieveAmountOfQuestions)
-                .await?;
-            return Ok(());
-        }
-        // TODO: Implement the parts of speech game
-        Some(PARTS_OF_SPEECH_GAME) => {
-            let keyboard = KeyboardMarkup::new(vec![
-                vec![KeyboardButton::new("5")],
-                vec![KeyboardButton::new("10")],
-                vec![KeyboardButton::new("15")],
-            ]);
-            bot.send_message(msg.chat.id, "Обери кількість питань")
-                .reply_markup(keyboard)
-                .await?;
-            dialogue
-                .update(State::PartsOfSpeechRecieveAmountOfQuestions)
-                .await?;
-            return Ok(());
-        }
-        Some(DECLENSION_GAME) => {
-            let keyboard = KeyboardMarkup::new(vec![
-                vec![KeyboardButton::new("5")],
-                vec![KeyboardButton::new("10")],
-                vec![KeyboardButton::new("15")],
-            ]);
-            bot.send_message(msg.chat.id, "Обери кількість питань")
-                .reply_markup(keyboard)
-                .await?;
-            dialogue
-                .update(State::DeclensionsRecieveAmountOfQuestions)
-                .await?;
+    Ok(())
+}
+
+async fn receive_game_choice(
+    bot: Bot,
+    dialogue: QuizDialogue,
+    msg: Message,
+    db: Arc<Database>,
+    config: Arc<Config>,
+) -> HandlerResult {
+    let kind = match msg.text() {
+        Some(STRESSED_WORDS_GAME) => Some(QuizKind::Stress),
+        Some(PARTS_OF_SPEECH_GAME) => Some(QuizKind::PartsOfSpeech),
+        Some(DECLENSION_GAME) => Some(QuizKind::Declensions),
+        Some(NOUN_FORMS_STRESS_GAME) => Some(QuizKind::NounFormsStress),
+        Some(MIXED_GAME) => None,
+        Some(HOMEWORK_BUTTON) => {
+            classes::send_pending_assignments(&bot, msg.chat.id, &db).await?;
             return Ok(());
         }
         _ => {
@@ -304,385 +1154,529 @@ async fn receive_game_choice(bot: Bot, dialogue: QuizDialogue, msg: Message) ->
                 .await?;
             return Ok(());
         }
-    }
+    };
+
+    let quiz_size = db.get_profile(msg.chat.id).await?.quiz_size;
+    bot.send_message(msg.chat.id, "Обери кількість питань")
+        .reply_markup(amount_keyboard(quiz_size, &config.quiz.sizes))
+        .await?;
+    dialogue.update(State::RecieveAmountOfQuestions { kind }).await?;
+    Ok(())
 }
 
-async fn receive_amount_of_questions(
-    dictionary: Arc<quiz::stress::StressWords>,
-    bot: Bot,
-    dialogue: QuizDialogue,
-    msg: Message,
-) -> HandlerResult {
-    if let None = msg.text() {
+/// Parses the amount of questions the user has chosen, asks again if it's not a valid one
+async fn receive_amount(bot: &Bot, msg: &Message) -> Result<Option<usize>, teloxide::RequestError> {
+    let Some(Ok(amount)) = msg.text().map(|text| text.parse::<usize>()) else {
         bot.send_message(msg.chat.id, "Будь ласка, введіть число")
             .await?;
-        return Ok(());
-    }
-    if let Err(_) = msg.text().unwrap().parse::<usize>() {
-        bot.send_message(msg.chat.id, "Будь ласка, введіть число")
-            .await?;
-        return Ok(());
-    }
-
-    // It is safe to unwrap here because we've already checked that the input is a number
-    let amount: usize = msg.text().unwrap().parse().unwrap();
+        return Ok(None);
+    };
     if amount == 0 {
         bot.send_message(msg.chat.id, "Кількість питань не може бути 0")
             .await?;
-        return Ok(());
+        return Ok(None);
     }
-
-    let quiz = quiz::Quiz::new(
-        (0..amount)
-            .map(|_| dictionary.get_random_word().generate_question())
-            .collect(),
-    );
-
-    bot.send_message(msg.chat.id, "Чудово! Почнемо тест!")
-        .reply_markup(KeyboardMarkup::new(vec![vec![KeyboardButton::new("Вйо!")]]))
-        .await?;
-
-    dialogue
-        .update(State::StressedWordsQuiz {
-            quiz,
-            question_number: 0,
-            score: 0,
-        })
-        .await?;
-    Ok(())
+    if amount > MAX_QUIZ_SIZE {
+        bot.send_message(msg.chat.id, format!("Забагато питань, можна не більше {}", MAX_QUIZ_SIZE))
+            .await?;
+        return Ok(None);
+    }
+    Ok(Some(amount))
 }
 
-async fn stressed_quiz(
+/// Starts the quiz of the kind, or the mixed one if there is no kind
+async fn receive_amount_of_questions(
+    kind: Option<QuizKind>,
+    datasets: Datasets,
     ai_helper: Arc<QuizHelper>,
     bot: Bot,
     dialogue: QuizDialogue,
-    (quiz, question_number, score): (quiz::Quiz, usize, usize),
     msg: Message,
+    db: Arc<Database>,
 ) -> HandlerResult {
-    let mut current_score = score;
-    if question_number != 0 {
-        let answer = msg.text().unwrap();
-        let question = &quiz.questions[question_number - 1];
-        let correct_answer = question.answers.iter().find(|a| a.is_correct).unwrap();
-        if answer == correct_answer.text {
-            bot.send_message(msg.chat.id, "Правильно!").await?;
-            current_score += 1;
-        } else {
-            let ai_reply = ai_helper
-                .generate_reply_to_wrong_stress_answer(question.clone())
-                .await?;
+    let Some(amount) = receive_amount(&bot, &msg).await? else {
+        return Ok(());
+    };
 
-            bot.send_message(msg.chat.id, ai_reply)
-                .parse_mode(ParseMode::Html)
-                .await?;
+    let profile = db.get_profile(msg.chat.id).await?;
+    let mastery = if profile.adaptive { Some(db.mastery(msg.chat.id).await?) } else { None };
+    let (questions, text) = match (kind, mastery) {
+        (None, mastery) => (
+            datasets.generate_mixed_quiz(&profile.quiz_mix, amount, mastery.as_ref(), &mut rand::thread_rng()),
+            format!(
+                "Чудово! Почнемо змішаний тест!\nУ ньому: {}\nЗмінити пропорції можна командою /mix",
+                profile.quiz_mix.describe()
+            ),
+        ),
+        // The quiz picked for the learner can't be shared, the random one can be with its code
+        (Some(kind), Some(mastery)) => (
+            datasets.generate_adaptive_questions(kind, amount, &mastery, &mut rand::thread_rng()),
+            "Чудово! Почнемо тест!\nПитання підібрано під твій рівень. Вимкнути підбір можна в /profile".to_string(),
+        ),
+        (Some(kind), None) => {
+            let code = QuizCode::random(kind, amount, None);
+            (
+                datasets.generate_quiz(&code),
+                format!(
+                    "Чудово! Почнемо тест!\nКод цього тесту: <code>{}</code>\nПоділись ним, і друзі зможуть пройти такий самий тест командою /quiz",
+                    code
+                ),
+            )
         }
-    }
-
-    if question_number >= quiz.questions.len() {
-        let keyboard = KeyboardMarkup::new(vec![vec![
-            KeyboardButton::new(STRESSED_WORDS_GAME),
-            KeyboardButton::new(PARTS_OF_SPEECH_GAME),
-        ], vec![KeyboardButton::new(DECLENSION_GAME),]]);
-        let quiz_score = format!(
-            "Квіз закінчився! Ти відповів правильно на {} з {} питань\nЩо б ти хотів зробити далі?",
-            current_score,
-            quiz.questions.len()
-        );
-        bot.send_message(msg.chat.id, quiz_score.as_str())
-            .reply_markup(keyboard)
+    };
+    let Some(questions) = questions else {
+        bot.send_message(msg.chat.id, "На жаль, цей тест зараз недоступний")
+            .reply_markup(game_choice_keyboard())
             .await?;
-
         dialogue.update(State::RecieveGameChoice).await?;
         return Ok(());
-    }
-
-    let question = &quiz.questions[question_number];
-
-    let text_question_from_answers = question
-        .answers
-        .iter()
-        .map(|a| a.text.clone())
-        .collect::<Vec<_>>()
-        .join(" чи ");
-
-    let ai_example = ai_helper
-        .generate_example_for_stress_question(question.clone())
-        .await?;
-
-    let question_text = format!(
-        "Питання №{}: \n{}?\n\nПриклад:\n{}",
-        question_number + 1,
-        text_question_from_answers,
-        ai_example
-    );
-
-    let mut answers = Vec::new();
-    for answer in &question.answers {
-        answers.push(KeyboardButton::new(answer.text.clone()));
-    }
+    };
 
-    bot
-        .send_message(msg.chat.id, question_text)
+    bot.send_message(msg.chat.id, text)
         .parse_mode(ParseMode::Html)
-        .reply_markup(KeyboardMarkup::new(vec![answers]))
+        .reply_markup(KeyboardRemove::new())
         .await?;
 
-    dialogue
-        .update(State::StressedWordsQuiz {
-            quiz,
-            question_number: question_number + 1,
-            score: current_score,
-        })
-        .await?;
+    let state = send_first_question(
+        &bot,
+        msg.chat.id,
+        &ai_helper,
+        &datasets.declension,
+        &profile,
+        quiz::Quiz::new(questions),
+    )
+    .await?;
+    schedule_question_timeout(&bot, &dialogue, &ai_helper, &datasets.declension, &db, &state);
+    dialogue.update(state).await?;
     Ok(())
 }
 
-async fn receive_amount_of_questions_parts_of_speech(
-    collu_doc: Arc<PartsSentences>,
+async fn start_homework(
+    datasets: Datasets,
+    ai_helper: Arc<QuizHelper>,
     bot: Bot,
     dialogue: QuizDialogue,
-    msg: Message,
+    q: CallbackQuery,
+    db: Arc<Database>,
 ) -> HandlerResult {
-    if let None = msg.text() {
-        bot.send_message(msg.chat.id, "Будь ласка, введіть число")
+    let Some(message) = q.message else {
+        bot.answer_callback_query(q.id)
+            .text("Ця кнопка більше не працює, спробуй /homework ще раз")
             .await?;
         return Ok(());
-    }
-    if let Err(_) = msg.text().unwrap().parse::<usize>() {
-        bot.send_message(msg.chat.id, "Будь ласка, введіть число")
+    };
+    let chat_id = message.chat.id;
+
+    // Only the student's own homework which isn't done yet can be started
+    let assignment_id = q
+        .data
+        .as_deref()
+        .and_then(|d| d.strip_prefix(HOMEWORK_CALLBACK_PREFIX))
+        .and_then(|id| id.parse::<i64>().ok());
+    let assignment = match assignment_id {
+        Some(id) => db.pending_assignments(chat_id).await?.into_iter().find(|a| a.id == id),
+        None => None,
+    };
+    let Some(assignment) = assignment else {
+        bot.answer_callback_query(q.id)
+            .text("Це завдання вже виконане або його термін минув")
             .await?;
         return Ok(());
-    }
+    };
 
-    // It is safe to unwrap here because we've already checked that the input is a number
-    let amount: usize = msg.text().unwrap().parse().unwrap();
-    if amount == 0 {
-        bot.send_message(msg.chat.id, "Кількість питань не може бути 0")
+    if dialogue.get_or_default().await?.is_quiz() {
+        bot.answer_callback_query(q.id)
+            .text("Спершу закінчи тест або скасуй його (/cancel)")
             .await?;
         return Ok(());
     }
 
-    let quiz = quiz::Quiz::new(
-        (0..amount)
-            .map(|_| {
-                let random_sentence = collu_doc.get_random_sentence();
-                random_sentence.generate_question()
-            })
-            .collect(),
-    );
+    let Some(questions) = datasets.generate_quiz(&assignment.quiz_code()) else {
+        bot.answer_callback_query(q.id)
+            .text("На жаль, цей тест зараз недоступний")
+            .await?;
+        return Ok(());
+    };
+    bot.answer_callback_query(q.id).await?;
 
-    bot.send_message(msg.chat.id, "Чудово! Почнемо тест!")
-        .reply_markup(KeyboardMarkup::new(vec![vec![KeyboardButton::new("Вйо!")]]))
-        .await?;
+    bot.send_message(
+        chat_id,
+        format!("Домашнє завдання -- {}. Почнемо!", classes::describe_assignment(&assignment)),
+    )
+    .reply_markup(KeyboardRemove::new())
+    .await?;
+
+    let profile = db.get_profile(chat_id).await?;
+    let quiz = quiz::Quiz::new(questions).for_assignment(assignment.id);
+    let state = send_first_question(&bot, chat_id, &ai_helper, &datasets.declension, &profile, quiz).await?;
+    schedule_question_timeout(&bot, &dialogue, &ai_helper, &datasets.declension, &db, &state);
+    dialogue.update(state).await?;
+    Ok(())
+}
 
-    dialogue
-        .update(State::PartsOfSpeechQuiz {
-            quiz,
-            question_number: 0,
-            score: 0,
-        })
+async fn announce_time_limit(bot: &Bot, chat_id: ChatId, quiz: &quiz::Quiz) -> Result<(), teloxide::RequestError> {
+    if let Some(time_limit) = quiz.time_limit {
+        bot.send_message(
+            chat_id,
+            format!("⏱ Тест на час: на кожне питання -- {} секунд. Вимкнути режим на час можна в /profile", time_limit),
+        )
         .await?;
+    }
     Ok(())
 }
 
-async fn parts_of_speech_quiz(
-    ai_helper: Arc<QuizHelper>,
-    bot: Bot,
-    dialogue: QuizDialogue,
-    (quiz, question_number, score): (quiz::Quiz, usize, usize),
-    msg: Message,
+/// Sends the first question of the quiz and returns the state the quiz goes on in.
+/// The quiz is timed if the user has chosen the timed mode.
+async fn send_first_question(
+    bot: &Bot,
+    chat_id: ChatId,
+    ai_helper: &Arc<QuizHelper>,
+    words: &Arc<Declension>,
+    profile: &Profile,
+    quiz: quiz::Quiz,
+) -> Result<State, Box<dyn std::error::Error + Send + Sync>> {
+    let mut quiz = quiz.with_time_limit(profile.time_limit);
+    announce_time_limit(bot, chat_id, &quiz).await?;
+    send_quiz_question(bot, chat_id, ai_helper, words, profile, &mut quiz, 0).await?;
+    Ok(State::Quiz {
+        quiz,
+        question_number: 0,
+        score: 0,
+    })
+}
+
+/// Tells the user whether the stress is chosen right and explains the stress rule if it isn't
+async fn reply_to_stress_answer(
+    bot: &Bot,
+    chat_id: ChatId,
+    ai_helper: &QuizHelper,
+    profile: &Profile,
+    question: &quiz::Question,
+    answer: &quiz::Answer,
+    is_poll: bool,
 ) -> HandlerResult {
-    let mut current_score = score;
-    if question_number != 0 {
-        let answer = msg.text().unwrap();
-        let question = &quiz.questions[question_number - 1];
-        let correct_answer = question.answers.iter().find(|a| a.is_correct).unwrap();
-        if answer == correct_answer.text {
-            bot.send_message(msg.chat.id, "Правильно!").await?;
-            current_score += 1;
-        } else {
-            // We don't really care about the result here, so we'll just ignore the error if this action is unsuccessful
-            // But it adds to the user's experience if it works!
-            let _ = bot.send_chat_action(msg.chat.id, ChatAction::Typing)
-                .await;
+    if answer.is_correct {
+        // The poll shows whether the answer is correct by itself
+        if !is_poll {
+            bot.send_message(chat_id, "Правильно!").await?;
+        }
+        return Ok(());
+    }
 
-            let ai_reply: String = ai_helper
-                .generate_reply_to_wrong_parts_answer(question.clone(), answer.to_string())
-                // If the AI fails to generate a reply, we'll just tell the user the correct answer
-                // Sometimes it may happen due to timeout or other reasons
-                .await.unwrap_or(format!("Правильна відповідь -- {} Будь уважнішим!", correct_answer.text));
+    let correct_answer = question.answers.iter().find(|a| a.is_correct).unwrap();
+    let explanation = question
+        .explanation
+        .clone()
+        .unwrap_or_else(|| stress_rules::explain_stress(&correct_answer.text));
+    let mut reply = format!(
+        "Неправильно! Правильна відповідь -- <b>{}</b>\n\n{}",
+        correct_answer.text, explanation
+    );
 
-            bot.send_message(msg.chat.id, format!("Неправильно!\n\n{}", ai_reply)).await?;
+    if ai_helper.retell_stress_explanations {
+        let _ = bot.send_chat_action(chat_id, ChatAction::Typing)
+            .await;
+
+        // The AI only retells the offline explanation, so if it fails, the user still gets one
+        match ai_helper
+            .generate_reply_to_wrong_stress_answer(question.clone(), &explanation, profile.personality)
+            .await
+        {
+            Ok(ai_reply) => reply = format!("{}\n\n{}", reply, ai_reply),
+            Err(e) => log::warn!("Failed to generate the reply to the wrong stress answer: {}", e),
         }
     }
 
-    if question_number >= quiz.questions.len() {
-        let keyboard = KeyboardMarkup::new(vec![vec![
-            KeyboardButton::new(STRESSED_WORDS_GAME),
-            KeyboardButton::new(PARTS_OF_SPEECH_GAME),
-        ], vec![KeyboardButton::new(DECLENSION_GAME),]]);
-        let quiz_score = format!(
-            "Квіз закінчився! Ти відповів правильно на {} з {} питань\nЩо б ти хотів зробити далі?",
-            current_score,
-            quiz.questions.len()
-        );
-        bot.send_message(msg.chat.id, quiz_score.as_str())
-            .reply_markup(keyboard)
+    // The poll already shows the offline explanation, so there is only something to add if the AI has retold it
+    if !is_poll || ai_helper.retell_stress_explanations {
+        bot.send_message(chat_id, reply)
+            .parse_mode(ParseMode::Html)
             .await?;
-
-        dialogue.update(State::RecieveGameChoice).await?;
-        return Ok(());
     }
+    Ok(())
+}
 
+async fn send_stress_question(
+    bot: &Bot,
+    chat_id: ChatId,
+    ai_helper: &Arc<QuizHelper>,
+    words: &Arc<Declension>,
+    profile: &Profile,
+    quiz: &mut quiz::Quiz,
+    question_number: usize,
+) -> HandlerResult {
+    let personality = profile.personality;
     let question = &quiz.questions[question_number];
-    let answers = &question
-        .answers
-        .iter()
-        .map(|a| a.text.clone())
-        .collect::<Vec<_>>();
 
-    bot.send_message(msg.chat.id, question.clone().text)
-        .parse_mode(ParseMode::Html)
-        .reply_markup(KeyboardMarkup::new(
-            answers
-                .iter()
-                .map(|a| vec![KeyboardButton::new(a.clone())])
-                .collect::<Vec<_>>(),
-        ))
-        .await?;
+    let question_text = match stress_question_example(ai_helper, words, question, personality).await {
+        Some(ai_example) => format!(
+            "Питання №{}: \n{}\n\nПриклад:\n{}",
+            question_number + 1,
+            question.text,
+            ai_example
+        ),
+        // The example is just a nice addition, so the quiz goes on without it
+        None => format!("Питання №{}: \n{}", question_number + 1, question.text),
+    };
 
-    dialogue
-        .update(State::PartsOfSpeechQuiz {
-            quiz,
-            question_number: question_number + 1,
-            score: current_score,
-        })
-        .await?;
+    send_question(bot, chat_id, quiz, question_number, question_text, profile.presentation).await?;
+
+    // The example for the next question is generated while the user answers this one,
+    // so it's already in the cache when the user gets to it; only the stress questions have the examples
+    let next_question = quiz.questions.get(question_number + 1).filter(|q| is_stress_question(q)).cloned();
+    if let Some(next_question) = next_question {
+        let (ai_helper, words) = (ai_helper.clone(), words.clone());
+        tokio::spawn(async move {
+            stress_question_example(&ai_helper, &words, &next_question, personality).await;
+        });
+    }
+    Ok(())
+}
+
+async fn stress_question_example(
+    ai_helper: &QuizHelper,
+    words: &Declension,
+    question: &quiz::Question,
+    personality: Personality,
+) -> Option<String> {
+    let correct_answer = question.answers.iter().find(|a| a.is_correct)?;
+    let word_forms = words.get_inflections(&stress::remove_stress(&correct_answer.text));
+
+    match ai_helper
+        .generate_example_for_stress_question(question.clone(), &word_forms, personality)
+        .await
+    {
+        Ok(ai_example) => ai_example,
+        Err(e) => {
+            log::warn!("Failed to generate the example for the stress question: {}", e);
+            None
+        }
+    }
+}
+
+/// Tells the user whether the answer is right and has the AI explain the mistake if it isn't
+/// (the parts of speech and the declensions questions)
+async fn reply_to_answer(
+    bot: &Bot,
+    chat_id: ChatId,
+    ai_helper: &QuizHelper,
+    profile: &Profile,
+    question: &quiz::Question,
+    answer: &quiz::Answer,
+    is_poll: bool,
+) -> HandlerResult {
+    if answer.is_correct {
+        // The poll shows whether the answer is correct by itself
+        if !is_poll {
+            bot.send_message(chat_id, "Правильно!").await?;
+        }
+        return Ok(());
+    }
+
+    // We don't really care about the result here, so we'll just ignore the error if this action is unsuccessful
+    // But it adds to the user's experience if it works!
+    let _ = bot.send_chat_action(chat_id, ChatAction::Typing)
+        .await;
+
+    let correct_answer = question.answers.iter().find(|a| a.is_correct).unwrap();
+    let ai_reply: String = ai_helper
+        .generate_reply_to_wrong_parts_answer(question.clone(), answer.text.clone(), profile.personality)
+        // If the AI fails to generate a reply, we'll just tell the user the correct answer
+        // Sometimes it may happen due to timeout or other reasons
+        .await.unwrap_or(format!(
+            "Правильна відповідь -- {} Будь уважнішим!\n\n{}",
+            correct_answer.text,
+            question.explanation.clone().unwrap_or_default()
+        ));
+
+    bot.send_message(chat_id, format!("Неправильно!\n\n{}", ai_reply)).await?;
     Ok(())
 }
 
-async fn declensions_quiz(
+/// The questions may be of different kinds, each one is explained and asked the way its kind is
+async fn receive_quiz_answer(
     ai_helper: Arc<QuizHelper>,
+    words: Arc<Declension>,
     bot: Bot,
     dialogue: QuizDialogue,
-    (quiz, question_number, score): (quiz::Quiz, usize, usize),
-    msg: Message,
+    (mut quiz, question_number, score): (quiz::Quiz, usize, usize),
+    update: AnswerUpdate,
+    db: Arc<Database>,
 ) -> HandlerResult {
-    let mut current_score = score;
-    if question_number != 0 {
-        let answer = msg.text().unwrap();
-        let question = &quiz.questions[question_number - 1];
-        let correct_answer = question.answers.iter().find(|a| a.is_correct).unwrap();
-        if answer == correct_answer.text {
-            bot.send_message(msg.chat.id, "Правильно!").await?;
-            current_score += 1;
-        } else {
-            // We don't really care about the result here, so we'll just ignore the error if this action is unsuccessful
-            // But it adds to the user's experience if it works!
-            let _ = bot.send_chat_action(msg.chat.id, ChatAction::Typing)
-                .await;
-
-            let ai_reply: String = ai_helper
-                .generate_reply_to_wrong_parts_answer(question.clone(), answer.to_string())
-                // If the AI fails to generate a reply, we'll just tell the user the correct answer
-                // Sometimes it may happen due to timeout or other reasons
-                .await.unwrap_or(format!("Правильна відповідь -- {} Будь уважнішим!", correct_answer.text));
+    let _lock = chat_lock::lock_chat(dialogue.chat_id()).await;
+    let Some((chat_id, answer)) = receive_timely_answer(&bot, &dialogue, &update, &quiz, question_number).await? else {
+        return Ok(());
+    };
+    quiz.record_response_time();
+    record_answer(&db, chat_id, &mut quiz, question_number, Some(&answer)).await?;
+    let profile = db.get_profile(chat_id).await?;
 
-            bot.send_message(msg.chat.id, format!("Неправильно!\n\n{}", ai_reply)).await?;
-        }
+    let current_score = score + usize::from(answer.is_correct);
+    let question = &quiz.questions[question_number];
+    if is_stress_question(question) {
+        reply_to_stress_answer(&bot, chat_id, &ai_helper, &profile, question, &answer, update.is_poll()).await?;
+    } else {
+        reply_to_answer(&bot, chat_id, &ai_helper, &profile, question, &answer, update.is_poll()).await?;
     }
 
+    let question_number = question_number + 1;
     if question_number >= quiz.questions.len() {
-        let keyboard = KeyboardMarkup::new(vec![vec![
-            KeyboardButton::new(STRESSED_WORDS_GAME),
-            KeyboardButton::new(PARTS_OF_SPEECH_GAME),
-        ], vec![KeyboardButton::new(DECLENSION_GAME),]]);
-
-        let quiz_score = format!(
-            "Квіз закінчився! Ти відповів правильно на {} з {} питань\nЩо б ти хотів зробити далі?",
-            current_score,
-            quiz.questions.len()
-        );
-        bot.send_message(msg.chat.id, quiz_score.as_str())
-            .reply_markup(keyboard)
-            .await?;
-
-        dialogue.update(State::RecieveGameChoice).await?;
-        return Ok(());
+        return finish_quiz(&bot, &dialogue, chat_id, &quiz, current_score, &profile, &db).await;
     }
 
-    let question = &quiz.questions[question_number];
-    let answers = &question
-        .answers
-        .iter()
-        .map(|a| a.text.clone())
-        .collect::<Vec<_>>();
+    send_quiz_question(&bot, chat_id, &ai_helper, &words, &profile, &mut quiz, question_number).await?;
 
-    bot.send_message(msg.chat.id, question.clone().text)
-        .parse_mode(ParseMode::Html)
-        .reply_markup(KeyboardMarkup::new(
-            answers
-                .iter()
-                .map(|a| vec![KeyboardButton::new(a.clone())])
-                .collect::<Vec<_>>(),
-        ))
-        .await?;
+    let state = State::Quiz {
+        quiz,
+        question_number,
+        score: current_score,
+    };
+    schedule_question_timeout(&bot, &dialogue, &ai_helper, &words, &db, &state);
+    dialogue.update(state).await?;
+    Ok(())
+}
 
-    dialogue
-        .update(State::PartsOfSpeechQuiz {
-            quiz,
-            question_number: question_number + 1,
-            score: current_score,
-        })
-        .await?;
+fn is_stress_question(question: &quiz::Question) -> bool {
+    matches!(question.kind, Some(QuizKind::Stress | QuizKind::NounFormsStress))
+}
+
+async fn send_quiz_question(
+    bot: &Bot,
+    chat_id: ChatId,
+    ai_helper: &Arc<QuizHelper>,
+    words: &Arc<Declension>,
+    profile: &Profile,
+    quiz: &mut quiz::Quiz,
+    question_number: usize,
+) -> HandlerResult {
+    if is_stress_question(&quiz.questions[question_number]) {
+        return send_stress_question(bot, chat_id, ai_helper, words, profile, quiz, question_number).await;
+    }
+    let text = quiz.questions[question_number].text.clone();
+    send_question(bot, chat_id, quiz, question_number, text, profile.presentation).await?;
     Ok(())
 }
 
-async fn receive_amount_of_questions_declensions(
-    words: Arc<Declension>,
-    bot: Bot,
-    dialogue: QuizDialogue,
-    msg: Message,
+async fn finish_quiz(
+    bot: &Bot,
+    dialogue: &QuizDialogue,
+    chat_id: ChatId,
+    quiz: &quiz::Quiz,
+    score: usize,
+    profile: &Profile,
+    db: &Database,
 ) -> HandlerResult {
-    if let None = msg.text() {
-        bot.send_message(msg.chat.id, "Будь ласка, введіть число")
-            .await?;
-        return Ok(());
+    let addressee = profile.name.as_ref().map(|name| format!("{}, ", name)).unwrap_or_default();
+    let mut quiz_score = format!(
+        "Квіз закінчився! {}ти відповів правильно на {} з {} питань",
+        addressee,
+        score,
+        quiz.questions.len()
+    );
+    // Only the mixed quiz and the redone mistakes may have the questions of more than one kind
+    let scores_by_kind = quiz.scores_by_kind();
+    if scores_by_kind.len() > 1 {
+        for (kind, score, total) in scores_by_kind {
+            quiz_score.push_str(&format!("\n• {}: {} з {}", kind.name(), score, total));
+        }
     }
-    if let Err(_) = msg.text().unwrap().parse::<usize>() {
-        bot.send_message(msg.chat.id, "Будь ласка, введіть число")
+    if quiz.time_limit.is_some() {
+        match quiz.average_response_time() {
+            Some(average) => {
+                quiz_score.push_str(&format!("\nСередній час відповіді: {:.1} с", average.as_secs_f64()))
+            }
+            None => quiz_score.push_str("\nНа жодне питання не вдалося відповісти вчасно"),
+        }
+    }
+    if let Some(day) = &quiz.daily {
+        let streak = daily::finish_daily(db, chat_id, day, score, quiz.questions.len()).await?;
+        quiz_score.push_str(&format!("\n{}", streak));
+    }
+    if let Some(assignment_id) = quiz.assignment_id {
+        db.save_assignment_result(assignment_id, chat_id, score, quiz.questions.len())
             .await?;
-        return Ok(());
+        quiz_score.push_str("\nРезультат надіслано вчителю");
     }
+    let kind = quiz.kind_key();
+    db.log_quiz(chat_id, kind, score, quiz.questions.len()).await?;
+    metrics::quiz_finished(kind);
+    let earned = achievements::evaluate(db, chat_id, quiz, score).await?;
+    quiz_score.push_str("\nЩо б ти хотів зробити далі?");
+    bot.send_message(chat_id, quiz_score.as_str())
+        .reply_markup(game_choice_keyboard())
+        .await?;
+    achievements::announce(bot, chat_id, &earned).await?;
+    mistakes::send_review(bot, chat_id, quiz, db).await?;
 
-    // It is safe to unwrap here because we've already checked that the input is a number
-    let amount: usize = msg.text().unwrap().parse().unwrap();
-    if amount == 0 {
-        bot.send_message(msg.chat.id, "Кількість питань не може бути 0")
-            .await?;
-        return Ok(());
+    dialogue.update(State::RecieveGameChoice).await?;
+    Ok(())
+}
+
+async fn quiz_expects_button(bot: Bot, msg: Message) -> HandlerResult {
+    bot.send_message(
+        msg.chat.id,
+        "Будь ласка, обери відповідь під питанням. Пропустити питання -- /skip, скасувати тест -- /cancel",
+    )
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // As the baseline saved them
+    const BASELINE_QUIZ: &str = r#"{"questions": [{"text": "Питання", "answers": [{"text": "а", "is_correct": true}]}], "current_question": 0, "score": 0}"#;
+
+    fn restore(saved: &str) -> State {
+        serde_json::from_str(saved).unwrap()
     }
 
-    let mut questions: Vec<quiz::Question> = Vec::new();
-    while questions.len() < amount {
-        let random_noun = words.get_random_noun();
-        if let Ok(q) = random_noun.generate_question_out_of_noun() {
-            questions.push(q);
+    #[test]
+    fn saved_quizzes_of_each_kind_are_restored() {
+        let kinds = [
+            ("StressedWordsQuiz", Some(QuizKind::Stress)),
+            ("PartsOfSpeechQuiz", Some(QuizKind::PartsOfSpeech)),
+            ("DeclensionsQuiz", Some(QuizKind::Declensions)),
+            ("MixedQuiz", None),
+            ("Quiz", None),
+        ];
+        for (name, kind) in kinds {
+            let saved = format!(r#"{{"{}": {{"quiz": {}, "question_number": 2, "score": 1}}}}"#, name, BASELINE_QUIZ);
+            let State::Quiz { quiz, question_number: 2, score: 1 } = restore(&saved) else {
+                panic!("{} isn't restored", name);
+            };
+            assert_eq!(quiz.questions[0].kind, kind, "{}", name);
         }
     }
 
-    let quiz = quiz::Quiz::new(questions);
-
-    bot.send_message(msg.chat.id, "Чудово! Почнемо тест!")
-        .reply_markup(KeyboardMarkup::new(vec![vec![KeyboardButton::new("Вйо!")]]))
-        .await?;
+    #[test]
+    fn saved_amounts_of_questions_of_each_kind_are_restored() {
+        let kinds = [
+            ("StressedWordsQuizRecieveAmountOfQuestions", Some(QuizKind::Stress)),
+            ("PartsOfSpeechRecieveAmountOfQuestions", Some(QuizKind::PartsOfSpeech)),
+            ("DeclensionsRecieveAmountOfQuestions", Some(QuizKind::Declensions)),
+            ("NounFormsStressRecieveAmountOfQuestions", Some(QuizKind::NounFormsStress)),
+            ("MixedQuizRecieveAmountOfQuestions", None),
+        ];
+        for (name, kind) in kinds {
+            let state = restore(&format!(r#""{}""#, name));
+            assert!(
+                matches!(state, State::RecieveAmountOfQuestions { kind: restored } if restored == kind),
+                "{}",
+                name
+            );
+        }
+        assert!(matches!(restore(r#""Start""#), State::Start));
+        assert!(matches!(restore(r#""RecieveGameChoice""#), State::RecieveGameChoice));
+    }
 
-    dialogue
-        .update(State::DeclensionsQuiz {
-            quiz,
-            question_number: 0,
-            score: 0,
-        })
-        .await?;
-    Ok(())
-}
\ No newline at end of file
+    #[test]
+    fn amount_of_questions_state_round_trips() {
+        for kind in [Some(QuizKind::Declensions), None] {
+            let saved = serde_json::to_string(&State::RecieveAmountOfQuestions { kind }).unwrap();
+            let state: State = serde_json::from_str(&saved).unwrap();
+            assert!(matches!(state, State::RecieveAmountOfQuestions { kind: restored } if restored == kind));
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, LazyLock};
+use std::time::Instant;
+
+use axum::{extract::State, http::StatusCode, routing::get, Router};
+use prometheus::{
+    register_histogram, register_histogram_vec, register_int_counter, register_int_counter_vec,
+    register_int_gauge_vec, Encoder, Histogram, HistogramVec, IntCounter, IntCounterVec, IntGaugeVec, TextEncoder,
+};
+use teloxide::{dispatching::dialogue::Storage, types::ChatId};
+
+use crate::db::Database;
+
+// The quiz kinds are the keys of `QuizKind`, or "mixed" for the quizzes of several kinds, see `Quiz::kind_key`
+static QUIZZES_STARTED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!("quizzes_started_total", "Quizzes started, by kind", &["kind"]).unwrap()
+});
+static QUIZZES_FINISHED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!("quizzes_finished_total", "Quizzes finished, by kind", &["kind"]).unwrap()
+});
+static ANSWERS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "answers_total",
+        "Answers to the questions, by kind and whether they are correct",
+        &["kind", "result"]
+    )
+    .unwrap()
+});
+static AI_REQUEST_DURATION: LazyLock<Histogram> = LazyLock::new(|| {
+    register_histogram!(
+        "ai_request_duration_seconds",
+        "How long the requests to the AI take",
+        vec![0.25, 0.5, 1.0, 2.0, 4.0, 8.0, 15.0, 30.0]
+    )
+    .unwrap()
+});
+static AI_REQUEST_ERRORS: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!("ai_request_errors_total", "Requests to the AI which have failed").unwrap()
+});
+static STORAGE_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "dialogue_storage_duration_seconds",
+        "How long the dialogue storage operations take",
+        &["operation"],
+        vec![0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 1.0]
+    )
+    .unwrap()
+});
+// Updated from the database on every scrape
+static ACTIVE_USERS: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!("active_users", "Users who have written to the bot, by period", &["period"]).unwrap()
+});
+
+// The periods of the active users gauge, in days
+const ACTIVE_USER_PERIODS: [(&str, u32); 3] = [("day", 1), ("week", 7), ("month", 30)];
+
+pub fn quiz_started(kind: &str) {
+    QUIZZES_STARTED.with_label_values(&[kind]).inc();
+}
+
+pub fn quiz_finished(kind: &str) {
+    QUIZZES_FINISHED.with_label_values(&[kind]).inc();
+}
+
+pub fn answer(kind: &str, correct: bool) {
+    let result = if correct { "correct" } else { "wrong" };
+    ANSWERS.with_label_values(&[kind, result]).inc();
+}
+
+pub fn ai_request(started: Instant, failed: bool) {
+    AI_REQUEST_DURATION.observe(started.elapsed().as_secs_f64());
+    if failed {
+        AI_REQUEST_ERRORS.inc();
+    }
+}
+
+/// Serves `/metrics` in the Prometheus text format, in the background
+pub fn serve(address: SocketAddr, db: Arc<Database>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let server = axum::Server::try_bind(&address)?;
+    let router = Router::new().route("/metrics", get(render)).with_state(db);
+    tokio::spawn(async move {
+        if let Err(e) = server.serve(router.into_make_service()).await {
+            log::error!("The metrics server failed: {}", e);
+        }
+    });
+    Ok(())
+}
+
+async fn render(State(db): State<Arc<Database>>) -> (StatusCode, String) {
+    for (period, days) in ACTIVE_USER_PERIODS {
+        match db.active_user_count(days).await {
+            Ok(count) => ACTIVE_USERS.with_label_values(&[period]).set(count),
+            Err(e) => log::error!("Failed to count the active users: {}", e),
+        }
+    }
+
+    let mut buffer = Vec::new();
+    match TextEncoder::new().encode(&prometheus::gather(), &mut buffer) {
+        Ok(()) => (StatusCode::OK, String::from_utf8_lossy(&buffer).into_owned()),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+}
+
+type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+/// The dialogue storage which measures how long the operations of the wrapped one take
+pub struct TimedStorage<S>(pub Arc<S>);
+
+impl<S, D> Storage<D> for TimedStorage<S>
+where
+    S: Storage<D> + Send + Sync + 'static,
+    D: Send + 'static,
+{
+    type Error = S::Error;
+
+    fn remove_dialogue(self: Arc<Self>, chat_id: ChatId) -> BoxFuture<Result<(), Self::Error>> {
+        timed("remove", self.0.clone().remove_dialogue(chat_id))
+    }
+
+    fn update_dialogue(self: Arc<Self>, chat_id: ChatId, dialogue: D) -> BoxFuture<Result<(), Self::Error>> {
+        timed("update", self.0.clone().update_dialogue(chat_id, dialogue))
+    }
+
+    fn get_dialogue(self: Arc<Self>, chat_id: ChatId) -> BoxFuture<Result<Option<D>, Self::Error>> {
+        timed("get", self.0.clone().get_dialogue(chat_id))
+    }
+}
+
+fn timed<T: 'static>(operation: &'static str, future: BoxFuture<T>) -> BoxFuture<T> {
+    Box::pin(async move {
+        let started = Instant::now();
+        let result = future.await;
+        STORAGE_DURATION
+            .with_label_values(&[operation])
+            .observe(started.elapsed().as_secs_f64());
+        result
+    })
+}
@@ -0,0 +1,120 @@
+use std::sync::Arc;
+
+use teloxide::{
+    prelude::*,
+    types::{InlineKeyboardButton, InlineKeyboardMarkup, KeyboardRemove, ParseMode},
+};
+
+use crate::db::Database;
+use crate::quiz::{self, ai_helper::QuizHelper, declension::Declension};
+use crate::{schedule_question_timeout, send_first_question, HandlerResult, QuizDialogue};
+
+pub const REDO_CALLBACK_PREFIX: &str = "redo:";
+
+// Telegram doesn't send the messages longer than 4096 characters, the long reviews are split
+const MAX_REVIEW_MESSAGE_LENGTH: usize = 4000;
+
+/// Lists the mistakes of the finished quiz with the correct answers and offers to redo them right away
+pub async fn send_review(bot: &Bot, chat_id: ChatId, quiz: &quiz::Quiz, db: &Database) -> HandlerResult {
+    if quiz.mistakes.is_empty() {
+        db.clear_mistakes(chat_id).await?;
+        return Ok(());
+    }
+
+    let mut messages = vec!["Твої помилки:".to_string()];
+    for (idx, mistake) in quiz.mistakes.iter().enumerate() {
+        let question = &quiz.questions[mistake.question_number];
+        let correct_answer = question
+            .answers
+            .iter()
+            .find(|a| a.is_correct)
+            .map(|a| a.text.as_str())
+            .unwrap_or_default();
+        let item = format!(
+            "{}. {}\nТвоя відповідь: {}\nПравильна відповідь: <b>{}</b>",
+            idx + 1,
+            question.text,
+            mistake.answer.as_deref().unwrap_or("немає"),
+            correct_answer
+        );
+
+        let last = messages.last_mut().unwrap();
+        if last.chars().count() + item.chars().count() + 2 <= MAX_REVIEW_MESSAGE_LENGTH {
+            last.push_str("\n\n");
+            last.push_str(&item);
+        } else {
+            messages.push(item);
+        }
+    }
+
+    let questions = quiz
+        .mistakes
+        .iter()
+        .map(|mistake| quiz.questions[mistake.question_number].clone())
+        .collect::<Vec<_>>();
+    db.save_mistakes(chat_id, quiz.id, questions).await?;
+
+    let keyboard = InlineKeyboardMarkup::new(vec![vec![InlineKeyboardButton::callback(
+        "Повторити помилки",
+        format!("{}{}", REDO_CALLBACK_PREFIX, quiz.id),
+    )]]);
+    let last = messages.len() - 1;
+    for (idx, text) in messages.into_iter().enumerate() {
+        let message = bot.send_message(chat_id, text).parse_mode(ParseMode::Html);
+        if idx == last {
+            message.reply_markup(keyboard.clone()).await?;
+        } else {
+            message.await?;
+        }
+    }
+    Ok(())
+}
+
+/// Starts the quiz of the questions the user has got wrong, whatever the kinds of them are
+pub async fn redo_mistakes(
+    ai_helper: Arc<QuizHelper>,
+    words: Arc<Declension>,
+    bot: Bot,
+    dialogue: QuizDialogue,
+    q: CallbackQuery,
+    db: Arc<Database>,
+) -> HandlerResult {
+    let quiz_id = q
+        .data
+        .as_deref()
+        .and_then(|d| d.strip_prefix(REDO_CALLBACK_PREFIX))
+        .and_then(|id| id.parse::<u64>().ok());
+    let (Some(quiz_id), Some(message)) = (quiz_id, q.message) else {
+        bot.answer_callback_query(q.id)
+            .text("Ця кнопка більше не працює")
+            .await?;
+        return Ok(());
+    };
+    let chat_id = message.chat.id;
+
+    if dialogue.get_or_default().await?.is_quiz() {
+        bot.answer_callback_query(q.id)
+            .text("Спершу закінчи тест або скасуй його (/cancel)")
+            .await?;
+        return Ok(());
+    }
+    // Only the mistakes of the last quiz with mistakes are kept
+    let Some(questions) = db.get_mistakes(chat_id, quiz_id).await? else {
+        bot.answer_callback_query(q.id)
+            .text("Ці помилки вже неактуальні, повторити можна лише помилки останнього тесту")
+            .await?;
+        return Ok(());
+    };
+    bot.answer_callback_query(q.id).await?;
+
+    bot.send_message(chat_id, format!("Повторимо помилки! Питань: {}", questions.len()))
+        .reply_markup(KeyboardRemove::new())
+        .await?;
+
+    let profile = db.get_profile(chat_id).await?;
+    // Each question is asked the way its kind is
+    let state = send_first_question(&bot, chat_id, &ai_helper, &words, &profile, quiz::Quiz::new(questions)).await?;
+    schedule_question_timeout(&bot, &dialogue, &ai_helper, &words, &db, &state);
+    dialogue.update(state).await?;
+    Ok(())
+}
@@ -0,0 +1,273 @@
+use teloxide::{
+    dispatching::dialogue::GetChatId,
+    prelude::*,
+    types::{InlineKeyboardButton, InlineKeyboardMarkup, ParseMode, PollAnswer, PollType},
+    RequestError,
+};
+
+use crate::metrics;
+use crate::quiz;
+use crate::reports::report_button;
+
+// Telegram limits for the quiz polls
+const MAX_POLL_QUESTION_LENGTH: usize = 300;
+const MAX_POLL_OPTION_LENGTH: usize = 100;
+const MAX_POLL_OPTIONS: usize = 10;
+const MAX_POLL_EXPLANATION_LENGTH: usize = 200;
+// Seconds
+const MIN_POLL_OPEN_PERIOD: u64 = 5;
+const MAX_POLL_OPEN_PERIOD: u64 = 600;
+
+/// How the questions are shown to the user
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Presentation {
+    // A message with the inline answer buttons
+    #[default]
+    Buttons,
+    // A native Telegram quiz poll
+    Polls,
+}
+impl Presentation {
+    pub const ALL: [Presentation; 2] = [Presentation::Buttons, Presentation::Polls];
+
+    // What the user sees when choosing the presentation
+    pub fn name(&self) -> &str {
+        match self {
+            Presentation::Buttons => "Кнопки під питанням",
+            Presentation::Polls => "Вікторини Telegram",
+        }
+    }
+
+    // How the presentation is stored in the database and in the callback data
+    pub fn key(&self) -> &str {
+        match self {
+            Presentation::Buttons => "buttons",
+            Presentation::Polls => "polls",
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<Presentation> {
+        Self::ALL.into_iter().find(|p| p.key() == key)
+    }
+}
+
+pub const ANSWER_CALLBACK_PREFIX: &str = "answer:";
+
+/// What is sent back when the user presses an answer button
+struct AnswerCallback {
+    quiz_id: u64,
+    question_number: usize,
+    option: usize,
+}
+impl AnswerCallback {
+    fn to_data(&self) -> String {
+        format!(
+            "{}{}:{}:{}",
+            ANSWER_CALLBACK_PREFIX, self.quiz_id, self.question_number, self.option
+        )
+    }
+
+    fn parse(data: &str) -> Option<Self> {
+        let mut parts = data.strip_prefix(ANSWER_CALLBACK_PREFIX)?.split(':');
+        let callback = Self {
+            quiz_id: parts.next()?.parse().ok()?,
+            question_number: parts.next()?.parse().ok()?,
+            option: parts.next()?.parse().ok()?,
+        };
+        parts.next().is_none().then_some(callback)
+    }
+}
+
+/// The user's answer to a question, either with a button or in a poll
+#[derive(Debug, Clone)]
+pub enum AnswerUpdate {
+    Button(Box<CallbackQuery>),
+    Poll(PollAnswer),
+}
+impl AnswerUpdate {
+    pub fn is_poll(&self) -> bool {
+        matches!(self, AnswerUpdate::Poll(_))
+    }
+}
+impl GetChatId for AnswerUpdate {
+    fn chat_id(&self) -> Option<ChatId> {
+        match self {
+            AnswerUpdate::Button(q) => q.chat_id(),
+            // The polls are only sent to the private chats, where the chat id is the user id
+            AnswerUpdate::Poll(answer) => Some(ChatId(answer.user.id.0 as i64)),
+        }
+    }
+}
+
+// `chosen` is the option the user has answered with, if the question is already answered.
+// The question can be reported as wrong with the last button.
+fn answers_keyboard(quiz: &quiz::Quiz, question_number: usize, chosen: Option<usize>) -> InlineKeyboardMarkup {
+    let question = &quiz.questions[question_number];
+    let answers = question.answers.iter().enumerate().map(|(option, answer)| {
+        let text = match chosen {
+            Some(_) if answer.is_correct => format!("✅ {}", answer.text),
+            Some(chosen) if chosen == option => format!("❌ {}", answer.text),
+            _ => answer.text.clone(),
+        };
+        let callback = AnswerCallback {
+            quiz_id: quiz.id,
+            question_number,
+            option,
+        };
+        vec![InlineKeyboardButton::callback(text, callback.to_data())]
+    });
+    InlineKeyboardMarkup::new(answers.chain(std::iter::once(vec![report_button(quiz.id, question_number)])))
+}
+
+/// Sends the question in the user's presentation. `text` is the question's HTML text.
+/// The poll the question is sent as (if it is) is remembered in the quiz, so the poll answer can be checked,
+/// and so is the time it's sent at, for the timed quizzes.
+pub async fn send_question(
+    bot: &Bot,
+    chat_id: ChatId,
+    quiz: &mut quiz::Quiz,
+    question_number: usize,
+    text: String,
+    presentation: Presentation,
+) -> Result<(), RequestError> {
+    quiz.poll_id = None;
+    if presentation == Presentation::Polls && can_be_poll(&quiz.questions[question_number]) {
+        let report = InlineKeyboardMarkup::new(vec![vec![report_button(quiz.id, question_number)]]);
+        let poll = send_poll(bot, chat_id, &quiz.questions[question_number], text, quiz.time_limit, report).await?;
+        quiz.poll_id = poll.poll().map(|p| p.id.clone());
+    } else {
+        bot.send_message(chat_id, text)
+            .parse_mode(ParseMode::Html)
+            .reply_markup(answers_keyboard(quiz, question_number, None))
+            .await?;
+    }
+    quiz.question_sent_at = Some(quiz::now_millis());
+    // Every quiz, the battles too, begins with sending its first question
+    if question_number == 0 {
+        metrics::quiz_started(quiz.kind_key());
+    }
+    Ok(())
+}
+
+fn can_be_poll(question: &quiz::Question) -> bool {
+    question.answers.len() >= 2
+        && question.answers.len() <= MAX_POLL_OPTIONS
+        && question
+            .answers
+            .iter()
+            .all(|a| a.text.chars().count() <= MAX_POLL_OPTION_LENGTH)
+}
+
+async fn send_poll(
+    bot: &Bot,
+    chat_id: ChatId,
+    question: &quiz::Question,
+    text: String,
+    time_limit: Option<u64>,
+    reply_markup: InlineKeyboardMarkup,
+) -> Result<Message, RequestError> {
+    // The poll question is plain text and a short one,
+    // so the long questions (e.g. with a whole sentence) are sent as a message before the poll
+    let plain_text = strip_html(&text);
+    let poll_question = if plain_text.chars().count() <= MAX_POLL_QUESTION_LENGTH {
+        plain_text
+    } else {
+        bot.send_message(chat_id, text)
+            .parse_mode(ParseMode::Html)
+            .await?;
+        "Обери правильну відповідь".to_string()
+    };
+
+    // `can_be_poll` makes sure there are no more than 10 options
+    let correct_option = question.answers.iter().position(|a| a.is_correct).unwrap_or(0) as u8;
+    let mut poll = bot
+        .send_poll(chat_id, poll_question, question.answers.iter().map(|a| a.text.clone()))
+        .type_(PollType::Quiz)
+        // Anonymous polls don't send the answers to the bot
+        .is_anonymous(false)
+        .correct_option_id(correct_option)
+        .reply_markup(reply_markup);
+    if let Some(explanation) = &question.explanation {
+        poll = poll.explanation(truncate(explanation, MAX_POLL_EXPLANATION_LENGTH));
+    }
+    // The poll closes itself when the time is up, so the late answer can't be chosen
+    if let Some(time_limit) = time_limit {
+        poll = poll.open_period(time_limit.clamp(MIN_POLL_OPEN_PERIOD, MAX_POLL_OPEN_PERIOD) as u16);
+    }
+    poll.await
+}
+
+/// Checks that the answer belongs to the current question and, for the buttons, marks the answer on them.
+/// Returns the chat and the chosen answer, or `None` if the answer is outdated.
+pub async fn receive_answer(
+    bot: &Bot,
+    update: &AnswerUpdate,
+    quiz: &quiz::Quiz,
+    question_number: usize,
+) -> Result<Option<(ChatId, quiz::Answer)>, RequestError> {
+    match update {
+        AnswerUpdate::Button(q) => {
+            let (Some(message), Some((option, answer))) = (&q.message, button_answer(q, quiz, question_number)) else {
+                // e.g. a button of an already answered question or of another quiz
+                bot.answer_callback_query(q.id.clone())
+                    .text("Це питання вже неактуальне")
+                    .await?;
+                return Ok(None);
+            };
+
+            bot.answer_callback_query(q.id.clone()).await?;
+            bot.edit_message_reply_markup(message.chat.id, message.id)
+                .reply_markup(answers_keyboard(quiz, question_number, Some(option)))
+                .await?;
+
+            Ok(Some((message.chat.id, answer)))
+        }
+        AnswerUpdate::Poll(poll_answer) => {
+            if quiz.poll_id.as_deref() != Some(poll_answer.poll_id.as_str()) {
+                return Ok(None);
+            }
+            let answer = poll_answer
+                .option_ids
+                .first()
+                .and_then(|option| quiz.questions[question_number].answers.get(*option as usize));
+
+            // The chat id is always there for the poll answers
+            Ok(answer.map(|answer| (update.chat_id().unwrap(), answer.clone())))
+        }
+    }
+}
+
+/// The option and the answer of the pressed button, or `None` if the button isn't of the current question
+pub fn button_answer(q: &CallbackQuery, quiz: &quiz::Quiz, question_number: usize) -> Option<(usize, quiz::Answer)> {
+    let callback = q.data.as_deref().and_then(AnswerCallback::parse)?;
+    if callback.quiz_id != quiz.id || callback.question_number != question_number {
+        return None;
+    }
+    let answer = quiz.questions[question_number].answers.get(callback.option)?;
+    Some((callback.option, answer.clone()))
+}
+
+pub fn strip_html(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    let mut in_tag = false;
+    for c in text.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            _ if !in_tag => plain.push(c),
+            _ => {}
+        }
+    }
+    plain
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&amp;", "&")
+}
+
+fn truncate(text: &str, max_length: usize) -> String {
+    if text.chars().count() <= max_length {
+        return text.to_string();
+    }
+    text.chars().take(max_length - 1).chain(std::iter::once('…')).collect()
+}
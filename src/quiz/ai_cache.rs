@@ -0,0 +1,254 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::future::Future;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::{watch, Notify, OnceCell};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PromptKind {
+    StressExample,
+    WrongStressReply,
+    WrongPartsReply,
+}
+impl PromptKind {
+    fn as_str(&self) -> &str {
+        match self {
+            PromptKind::StressExample => "stress_example",
+            PromptKind::WrongStressReply => "wrong_stress_reply",
+            PromptKind::WrongPartsReply => "wrong_parts_reply",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    pub kind: PromptKind,
+    // What the prompt is about: the word, or the whole question for the parts of speech
+    pub word: String,
+    pub personality: String,
+}
+impl CacheKey {
+    pub fn new(kind: PromptKind, word: impl Into<String>, personality: impl Into<String>) -> Self {
+        Self {
+            kind,
+            word: word.into(),
+            personality: personality.into(),
+        }
+    }
+
+    // JSON objects can only have string keys
+    fn to_file_key(&self) -> String {
+        format!("{}|{}|{}", self.kind.as_str(), self.personality, self.word)
+    }
+}
+
+type InFlight = Arc<OnceCell<Option<String>>>;
+
+/// The AI replies which are persisted on disk, so the same prompt doesn't cost another API call,
+/// and the requests which are being generated right now, so the prefetched content is awaited instead of requested twice.
+pub struct AiCache {
+    entries: Arc<Mutex<Entries>>,
+    in_flight: Mutex<HashMap<CacheKey, InFlight>>,
+    // The writer task is told the entries have changed and tells which version of them is on disk
+    changed: Arc<Notify>,
+    saved: watch::Receiver<u64>,
+}
+
+#[derive(Default)]
+struct Entries {
+    contents: HashMap<String, String>,
+    // Bumped on every insert
+    version: u64,
+}
+
+impl AiCache {
+    /// Starts the task which writes the cache to the file, so it must be called inside the runtime
+    pub fn open(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        // A missing or broken cache file just means that we start with an empty cache
+        let contents = File::open(&path)
+            .ok()
+            .and_then(|file| serde_json::from_reader(file).ok())
+            .unwrap_or_default();
+
+        let entries = Arc::new(Mutex::new(Entries { contents, version: 0 }));
+        let changed = Arc::new(Notify::new());
+        let (saved_sender, saved) = watch::channel(0);
+        tokio::spawn(write_changes(path, entries.clone(), changed.clone(), saved_sender));
+
+        Self {
+            entries,
+            in_flight: Mutex::new(HashMap::new()),
+            changed,
+            saved,
+        }
+    }
+
+    pub fn get(&self, key: &CacheKey) -> Option<String> {
+        self.entries.lock().unwrap().contents.get(&key.to_file_key()).cloned()
+    }
+
+    /// Returns the cached content or generates it with `generate`.
+    /// Only the generated `Some` content is cached, so failed or rejected generations are retried next time.
+    pub async fn get_or_generate<E, Fut>(
+        &self,
+        key: CacheKey,
+        generate: impl FnOnce() -> Fut,
+    ) -> Result<Option<String>, E>
+    where
+        Fut: Future<Output = Result<Option<String>, E>>,
+    {
+        if let Some(content) = self.get(&key) {
+            return Ok(Some(content));
+        }
+
+        let cell = self
+            .in_flight
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_default()
+            .clone();
+        let result = cell.get_or_try_init(generate).await.cloned();
+
+        if let Ok(Some(content)) = &result {
+            self.insert(&key, content.clone()).await;
+        }
+        // The content is in the cache by now, so the next callers won't need the in-flight request
+        self.in_flight.lock().unwrap().remove(&key);
+
+        result
+    }
+
+    // Returns once the content is on disk (or has failed to get there)
+    async fn insert(&self, key: &CacheKey, content: String) {
+        let version = {
+            let mut entries = self.entries.lock().unwrap();
+            entries.contents.insert(key.to_file_key(), content);
+            entries.version += 1;
+            entries.version
+        };
+        self.changed.notify_one();
+
+        let mut saved = self.saved.clone();
+        while *saved.borrow() < version {
+            if saved.changed().await.is_err() {
+                break;
+            }
+        }
+    }
+}
+
+// The only one who writes the file, so the saves can't overtake each other.
+// The inserts made while the file is being written are saved together the next time.
+async fn write_changes(path: PathBuf, entries: Arc<Mutex<Entries>>, changed: Arc<Notify>, saved: watch::Sender<u64>) {
+    loop {
+        changed.notified().await;
+        let (contents, version) = {
+            let entries = entries.lock().unwrap();
+            (entries.contents.clone(), entries.version)
+        };
+
+        let file_path = path.clone();
+        match tokio::task::spawn_blocking(move || save(&file_path, &contents)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => log::warn!("Failed to save the AI cache to {:?}: {}", path, e),
+            Err(e) => log::error!("The AI cache saving task failed: {}", e),
+        }
+        // Even if it has failed, so the inserts don't wait for it forever
+        if saved.send(version).is_err() {
+            // The cache is dropped
+            return;
+        }
+    }
+}
+
+// Written next to the file and renamed over it, so a crash in the middle doesn't leave a truncated cache
+fn save(path: &Path, entries: &HashMap<String, String>) -> io::Result<()> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+
+    let mut writer = BufWriter::new(File::create(&temp_path)?);
+    serde_json::to_writer(&mut writer, entries)?;
+    writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    std::fs::rename(&temp_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn cache_path(dir: &TempDir) -> PathBuf {
+        dir.path().join("ai_cache.json")
+    }
+
+    #[tokio::test]
+    async fn generated_content_is_saved() {
+        let dir = TempDir::new().unwrap();
+        let path = cache_path(&dir);
+        let cache = AiCache::open(&path);
+        let key = CacheKey::new(PromptKind::StressExample, "молоко", "teacher");
+
+        let content = cache
+            .get_or_generate(key.clone(), || async { Ok::<_, ()>(Some("Приклад".to_string())) })
+            .await;
+        assert_eq!(content, Ok(Some("Приклад".to_string())));
+        // Cached, so it isn't generated again
+        let content = cache
+            .get_or_generate(key.clone(), || async { Err::<Option<String>, _>(()) })
+            .await;
+        assert_eq!(content, Ok(Some("Приклад".to_string())));
+
+        assert_eq!(AiCache::open(&path).get(&key), Some("Приклад".to_string()));
+        // Only the cache itself, without the temporary file
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn concurrent_inserts_are_all_saved() {
+        let dir = TempDir::new().unwrap();
+        let path = cache_path(&dir);
+        let cache = Arc::new(AiCache::open(&path));
+        let keys = (0..20)
+            .map(|i| CacheKey::new(PromptKind::WrongPartsReply, format!("питання {}", i), "franko"))
+            .collect::<Vec<_>>();
+
+        let tasks = keys
+            .iter()
+            .cloned()
+            .map(|key| {
+                let cache = cache.clone();
+                tokio::spawn(async move {
+                    let content = format!("Відповідь на {}", key.word);
+                    cache.get_or_generate(key, || async { Ok::<_, ()>(Some(content)) }).await
+                })
+            })
+            .collect::<Vec<_>>();
+        for task in tasks {
+            task.await.unwrap().unwrap();
+        }
+
+        let reopened = AiCache::open(&path);
+        for key in &keys {
+            assert_eq!(reopened.get(key), Some(format!("Відповідь на {}", key.word)));
+        }
+    }
+
+    #[tokio::test]
+    async fn rejected_content_is_not_saved() {
+        let dir = TempDir::new().unwrap();
+        let path = cache_path(&dir);
+        let cache = AiCache::open(&path);
+        let key = CacheKey::new(PromptKind::WrongStressReply, "кварта\u{301}л", "lesya");
+
+        let content = cache.get_or_generate(key.clone(), || async { Ok::<_, ()>(None) }).await;
+        assert_eq!(content, Ok(None));
+        assert_eq!(cache.get(&key), None);
+        assert!(!path.exists());
+    }
+}
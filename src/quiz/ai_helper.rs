@@ -1,68 +1,118 @@
-use crate::quiz::Question;
+use crate::metrics;
+use crate::quiz::ai_cache::{AiCache, CacheKey, PromptKind};
+use crate::quiz::{stress, Question};
 use chatgpt::prelude::*;
 use chatgpt::types::CompletionResponse;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
 
-use super::Answer;
+const MAX_EXAMPLE_LENGTH: usize = 200;
+const MAX_EXAMPLE_ATTEMPTS: usize = 2;
 
 pub struct QuizHelper {
-    personality: Personality,
     chat_gpt: ChatGPT,
-}
-enum QuizHelperError {
-    ChatGPTError(chatgpt::err::Error),
-    NoWrongAnswerError,
-    NoCorrectAnswerError,
+    cache: AiCache,
+    // Whether the offline stress explanations are retold by the AI
+    pub retell_stress_explanations: bool,
+    // The requests to the AI since the start (the cached answers aren't requested), for the admins
+    calls: AtomicU64,
+    failures: AtomicU64,
 }
 impl QuizHelper {
-    pub fn new(chat_gpt: ChatGPT, personality: Personality) -> Self {
+    pub fn new(chat_gpt: ChatGPT, cache: AiCache, retell_stress_explanations: bool) -> Self {
         Self {
-            personality,
             chat_gpt,
+            cache,
+            retell_stress_explanations,
+            calls: AtomicU64::new(0),
+            failures: AtomicU64::new(0),
+        }
+    }
+
+    /// How many requests to the AI have been sent since the start and how many of them have failed
+    pub fn usage(&self) -> (u64, u64) {
+        (self.calls.load(Ordering::Relaxed), self.failures.load(Ordering::Relaxed))
+    }
+
+    async fn complete(&self, prompt: &str) -> Result<String> {
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        let started = Instant::now();
+        let response: Result<CompletionResponse> = self.chat_gpt.send_message(prompt).await;
+        if response.is_err() {
+            self.failures.fetch_add(1, Ordering::Relaxed);
         }
+        metrics::ai_request(started, response.is_err());
+        Ok(response?.message().clone().content)
     }
 
-    pub async fn generate_example_for_stress_question(&self, question: Question) -> Result<String> {
+    /// Generates an example sentence with the word the stress question is about.
+    /// Returns `None` if the model didn't manage to write a valid example (see `validate_stress_example`).
+    pub async fn generate_example_for_stress_question(
+        &self,
+        question: Question,
+        word_forms: &[String],
+        personality: Personality,
+    ) -> Result<Option<String>> {
         println!("Generating example for question: {:?}", question.text);
+        let correct_answer = question.answers.iter().find(|a| a.is_correct)
+        .ok_or(chatgpt::err::Error::BackendError { message: "No correct answer found".to_string(), error_type: "QuizError".to_string() })?;
+        // The model mustn't see the variants, otherwise it's likely to copy the stress into the example
+        let word = stress::remove_stress(&correct_answer.text);
+
         let prompt = format!("Ти -- Чат-бот, який допомагає учням вивчати українську мову.
-        Учню було задано питання про наголос у слові з двома варіянтами: \"{}\".
-        Згенеруй речення де використовується це слово (не вказуючи наголос, звісно). До того ж напиши це речення так, наче ти -- {}", question.text, self.personality.get_personality());
+        Учню було задано питання про наголос у слові \"{}\".
+        Згенеруй одне коротке речення (до {} символів), де використовується це слово. Не вказуй наголос жодним чином: ні знаком наголосу, ні великою літерою. До того ж напиши це речення так, наче ти -- {}", word, MAX_EXAMPLE_LENGTH, personality.get_personality());
 
-        let response: CompletionResponse = self.chat_gpt.send_message(&prompt).await?;
-        let content = response.message().clone().content;
+        let key = CacheKey::new(PromptKind::StressExample, word.clone(), personality.key());
+        self.cache.get_or_generate(key, || async {
+            for attempt in 1..=MAX_EXAMPLE_ATTEMPTS {
+                let content = self.complete(&prompt).await?;
 
-        println!("Completion: {:?}", content);
+                println!("Completion: {:?}", content);
 
-        Ok(content)
+                let content = content.trim().to_string();
+                match validate_stress_example(&content, &word, word_forms) {
+                    Ok(()) => return Ok(Some(content)),
+                    Err(e) => log::warn!("Invalid example for {:?} (attempt {}): {:?}", word, attempt, e),
+                }
+            }
+
+            Ok(None)
+        })
+        .await
     }
 
+    /// Retells the offline explanation of the stress (see `stress_rules`) in the personality's manner.
+    /// The model is not asked to explain the stress by itself, since it tends to make the rules up.
     pub async fn generate_reply_to_wrong_stress_answer(
         &self,
         question: Question,
+        explanation: &str,
+        personality: Personality,
     ) -> Result<String> {
         println!(
             "Generating reply to wrong answer for question: {:?}",
             question.text
         );
-        let wrong_answer = question.answers.iter().find(|a| !a.is_correct).unwrap();
-        let correct_answer = question.answers.iter().find(|a| a.is_correct).unwrap();
+        let correct_answer = question.answers.iter().find(|a| a.is_correct)
+        .ok_or(chatgpt::err::Error::BackendError { message: "No correct answer found".to_string(), error_type: "QuizError".to_string() })?;
 
         let prompt = format!("Ти -- Чат-бот, який допомагає учням вивчати українську мову.
-        Учень відповів неправильно на питання про наголос у слові; з двома варіянтами: \"{}\".
-        Учень відповів {}, а правильна відповідь -- {}.
-        Згенеруй відповідь, яка пояснює, чому правильний наголос саме на цьому слові. До того ж напиши це речення так, наче ти -- {}. Ліміт речення -- 100 символів.", question.text, wrong_answer.text, correct_answer.text, self.personality.get_personality());
+        Учень відповів неправильно на питання про наголос у слові. Правильна відповідь -- {}.
+        Ось пояснення правильного наголосу: \"{}\"
+        Перекажи це пояснення своїми словами, не додаючи жодних нових правил. До того ж напиши це так, наче ти -- {}. Ліміт -- 200 символів.", correct_answer.text, explanation, personality.get_personality());
 
-        let response: CompletionResponse = self.chat_gpt.send_message(&prompt).await?;
-        let content = response.message().clone().content;
-
-        println!("Completion: {:?}", content);
-
-        Ok(content)
+        let key = CacheKey::new(PromptKind::WrongStressReply, correct_answer.text.clone(), personality.key());
+        self.cache.get_or_generate(key, || self.send_prompt(&prompt))
+            .await
+            .map(Option::unwrap_or_default)
     }
 
     pub async fn generate_reply_to_wrong_parts_answer(
         &self,
         question: Question,
         wrong_answer: String,
+        personality: Personality,
     ) -> Result<String> {
         println!(
             "Generating reply to wrong answer for question: {:?}",
@@ -81,29 +131,186 @@ impl QuizHelper {
         Учень відповів {}, а правильна відповідь -- {}.
         Згенеруй відповідь, яка пояснює в чому була помилка, на яке питання відповідає правильна частина мови.
         До того ж напиши це речення так, наче ти -- {}. Ліміт 1-2 середніх абзаців.",
-         question.text, wrong_answer, correct_answer.text, self.personality.get_personality());
+         question.text, wrong_answer, correct_answer.text, personality.get_personality());
+
+        let key = CacheKey::new(
+            PromptKind::WrongPartsReply,
+            format!("{}\n{}", question.text, wrong_answer),
+            personality.key(),
+        );
+        self.cache.get_or_generate(key, || self.send_prompt(&prompt))
+            .await
+            .map(Option::unwrap_or_default)
+    }
 
-        let response: CompletionResponse = self.chat_gpt.send_message(&prompt).await?;
-        let content = response.message().clone().content;
+    async fn send_prompt(&self, prompt: &str) -> Result<Option<String>> {
+        let content = self.complete(prompt).await?;
 
         println!("Completion: {:?}", content);
 
-        Ok(content)
+        Ok(Some(content))
     }
 }
 
+#[derive(Debug)]
+pub enum InvalidExampleError {
+    // The example doesn't use the word (in any of its forms)
+    NoWord,
+    // The example shows the stress with the stress symbol or a capital letter
+    RevealsStress,
+    TooLong,
+}
+
+/// Checks that the AI-generated example can be shown along with the stress question.
+/// `word_forms` are the inflections of the word (e.g. from the declension dictionary),
+/// if there are none, the forms are guessed by the stem of the word.
+pub fn validate_stress_example(
+    example: &str,
+    word: &str,
+    word_forms: &[String],
+) -> std::result::Result<(), InvalidExampleError> {
+    if example.chars().count() > MAX_EXAMPLE_LENGTH {
+        return Err(InvalidExampleError::TooLong);
+    }
+
+    if stress::has_stress(example) {
+        return Err(InvalidExampleError::RevealsStress);
+    }
+
+    let tokens = example
+        .split(|c: char| !(c.is_alphabetic() || c == '\'' || c == '’' || c == 'ʼ' || c == '-'))
+        .filter(|t| !t.is_empty())
+        .collect::<Vec<_>>();
+
+    // e.g. "сестрА" or "сЕстри" -- a capital letter in the middle of the word is a stress hint.
+    // The parts of the compound names (e.g. "Івано-Франківськ") start with a capital letter each.
+    let has_capitalised_stress = tokens
+        .iter()
+        .flat_map(|t| t.split(['-', '\'', '’', 'ʼ']))
+        .any(|part| part.chars().skip(1).any(|c| c.is_uppercase()) && part.chars().any(|c| c.is_lowercase()));
+    if has_capitalised_stress {
+        return Err(InvalidExampleError::RevealsStress);
+    }
+
+    let word = word.to_lowercase();
+    let has_word = if word_forms.is_empty() {
+        // Without the dictionary we only know that the forms share the stem,
+        // which we take as the word without its ending (the last 2 letters)
+        let stem = word.chars().take(word.chars().count().saturating_sub(2).max(3)).collect::<String>();
+        tokens.iter().any(|t| t.to_lowercase().starts_with(&stem))
+    } else {
+        tokens
+            .iter()
+            .any(|t| t.to_lowercase() == word || word_forms.iter().any(|f| f.to_lowercase() == t.to_lowercase()))
+    };
+    if !has_word {
+        return Err(InvalidExampleError::NoWord);
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub enum Personality {
+    #[default]
     Shevchenko,
     Lesya,
     Franko,
+    // Neutral one, for the serious study
+    Teacher,
 }
 impl Personality {
+    pub const ALL: [Personality; 4] = [
+        Personality::Shevchenko,
+        Personality::Lesya,
+        Personality::Franko,
+        Personality::Teacher,
+    ];
+
     pub fn get_personality(&self) -> String {
         match self {
             Personality::Shevchenko => "Тарас Шевченко",
             Personality::Lesya => "Леся Українка",
             Personality::Franko => "Іван Франко",
+            Personality::Teacher => "вчитель української мови, який пояснює спокійно, коротко і по суті, без художніх прикрас",
         }
         .to_string()
     }
+
+    // What the user sees when choosing the personality
+    pub fn name(&self) -> &str {
+        match self {
+            Personality::Shevchenko => "Тарас Шевченко",
+            Personality::Lesya => "Леся Українка",
+            Personality::Franko => "Іван Франко",
+            Personality::Teacher => "Вчитель",
+        }
+    }
+
+    // How the personality is stored in the database and in the callback data
+    pub fn key(&self) -> &str {
+        match self {
+            Personality::Shevchenko => "shevchenko",
+            Personality::Lesya => "lesya",
+            Personality::Franko => "franko",
+            Personality::Teacher => "teacher",
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<Personality> {
+        Self::ALL.into_iter().find(|p| p.key() == key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate(example: &str, word: &str, word_forms: &[&str]) -> std::result::Result<(), InvalidExampleError> {
+        let word_forms = word_forms.iter().map(|f| f.to_string()).collect::<Vec<_>>();
+        validate_stress_example(example, word, &word_forms)
+    }
+
+    #[test]
+    fn valid_examples_pass() {
+        assert!(validate("Моя сестра любить читати.", "сестра", &[]).is_ok());
+        assert!(validate("Я подарував сестрі книгу.", "сестра", &["сестри", "сестрі", "сестру"]).is_ok());
+    }
+
+    #[test]
+    fn compound_names_are_not_stress_hints() {
+        assert!(validate("Сестра поїхала до Івано-Франківська.", "сестра", &[]).is_ok());
+        assert!(validate("Сестра читала О'Генрі.", "сестра", &[]).is_ok());
+    }
+
+    #[test]
+    fn stress_hints_are_rejected() {
+        assert!(matches!(
+            validate("Моя сестрА любить читати.", "сестра", &[]),
+            Err(InvalidExampleError::RevealsStress)
+        ));
+        assert!(matches!(
+            validate("Моя сестра\u{301} любить читати.", "сестра", &[]),
+            Err(InvalidExampleError::RevealsStress)
+        ));
+        assert!(matches!(
+            validate("Прийшли Івано-ФранкІвці та сестра.", "сестра", &[]),
+            Err(InvalidExampleError::RevealsStress)
+        ));
+    }
+
+    #[test]
+    fn examples_without_the_word_are_rejected() {
+        assert!(matches!(validate("Брат любить читати.", "сестра", &[]), Err(InvalidExampleError::NoWord)));
+        assert!(matches!(
+            validate("Сестринський обов'язок.", "сестра", &["сестри", "сестрі"]),
+            Err(InvalidExampleError::NoWord)
+        ));
+    }
+
+    #[test]
+    fn long_examples_are_rejected() {
+        let example = format!("Сестра {}", "дуже ".repeat(MAX_EXAMPLE_LENGTH));
+        assert!(matches!(validate(&example, "сестра", &[]), Err(InvalidExampleError::TooLong)));
+    }
 }
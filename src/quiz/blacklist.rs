@@ -0,0 +1,27 @@
+use std::collections::HashSet;
+use std::sync::RwLock;
+
+use crate::quiz::QuestionSource;
+
+/// The dictionary items the admins have found wrong (see `QuestionSource::key`), no questions are made of them.
+/// It's loaded from the database at the start and filled in while the bot is running.
+#[derive(Debug, Default)]
+pub struct Blacklist {
+    items: RwLock<HashSet<String>>,
+}
+
+impl Blacklist {
+    pub fn new(items: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            items: RwLock::new(items.into_iter().collect()),
+        }
+    }
+
+    pub fn add(&self, key: String) {
+        self.items.write().unwrap().insert(key);
+    }
+
+    pub fn contains(&self, source: &QuestionSource) -> bool {
+        self.items.read().unwrap().contains(&source.key())
+    }
+}
@@ -1,34 +1,109 @@
 use rand::seq::SliceRandom;
-use rand::thread_rng;
 use rand::Rng;
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::BufReader;
 use serde_json::Value;
 
 use crate::quiz;
+use crate::quiz::kind::DatasetError;
+use crate::quiz::stress::{self, StressWords};
+use crate::quiz::mastery::Skill;
+use crate::quiz::stress_rules;
 
 pub struct Declension {
     pub noun_words: Vec<Noun>,
+    // Indexes of the nouns which have at least one form with a known stress
+    stressed_noun_idxs: Vec<usize>,
 }
 
 impl Declension {
-    pub fn new(file: File) -> Self {
-        let data: Vec<JsonWord> = serde_json::from_reader(file).expect("Unable to parse");
+    pub fn new(file: File) -> Result<Self, DatasetError> {
+        let data: Vec<JsonWord> = serde_json::from_reader(BufReader::new(file))
+            .map_err(|e| DatasetError::Parse(e.to_string()))?;
         let words: Vec<Noun> = data.iter()
         .filter(|x| x.pos == "noun")
-        .map(|x| x.to_noun())
-        .filter(|x| x.is_some())
-        .map(|x| x.unwrap())
+        .filter_map(|x| x.to_noun())
         .collect();
+        if words.is_empty() {
+            return Err(DatasetError::Empty);
+        }
 
-        return Self { noun_words: words };
+        let mut declension = Self { noun_words: words, stressed_noun_idxs: Vec::new() };
+        declension.update_stressed_noun_idxs();
+        Ok(declension)
     }
 
-    pub fn get_random_noun(&self) -> &Noun {
-        let rand = rand::thread_rng().gen_range(0..self.noun_words.len());
+    pub fn get_random_noun<R: Rng + ?Sized>(&self, rng: &mut R) -> &Noun {
+        let rand = rng.gen_range(0..self.noun_words.len());
         let rand_word = self.noun_words.get(rand).unwrap();
-        return rand_word;
+        rand_word
+    }
+
+    /// Fills in the stress of the forms which don't have one yet
+    /// by looking them up in the dictionary of stressed words (e.g. `stress.txt` or a companion file with forms).
+    pub fn attach_stress(&mut self, dictionary: &StressWords) {
+        let mut stress_by_word: HashMap<&str, Option<&str>> = HashMap::new();
+        for word in &dictionary.words {
+            stress_by_word
+                .entry(word.word_without_stress_symbol.as_str())
+                // Homographs (e.g. "за́мок" and "замо́к") can't be resolved without the context,
+                // so we don't use them at all
+                .and_modify(|stressed| {
+                    if *stressed != Some(word.word_with_stress_symbol.as_str()) {
+                        *stressed = None;
+                    }
+                })
+                .or_insert(Some(word.word_with_stress_symbol.as_str()));
+        }
+
+        for noun in &mut self.noun_words {
+            // The stress may move inside the paradigm (сестри́ in the genitive singular, се́стри in the nominative plural),
+            // and the dictionary doesn't tell the case, so the spellings of more than one form are left without it
+            let mut spellings: HashMap<String, usize> = HashMap::new();
+            for form in &noun.forms {
+                *spellings.entry(form.word.clone()).or_default() += 1;
+            }
+            for form in noun.forms.iter_mut().filter(|f| f.stressed.is_none() && spellings[&f.word] == 1) {
+                if let Some(Some(stressed)) = stress_by_word.get(form.word.as_str()) {
+                    form.stressed = Some(stressed.to_string());
+                }
+            }
+        }
+
+        self.update_stressed_noun_idxs();
+    }
+
+    /// Returns all the forms of the nouns the word is a form of (including the dictionary forms).
+    /// The result is empty if the word isn't a noun from the dictionary.
+    pub fn get_inflections(&self, word: &str) -> Vec<String> {
+        let word = word.to_lowercase();
+        self.noun_words
+            .iter()
+            .filter(|noun| noun.word.to_lowercase() == word || noun.forms.iter().any(|f| f.word.to_lowercase() == word))
+            .flat_map(|noun| std::iter::once(noun.word.clone()).chain(noun.forms.iter().map(|f| f.word.clone())))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect()
+    }
+
+    pub fn has_stressed_nouns(&self) -> bool {
+        !self.stressed_noun_idxs.is_empty()
+    }
+
+    pub fn get_random_noun_with_stress<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<&Noun> {
+        let rand = self.stressed_noun_idxs.choose(rng)?;
+        self.noun_words.get(*rand)
+    }
+
+    fn update_stressed_noun_idxs(&mut self) {
+        self.stressed_noun_idxs = self
+            .noun_words
+            .iter()
+            .enumerate()
+            .filter(|(_, noun)| noun.forms.iter().any(|f| f.can_be_asked_about_stress()))
+            .map(|(i, _)| i)
+            .collect();
     }
 }
 
@@ -49,7 +124,8 @@ impl JsonWord {
             return None;
         }
         let mut noun_forms: Vec<NounForm> = Vec::new();
-        let forms = self.forms.as_object().unwrap();
+        // The broken entries are left out
+        let forms = self.forms.as_object()?;
         for (case_plural, forms) in forms {
             // "forms" is an array of strings, always
             let splitted = case_plural.split_once(" ");
@@ -77,13 +153,12 @@ impl JsonWord {
                 _ => continue
                 // _ => panic!("Unknown plurality"),
             };
-            noun_forms.push(NounForm {
-                word: forms[0].as_str().unwrap().to_string(),
-                case,
-                is_plural,
-            });                        
+            let Some(form) = forms[0].as_str() else {
+                continue;
+            };
+            noun_forms.push(NounForm::new(form, case, is_plural));
         }
-        return Some(Noun {
+        Some(Noun {
             word: self.word.clone(),
             forms: noun_forms,
         })
@@ -96,12 +171,12 @@ pub struct Noun {
     pub forms: Vec<NounForm>,
 }
 pub enum GenerateQuestionError {
-    NoNominativeForm,
     NoCorrectAnswer,
+    NoStressedForm,
 }
 
 impl Noun {
-pub fn generate_question_out_of_noun(&self) -> Result<quiz::Question, GenerateQuestionError> {
+pub fn generate_question_out_of_noun<R: Rng + ?Sized>(&self, rng: &mut R) -> Result<quiz::Question, GenerateQuestionError> {
         // let default_nominative_form = &self.forms.iter()
         // .find(|f| f.case == NounCase::Nominative && f.is_plural == false)
         // // or find plural if there is no singular
@@ -111,8 +186,8 @@ pub fn generate_question_out_of_noun(&self) -> Result<quiz::Question, GenerateQu
         // .ok_or(GenerateQuestionError::NoNominativeForm)?;
         let default_nominative_form = &self.word;
         
-        let random_case = NounCase::get_random_by_case_exluding_nominative();
-        let random_plurality = rand::thread_rng().gen_bool(0.5);
+        let random_case = NounCase::get_random_by_case_exluding_nominative(rng);
+        let random_plurality = rng.gen_bool(0.5);
     
         let text: String = format!("Поставте іменник \"{}\" у {} відмінок ({}) {} ", 
             default_nominative_form, 
@@ -133,8 +208,8 @@ pub fn generate_question_out_of_noun(&self) -> Result<quiz::Question, GenerateQu
         // Filter our correct answer too
         .filter(|f| f.case != correct_answer.case && f.is_plural == correct_answer.is_plural)
         .map(|f| f.word.clone())
-        // Removes duplicates
-        .collect::<HashSet<_>>().into_iter()
+        // Removes duplicates, keeping the order stable so the seeded quizzes are the same every time
+        .collect::<BTreeSet<_>>().into_iter()
         .collect::<Vec<String>>();
     
         let answers = {
@@ -143,11 +218,58 @@ pub fn generate_question_out_of_noun(&self) -> Result<quiz::Question, GenerateQu
             .collect::<Vec<quiz::Answer>>();
     
             shuffled_answers.push(quiz::Answer::new(correct_answer.word.clone(), true));
-            shuffled_answers.shuffle(&mut rand::thread_rng());
+            shuffled_answers.shuffle(rng);
             // returns
             shuffled_answers
         };    
-        return Ok(quiz::Question::new(text, answers));
+        let explanation = format!("{} -- {} відмінок ({}) {}",
+            correct_answer.word,
+            random_case.to_ukrainian_string(),
+            random_case.ukrainian_question(),
+            if correct_answer.is_plural { "множини" } else { "однини" },
+        );
+        Ok(quiz::Question::new(text, answers)
+            .with_explanation(explanation)
+            .with_skill(Skill::Case(random_case.clone()))
+            .made_of(quiz::QuestionSource::NounForm {
+                lemma: self.word.clone(),
+                case: random_case,
+                is_plural: correct_answer.is_plural,
+            }))
+    }
+
+    pub fn generate_stress_question<R: Rng + ?Sized>(&self, rng: &mut R) -> Result<quiz::Question, GenerateQuestionError> {
+        let random_case = NounCase::get_random_by_case(rng);
+        let random_plurality = rng.gen_bool(0.5);
+
+        let stressed_forms = self.forms.iter()
+        .filter(|f| f.can_be_asked_about_stress())
+        .collect::<Vec<_>>();
+
+        let form = stressed_forms.iter()
+        .find(|f| f.case == random_case && f.is_plural == random_plurality)
+        // The random form may be missing or not have a known stress, so we just take any other one
+        .or_else(|| stressed_forms.choose(rng))
+        .ok_or(GenerateQuestionError::NoStressedForm)?;
+
+        // It is safe to unwrap here since `can_be_asked_about_stress` checks that the form has a stress
+        let stressed = form.stressed.as_ref().unwrap();
+        let answers = stress::generate_stress_answers(stressed, rng)
+        .ok_or(GenerateQuestionError::NoStressedForm)?;
+
+        let text = format!("Де наголос у формі іменника \"{}\":\n{}?\n\n{}",
+            self.word,
+            form.to_ukrainian_string(),
+            stress::format_stress_variants(&answers),
+        );
+        Ok(quiz::Question::new(text, answers)
+            .with_explanation(stress_rules::explain_stress(stressed))
+            .with_skill(Skill::of_stress(stressed))
+            .made_of(quiz::QuestionSource::NounForm {
+                lemma: self.word.clone(),
+                case: form.case.clone(),
+                is_plural: form.is_plural,
+            }))
     }
 }
 
@@ -155,17 +277,39 @@ pub fn generate_question_out_of_noun(&self) -> Result<quiz::Question, GenerateQu
 pub struct NounForm {
     pub word: String,
     pub case: NounCase,
-    pub is_plural: bool
+    pub is_plural: bool,
+    // The form with the stress symbol, if the stress is known
+    #[serde(default)]
+    pub stressed: Option<String>,
 }
 impl NounForm {
+    pub fn new(form: &str, case: NounCase, is_plural: bool) -> Self {
+        // The declension dictionary may already have the stress marked in the form
+        let stressed = if stress::has_stress(form) { Some(form.to_string()) } else { None };
+        Self {
+            word: stress::remove_stress(form),
+            case,
+            is_plural,
+            stressed,
+        }
+    }
+
+    pub fn can_be_asked_about_stress(&self) -> bool {
+        match &self.stressed {
+            Some(stressed) => stress::can_have_incorrect_stress(stressed),
+            None => false,
+        }
+    }
+
     pub fn to_ukrainian_string(&self) -> String {
         let plurality = if self.is_plural { "множина" } else { "однина" };
         format!("{} ({} відмінок, {})", self.word, self.case.to_ukrainian_string(), plurality)
     }
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize, PartialEq, Eq, PartialOrd, Ord)]
 pub enum NounCase {
+    #[default]
     Nominative,
     Genitive,
     Dative,
@@ -176,6 +320,25 @@ pub enum NounCase {
 }
 
 impl NounCase {
+    pub const ALL: [NounCase; 7] = [
+        NounCase::Nominative,
+        NounCase::Genitive,
+        NounCase::Dative,
+        NounCase::Accusative,
+        NounCase::Instrumental,
+        NounCase::Locative,
+        NounCase::Vocative,
+    ];
+    // The cases the declension questions ask about: the nominative is the form the question starts from
+    pub const ASKED: [NounCase; 6] = [
+        NounCase::Genitive,
+        NounCase::Dative,
+        NounCase::Accusative,
+        NounCase::Instrumental,
+        NounCase::Locative,
+        NounCase::Vocative,
+    ];
+
     pub fn to_ukrainian_string(&self) -> &str {
         match self {
             NounCase::Nominative => "називний",
@@ -187,30 +350,13 @@ impl NounCase {
             NounCase::Vocative => "кличний",
         }
     }
-    pub fn get_random_by_case() -> NounCase {
-        let cases = vec![
-            NounCase::Nominative,
-            NounCase::Genitive,
-            NounCase::Dative,
-            NounCase::Accusative,
-            NounCase::Instrumental,
-            NounCase::Locative,
-            NounCase::Vocative,
-        ];
-        let rand = rand::thread_rng().gen_range(0..cases.len());
-        return cases.get(rand).unwrap().clone();
-    }
-    pub fn get_random_by_case_exluding_nominative() -> NounCase {
-        let cases = vec![
-            NounCase::Genitive,
-            NounCase::Dative,
-            NounCase::Accusative,
-            NounCase::Instrumental,
-            NounCase::Locative,
-            NounCase::Vocative,
-        ];
-        let rand = rand::thread_rng().gen_range(0..cases.len());
-        return cases.get(rand).unwrap().clone();
+    pub fn get_random_by_case<R: Rng + ?Sized>(rng: &mut R) -> NounCase {
+        let rand = rng.gen_range(0..Self::ALL.len());
+        Self::ALL[rand].clone()
+    }
+    pub fn get_random_by_case_exluding_nominative<R: Rng + ?Sized>(rng: &mut R) -> NounCase {
+        let rand = rng.gen_range(0..Self::ASKED.len());
+        Self::ASKED[rand].clone()
     }
     pub fn ukrainian_question(&self) -> &str {
         match self {
@@ -225,9 +371,44 @@ impl NounCase {
     }
 }
 
-impl Default for NounCase {
-    fn default() -> Self {
-        NounCase::Nominative
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quiz::stress::StressWord;
+
+    fn dictionary(words: &[&str]) -> StressWords {
+        StressWords {
+            words: words
+                .iter()
+                .map(|word| StressWord {
+                    word_with_stress_symbol: word.to_string(),
+                    word_without_stress_symbol: stress::remove_stress(word),
+                })
+                .collect(),
+        }
     }
-}
 
+    #[test]
+    fn stress_is_attached_only_to_the_unambiguous_forms() {
+        let noun = Noun {
+            word: "сестра".to_string(),
+            forms: vec![
+                NounForm::new("сестра", NounCase::Nominative, false),
+                NounForm::new("сестри", NounCase::Genitive, false),
+                NounForm::new("сестрі", NounCase::Dative, false),
+                NounForm::new("сестри", NounCase::Nominative, true),
+                NounForm::new("сестер", NounCase::Genitive, true),
+            ],
+        };
+        let mut declension = Declension { noun_words: vec![noun], stressed_noun_idxs: Vec::new() };
+        declension.attach_stress(&dictionary(&["сестри\u{301}", "сестрі\u{301}", "сесте\u{301}р"]));
+
+        let stressed = declension.noun_words[0]
+            .forms
+            .iter()
+            .map(|f| f.stressed.as_deref())
+            .collect::<Vec<_>>();
+        assert_eq!(stressed, vec![None, None, Some("сестрі\u{301}"), None, Some("сесте\u{301}р")]);
+        assert!(declension.has_stressed_nouns());
+    }
+}
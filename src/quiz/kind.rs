@@ -0,0 +1,583 @@
+use std::fmt;
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+use crate::config::PathsConfig;
+use crate::quiz::{
+    self, blacklist::Blacklist, declension::Declension, mastery::Mastery, parts::PartsSentences, stress,
+};
+
+pub const MAX_QUIZ_SIZE: usize = 50;
+
+// How many times we try to find a question of the chosen difficulty before taking any question
+const MAX_ATTEMPTS_PER_QUESTION: usize = 50;
+// How many questions the one suiting the learner best is picked from
+const ADAPTIVE_CANDIDATES: usize = 8;
+
+/// The quizzes the bot can generate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum QuizKind {
+    Stress,
+    PartsOfSpeech,
+    Declensions,
+    NounFormsStress,
+}
+impl QuizKind {
+    pub const ALL: [QuizKind; 4] = [
+        QuizKind::Stress,
+        QuizKind::PartsOfSpeech,
+        QuizKind::Declensions,
+        QuizKind::NounFormsStress,
+    ];
+
+    pub fn name(&self) -> &str {
+        match self {
+            QuizKind::Stress => "Наголос",
+            QuizKind::PartsOfSpeech => "Частини мови",
+            QuizKind::Declensions => "Відмінювання",
+            QuizKind::NounFormsStress => "Наголос у формах іменників",
+        }
+    }
+
+    // How the kind is stored in the database and typed in the commands
+    pub fn key(&self) -> &'static str {
+        match self {
+            QuizKind::Stress => "stress",
+            QuizKind::PartsOfSpeech => "parts",
+            QuizKind::Declensions => "declensions",
+            QuizKind::NounFormsStress => "noun_stress",
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<QuizKind> {
+        Self::ALL.into_iter().find(|k| k.key() == key)
+    }
+}
+
+/// The difficulty is judged by the length of what is asked about:
+/// the syllables of the word or the words of the sentence
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Difficulty {
+    Easy,
+    Medium,
+    Hard,
+}
+impl Difficulty {
+    pub const ALL: [Difficulty; 3] = [Difficulty::Easy, Difficulty::Medium, Difficulty::Hard];
+
+    pub fn name(&self) -> &str {
+        match self {
+            Difficulty::Easy => "легкий",
+            Difficulty::Medium => "середній",
+            Difficulty::Hard => "складний",
+        }
+    }
+
+    pub fn key(&self) -> &'static str {
+        match self {
+            Difficulty::Easy => "easy",
+            Difficulty::Medium => "medium",
+            Difficulty::Hard => "hard",
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<Difficulty> {
+        Self::ALL.into_iter().find(|d| d.key() == key)
+    }
+
+    fn of_word(word: &str) -> Difficulty {
+        match stress::vowels_count(word) {
+            0..=2 => Difficulty::Easy,
+            3 => Difficulty::Medium,
+            _ => Difficulty::Hard,
+        }
+    }
+
+    fn of_sentence(words_count: usize) -> Difficulty {
+        match words_count {
+            0..=8 => Difficulty::Easy,
+            9..=15 => Difficulty::Medium,
+            _ => Difficulty::Hard,
+        }
+    }
+}
+
+/// Everything the quiz is generated from, so the same code gives the same questions in the same order.
+/// It looks like `stress-10-any-5f3a9c21`: the kind, the size, the difficulty and the seed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuizCode {
+    pub kind: QuizKind,
+    pub size: usize,
+    pub difficulty: Option<Difficulty>,
+    pub seed: u32,
+}
+
+impl QuizCode {
+    pub fn random(kind: QuizKind, size: usize, difficulty: Option<Difficulty>) -> Self {
+        Self {
+            kind,
+            size,
+            difficulty,
+            seed: rand::random(),
+        }
+    }
+
+    pub fn parse(code: &str) -> Option<Self> {
+        let parts = code.trim().to_lowercase();
+        let [kind, size, difficulty, seed] = parts.split('-').collect::<Vec<_>>()[..] else {
+            return None;
+        };
+        let difficulty = match difficulty {
+            "any" => None,
+            difficulty => Some(Difficulty::from_key(difficulty)?),
+        };
+        Some(Self {
+            kind: QuizKind::from_key(kind)?,
+            size: size.parse().ok().filter(|size| (1..=MAX_QUIZ_SIZE).contains(size))?,
+            difficulty,
+            seed: u32::from_str_radix(seed, 16).ok()?,
+        })
+    }
+}
+
+impl fmt::Display for QuizCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}-{}-{:08x}",
+            self.kind.key(),
+            self.size,
+            self.difficulty.map(|d| d.key()).unwrap_or("any"),
+            self.seed
+        )
+    }
+}
+
+// The weight of a kind in the mixed quiz can't be more than that, so the commands stay readable
+const MAX_MIX_WEIGHT: u32 = 10;
+
+/// How many questions of each kind the mixed quiz has, relative to each other.
+/// It's written as `stress 2 parts 1 declensions 1`, the kinds which aren't mentioned aren't asked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuizMix {
+    weights: Vec<(QuizKind, u32)>,
+}
+
+impl Default for QuizMix {
+    // All the kinds equally
+    fn default() -> Self {
+        Self {
+            weights: QuizKind::ALL.iter().map(|kind| (*kind, 1)).collect(),
+        }
+    }
+}
+
+impl QuizMix {
+    pub fn parse(mix: &str) -> Option<Self> {
+        let tokens = mix.split_whitespace().collect::<Vec<_>>();
+        if tokens.is_empty() || tokens.len() % 2 != 0 {
+            return None;
+        }
+
+        let mut weights: Vec<(QuizKind, u32)> = Vec::new();
+        for pair in tokens.chunks(2) {
+            let kind = QuizKind::from_key(pair[0])?;
+            let weight = pair[1].parse::<u32>().ok().filter(|w| *w <= MAX_MIX_WEIGHT)?;
+            if weights.iter().any(|(k, _)| *k == kind) {
+                return None;
+            }
+            weights.push((kind, weight));
+        }
+        weights.retain(|(_, weight)| *weight > 0);
+        (!weights.is_empty()).then_some(Self { weights })
+    }
+
+    /// What the user sees, e.g. `Наголос -- 2, Частини мови -- 1`
+    pub fn describe(&self) -> String {
+        self.weights
+            .iter()
+            .map(|(kind, weight)| format!("{} -- {}", kind.name(), weight))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Splits `amount` questions between the kinds in proportion to their weights
+    fn split(&self, amount: usize) -> Vec<(QuizKind, usize)> {
+        let total = self.weights.iter().map(|(_, weight)| *weight as usize).sum::<usize>();
+        if total == 0 {
+            return Vec::new();
+        }
+
+        // Everyone gets the whole part of their share first, the questions left go to the biggest remainders
+        let mut counts = self
+            .weights
+            .iter()
+            .map(|(kind, weight)| (*kind, amount * *weight as usize / total, amount * *weight as usize % total))
+            .collect::<Vec<_>>();
+        let left = amount - counts.iter().map(|(_, count, _)| count).sum::<usize>();
+        let mut by_remainder = (0..counts.len()).collect::<Vec<_>>();
+        by_remainder.sort_by(|a, b| counts[*b].2.cmp(&counts[*a].2));
+        for idx in by_remainder.into_iter().take(left) {
+            counts[idx].1 += 1;
+        }
+
+        counts
+            .into_iter()
+            .filter(|(_, count, _)| *count > 0)
+            .map(|(kind, count, _)| (kind, count))
+            .collect()
+    }
+
+    fn without(&self, unavailable: impl Fn(QuizKind) -> bool) -> Self {
+        Self {
+            weights: self.weights.iter().copied().filter(|(kind, _)| !unavailable(*kind)).collect(),
+        }
+    }
+}
+
+impl fmt::Display for QuizMix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mix = self
+            .weights
+            .iter()
+            .map(|(kind, weight)| format!("{} {}", kind.key(), weight))
+            .collect::<Vec<_>>();
+        write!(f, "{}", mix.join(" "))
+    }
+}
+
+/// All the dictionaries the questions are generated from
+#[derive(Clone)]
+pub struct Datasets {
+    pub stress: Arc<stress::StressWords>,
+    pub parts: Arc<PartsSentences>,
+    pub declension: Arc<Declension>,
+    pub blacklist: Arc<Blacklist>,
+}
+
+/// Why a dictionary can't be loaded
+#[derive(Debug)]
+pub enum DatasetError {
+    Io(std::io::Error),
+    Parse(String),
+    Empty,
+    // Any of the above, in the file
+    File { path: String, error: Box<DatasetError> },
+}
+
+impl DatasetError {
+    fn in_file(self, path: &str) -> Self {
+        DatasetError::File {
+            path: path.to_string(),
+            error: Box::new(self),
+        }
+    }
+}
+
+impl fmt::Display for DatasetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DatasetError::Io(e) => write!(f, "{}", e),
+            DatasetError::Parse(message) => write!(f, "{}", message),
+            DatasetError::Empty => write!(f, "nothing to ask about"),
+            DatasetError::File { path, error } => write!(f, "failed to load '{}': {}", path, error),
+        }
+    }
+}
+
+impl std::error::Error for DatasetError {}
+
+impl From<std::io::Error> for DatasetError {
+    fn from(e: std::io::Error) -> Self {
+        DatasetError::Io(e)
+    }
+}
+
+fn load_file<T>(path: &str, load: impl FnOnce(File) -> Result<T, DatasetError>) -> Result<T, DatasetError> {
+    File::open(path)
+        .map_err(DatasetError::from)
+        .and_then(load)
+        .map_err(|e| e.in_file(path))
+}
+
+impl Datasets {
+    /// Reads all the dictionaries from the files
+    pub fn load(paths: &PathsConfig, blacklist: Arc<Blacklist>) -> Result<Self, DatasetError> {
+        let stress = load_file(&paths.stress, stress::StressWords::new)?;
+        log::info!("Loaded {} stressed words from {}", stress.words.len(), paths.stress);
+
+        let parts = load_file(&paths.treebank, PartsSentences::new)?;
+        log::info!("Loaded {} sentences from {}", parts.sentenses.len(), paths.treebank);
+
+        let mut declension = load_file(&paths.declensions, Declension::new)?;
+        // The companion file with the stressed noun forms is optional,
+        // the stress of the dictionary forms is taken from the dictionary of stressed words anyway
+        if Path::new(&paths.noun_forms_stress).exists() {
+            declension.attach_stress(&load_file(&paths.noun_forms_stress, stress::StressWords::new)?);
+        }
+        declension.attach_stress(&stress);
+        log::info!("Loaded {} nouns from {}", declension.noun_words.len(), paths.declensions);
+
+        Ok(Self {
+            stress: Arc::new(stress),
+            parts: Arc::new(parts),
+            declension: Arc::new(declension),
+            blacklist,
+        })
+    }
+    /// The mixed quiz of the day (YYYY-MM-DD), the same for everyone as long as the dictionaries are the same
+    pub fn generate_daily_quiz(&self, day: &str, amount: usize) -> Option<Vec<quiz::Question>> {
+        // The date's digits make a seed which is different every day
+        let seed = day.replace('-', "").parse::<u64>().ok()?;
+        let mut rng = StdRng::seed_from_u64(seed);
+        self.generate_mixed_quiz(&QuizMix::default(), amount, None, &mut rng)
+    }
+
+    /// Generates the quiz of the code, the same for everyone as long as the dictionaries are the same
+    pub fn generate_quiz(&self, code: &QuizCode) -> Option<Vec<quiz::Question>> {
+        let mut rng = StdRng::seed_from_u64(code.seed.into());
+        self.generate_questions(code.kind, code.size, code.difficulty, &mut rng)
+    }
+
+    /// Generates the quiz of the questions of different kinds, shuffled together.
+    /// The kinds which can't be generated at all are left out, their share goes to the others.
+    /// With the learner's `mastery` the questions are picked for the learner, see `generate_adaptive_questions`.
+    pub fn generate_mixed_quiz(
+        &self,
+        mix: &QuizMix,
+        amount: usize,
+        mastery: Option<&Mastery>,
+        rng: &mut impl Rng,
+    ) -> Option<Vec<quiz::Question>> {
+        let mix = mix.without(|kind| !self.is_available(kind));
+
+        let mut questions = Vec::new();
+        for (kind, count) in mix.split(amount) {
+            let kind_questions = match mastery {
+                Some(mastery) => self.generate_adaptive_questions(kind, count, mastery, rng),
+                None => self.generate_questions(kind, count, None, rng),
+            };
+            questions.extend(kind_questions.unwrap_or_default());
+        }
+        questions.shuffle(rng);
+        (!questions.is_empty()).then_some(questions)
+    }
+
+    /// Generates `amount` questions of the kind, each one the best for the learner of a few random ones:
+    /// of the skills the learner is weak at and of the difficulty the learner likely, but not surely, manages.
+    /// The best one is still picked at random (the better, the likelier), so the quiz isn't all about one skill.
+    pub fn generate_adaptive_questions(
+        &self,
+        kind: QuizKind,
+        amount: usize,
+        mastery: &Mastery,
+        rng: &mut impl Rng,
+    ) -> Option<Vec<quiz::Question>> {
+        if !self.is_available(kind) {
+            return None;
+        }
+
+        let mut questions = Vec::new();
+        for _ in 0..amount {
+            let candidates = (0..ADAPTIVE_CANDIDATES)
+                .filter_map(|_| self.generate_question(kind, rng))
+                .map(|(question, _)| question)
+                .collect::<Vec<_>>();
+            if let Ok(question) = candidates.choose_weighted(rng, |question| mastery.weight(question)) {
+                questions.push(question.clone());
+            }
+        }
+
+        (!questions.is_empty()).then_some(questions)
+    }
+
+    fn is_available(&self, kind: QuizKind) -> bool {
+        kind != QuizKind::NounFormsStress || self.declension.has_stressed_nouns()
+    }
+
+    /// Generates `amount` questions of the kind, preferring the ones of the `difficulty` (any if `None`).
+    /// Returns `None` if the questions of the kind can't be generated at all (e.g. no stressed noun forms).
+    pub fn generate_questions(
+        &self,
+        kind: QuizKind,
+        amount: usize,
+        difficulty: Option<Difficulty>,
+        rng: &mut impl Rng,
+    ) -> Option<Vec<quiz::Question>> {
+        if !self.is_available(kind) {
+            return None;
+        }
+
+        let mut questions = Vec::new();
+        let mut attempts = 0;
+        while questions.len() < amount && attempts < amount * MAX_ATTEMPTS_PER_QUESTION {
+            attempts += 1;
+            let Some((question, level)) = self.generate_question(kind, rng) else {
+                continue;
+            };
+            // When we are running out of attempts, any question will do
+            let runs_out = attempts > amount * MAX_ATTEMPTS_PER_QUESTION / 2;
+            if difficulty.is_none() || difficulty == Some(level) || runs_out {
+                questions.push(question);
+            }
+        }
+
+        (!questions.is_empty()).then_some(questions)
+    }
+
+    // The question comes tagged with its kind and difficulty
+    fn generate_question(&self, kind: QuizKind, rng: &mut impl Rng) -> Option<(quiz::Question, Difficulty)> {
+        let (question, level) = match kind {
+            QuizKind::Stress => {
+                let word = self.stress.get_random_word(rng)?;
+                Some((word.generate_question(rng), Difficulty::of_word(&word.word_without_stress_symbol)))
+            }
+            QuizKind::PartsOfSpeech => {
+                let sentence = self.parts.get_random_sentence(rng);
+                Some((sentence.generate_question(rng), Difficulty::of_sentence(sentence.words_count())))
+            }
+            QuizKind::Declensions => {
+                let noun = self.declension.get_random_noun(rng);
+                let question = noun.generate_question_out_of_noun(rng).ok()?;
+                Some((question, Difficulty::of_word(&noun.word)))
+            }
+            QuizKind::NounFormsStress => {
+                let noun = self.declension.get_random_noun_with_stress(rng)?;
+                let question = noun.generate_stress_question(rng).ok()?;
+                Some((question, Difficulty::of_word(&noun.word)))
+            }
+        }?;
+        if question.source.as_ref().is_some_and(|source| self.blacklist.contains(source)) {
+            return None;
+        }
+        Some((question.of_kind(kind).at_level(level), level))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STRESS_WORDS: &str = "моло\u{301}ко\nкварта\u{301}л\nдокуме\u{301}нт\nвипадко\u{301}вий\nоди\u{301}надцять\n";
+    const DECLENSIONS: &str = r#"[
+        {"word": "книга", "pos": "noun", "forms": {
+            "nom ns": ["книга"], "gen ns": ["книги"], "dat ns": ["книзі"], "acc ns": ["книгу"],
+            "ins ns": ["книгою"], "loc ns": ["книзі"], "voc ns": ["книго"],
+            "nom np": ["книги"], "gen np": ["книг"], "dat np": ["книгам"], "acc np": ["книги"],
+            "ins np": ["книгами"], "loc np": ["книгах"], "voc np": ["книги"]
+        }},
+        {"word": "місто", "pos": "noun", "forms": {
+            "nom ns": ["місто"], "gen ns": ["міста"], "dat ns": ["місту"], "acc ns": ["місто"],
+            "ins ns": ["містом"], "loc ns": ["місті"], "voc ns": ["місто"],
+            "nom np": ["міста"], "gen np": ["міст"], "dat np": ["містам"], "acc np": ["міста"],
+            "ins np": ["містами"], "loc np": ["містах"], "voc np": ["міста"]
+        }}
+    ]"#;
+
+    fn datasets() -> Datasets {
+        let dir = std::env::temp_dir().join(format!("datasets-{}", rand::random::<u64>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        std::fs::write(path("stress.txt"), STRESS_WORDS).unwrap();
+        std::fs::write(path("declensions.json"), DECLENSIONS).unwrap();
+        let paths = PathsConfig {
+            stress: path("stress.txt"),
+            treebank: "uk_iu-ud-dev.conllu".to_string(),
+            declensions: path("declensions.json"),
+            noun_forms_stress: path("missing.txt"),
+            ..Default::default()
+        };
+        let datasets = Datasets::load(&paths, Arc::new(Blacklist::new(Vec::new()))).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        datasets
+    }
+
+    // What the user sees of the question
+    fn shown(questions: &[quiz::Question]) -> Vec<(String, Vec<String>)> {
+        questions
+            .iter()
+            .map(|q| (q.text.clone(), q.answers.iter().map(|a| a.text.clone()).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn quiz_code_round_trips() {
+        let code = QuizCode {
+            kind: QuizKind::PartsOfSpeech,
+            size: 10,
+            difficulty: Some(Difficulty::Medium),
+            seed: 0x5f3a9c21,
+        };
+        assert_eq!(code.to_string(), "parts-10-medium-5f3a9c21");
+        assert_eq!(QuizCode::parse(&code.to_string()), Some(code));
+
+        let code = QuizCode::random(QuizKind::NounFormsStress, 5, None);
+        assert_eq!(QuizCode::parse(&code.to_string()), Some(code));
+        // Typed by hand, it may be in the capitals and with the spaces around
+        assert_eq!(QuizCode::parse(" STRESS-5-ANY-0000002A "), Some(QuizCode::parse("stress-5-any-2a").unwrap()));
+    }
+
+    #[test]
+    fn broken_quiz_codes_are_rejected() {
+        for code in [
+            "",
+            "stress-10-any",
+            "stress-10-any-1-2",
+            "grammar-10-any-1",
+            "stress-0-any-1",
+            "stress-51-any-1",
+            "stress-10-extreme-1",
+            "stress-10-any-xyz",
+        ] {
+            assert_eq!(QuizCode::parse(code), None, "{}", code);
+        }
+    }
+
+    #[test]
+    fn the_same_code_gives_the_same_questions() {
+        let datasets = datasets();
+        for kind in [QuizKind::Stress, QuizKind::PartsOfSpeech, QuizKind::Declensions] {
+            let code = QuizCode::random(kind, 10, None);
+            let first = datasets.generate_quiz(&code).unwrap();
+            let second = datasets.generate_quiz(&code).unwrap();
+            assert_eq!(first.len(), 10);
+            assert_eq!(shown(&first), shown(&second), "{}", code);
+        }
+    }
+
+    #[test]
+    fn quiz_mix_parses() {
+        let mix = QuizMix::parse("stress 2 parts 1 declensions 0").unwrap();
+        // The kinds of the zero weight aren't asked
+        assert_eq!(mix.to_string(), "stress 2 parts 1");
+        assert_eq!(QuizMix::parse(&mix.to_string()), Some(mix));
+
+        for mix in ["", "stress", "stress 2 parts", "stress two", "grammar 1", "stress 11", "stress 1 stress 2", "stress 0"] {
+            assert_eq!(QuizMix::parse(mix), None, "{}", mix);
+        }
+    }
+
+    #[test]
+    fn quiz_mix_splits_in_proportion() {
+        let mix = QuizMix::parse("stress 2 parts 1 declensions 1").unwrap();
+        assert_eq!(
+            mix.split(8),
+            vec![(QuizKind::Stress, 4), (QuizKind::PartsOfSpeech, 2), (QuizKind::Declensions, 2)]
+        );
+        // The questions left over go to the biggest shares
+        assert_eq!(
+            mix.split(5),
+            vec![(QuizKind::Stress, 3), (QuizKind::PartsOfSpeech, 1), (QuizKind::Declensions, 1)]
+        );
+        // The kinds left without a question aren't there at all
+        assert_eq!(mix.split(1), vec![(QuizKind::Stress, 1)]);
+
+        for amount in 0..=MAX_QUIZ_SIZE {
+            let total = QuizMix::default().split(amount).iter().map(|(_, count)| count).sum::<usize>();
+            assert_eq!(total, amount);
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+
+use crate::quiz::{self, declension::NounCase, kind::Difficulty, stress, stress_rules};
+
+// How fast the rating follows the answers: a lot at first, when we know nothing about the learner,
+// and less and less as the answers pile up, so a single slip doesn't undo the progress
+const INITIAL_STEP: f64 = 0.8;
+const MIN_STEP: f64 = 0.15;
+const STEP_HALVING_ANSWERS: f64 = 10.0;
+// The rating can't get so far that a few answers can't bring it back
+const MAX_RATING: f64 = 4.0;
+
+// The chance of the right answer the questions are picked for: hard enough to learn something,
+// easy enough not to give up
+const TARGET_SUCCESS_CHANCE: f64 = 0.7;
+const SUCCESS_CHANCE_SPREAD: f64 = 0.15;
+
+/// What the question checks, the mastery is estimated for each skill separately
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Skill {
+    // The stress of the words the rule (see `stress_rules`) is about
+    StressRule(String),
+    // The stress no rule explains, by the syllable from the end it falls on (4 for the 4th and further)
+    StressSyllable(usize),
+    // The part of speech, as the answer names it
+    PartOfSpeech(String),
+    Case(NounCase),
+}
+
+impl Skill {
+    /// The skill of the word's stress (with the stress symbol)
+    pub fn of_stress(word_with_stress_symbol: &str) -> Skill {
+        if let Some(rule) = stress_rules::find_rule(word_with_stress_symbol) {
+            return Skill::StressRule(rule.key());
+        }
+        // The vowels after the stressed one, plus the stressed one itself
+        let syllable = word_with_stress_symbol
+            .split('\u{0301}')
+            .nth(1)
+            .map(|after| stress::vowels_count(after) + 1)
+            .unwrap_or(1);
+        Skill::StressSyllable(syllable.min(4))
+    }
+
+    // How the skill is stored in the database
+    pub fn key(&self) -> String {
+        match self {
+            Skill::StressRule(rule) => format!("stress_rule:{}", rule),
+            Skill::StressSyllable(syllable) => format!("stress_syllable:{}", syllable),
+            Skill::PartOfSpeech(part) => format!("part:{}", part),
+            Skill::Case(case) => format!("case:{:?}", case),
+        }
+    }
+}
+
+/// The learner's estimated mastery of a skill on the logistic scale of the item response theory:
+/// 0 is a medium question answered right half the time (apart from guessing)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SkillRating {
+    pub rating: f64,
+    pub answers: u32,
+}
+
+impl SkillRating {
+    /// Moves the rating towards the answer, as much as the answer is a surprise (Elo-style)
+    pub fn updated(self, question: &quiz::Question, correct: bool) -> Self {
+        let expected = success_chance(self.rating, question);
+        let step = (INITIAL_STEP / (1.0 + f64::from(self.answers) / STEP_HALVING_ANSWERS)).max(MIN_STEP);
+        Self {
+            rating: (self.rating + step * (f64::from(u8::from(correct)) - expected)).clamp(-MAX_RATING, MAX_RATING),
+            answers: self.answers + 1,
+        }
+    }
+}
+
+// The difficulty of the question on the same scale as the rating
+fn item_difficulty(level: Option<Difficulty>) -> f64 {
+    match level {
+        Some(Difficulty::Easy) => -1.0,
+        Some(Difficulty::Medium) | None => 0.0,
+        Some(Difficulty::Hard) => 1.0,
+    }
+}
+
+/// The chance the learner of the `rating` answers the question right, guessing included
+pub fn success_chance(rating: f64, question: &quiz::Question) -> f64 {
+    let guess = 1.0 / question.answers.len().max(1) as f64;
+    let knows = 1.0 / (1.0 + (item_difficulty(question.level) - rating).exp());
+    guess + (1.0 - guess) * knows
+}
+
+/// Everything we know about the learner's skills, the skills never asked about are of the rating 0
+#[derive(Debug, Clone, Default)]
+pub struct Mastery {
+    ratings: HashMap<String, SkillRating>,
+}
+
+impl Mastery {
+    pub fn new(ratings: HashMap<String, SkillRating>) -> Self {
+        Self { ratings }
+    }
+
+    fn rating(&self, question: &quiz::Question) -> f64 {
+        question
+            .skill
+            .as_ref()
+            .and_then(|skill| self.ratings.get(&skill.key()))
+            .map(|r| r.rating)
+            .unwrap_or_default()
+    }
+
+    /// How much the question suits the learner: the weaker the skill and the closer
+    /// the chance of the right answer to the target, the bigger. Always positive.
+    pub fn weight(&self, question: &quiz::Question) -> f64 {
+        let rating = self.rating(question);
+        let weakness = (-rating).exp();
+        let miss = (success_chance(rating, question) - TARGET_SUCCESS_CHANCE) / SUCCESS_CHANCE_SPREAD;
+        weakness * (-miss * miss / 2.0).exp()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn question(skill: Skill, level: Difficulty) -> quiz::Question {
+        let answers = vec![quiz::Answer::new("так".to_string(), true), quiz::Answer::new("ні".to_string(), false)];
+        quiz::Question::new("?".to_string(), answers).with_skill(skill).at_level(level)
+    }
+
+    fn case_question(level: Difficulty) -> quiz::Question {
+        question(Skill::Case(NounCase::Dative), level)
+    }
+
+    #[test]
+    fn the_rating_follows_the_answers() {
+        let question = case_question(Difficulty::Medium);
+        let right = SkillRating::default().updated(&question, true);
+        let wrong = SkillRating::default().updated(&question, false);
+        assert!(right.rating > 0.0);
+        assert!(wrong.rating < 0.0);
+        assert_eq!((right.answers, wrong.answers), (1, 1));
+    }
+
+    #[test]
+    fn the_expected_answers_move_the_rating_less() {
+        // The easy question is answered right by most, the hard one is a surprise
+        let easy = SkillRating::default().updated(&case_question(Difficulty::Easy), true);
+        let hard = SkillRating::default().updated(&case_question(Difficulty::Hard), true);
+        assert!(hard.rating > easy.rating);
+    }
+
+    #[test]
+    fn the_steps_get_smaller_with_the_answers() {
+        let question = case_question(Difficulty::Medium);
+        let fresh = SkillRating::default();
+        let experienced = SkillRating { rating: 0.0, answers: 100 };
+        assert!(fresh.updated(&question, true).rating > experienced.updated(&question, true).rating);
+    }
+
+    #[test]
+    fn the_rating_is_bounded() {
+        let question = case_question(Difficulty::Hard);
+        let rating = (0..1000).fold(SkillRating::default(), |rating, _| rating.updated(&question, true));
+        assert!(rating.rating <= MAX_RATING);
+        let rating = (0..1000).fold(SkillRating::default(), |rating, _| rating.updated(&question, false));
+        assert!(rating.rating >= -MAX_RATING);
+    }
+
+    #[test]
+    fn the_weak_skills_weigh_more() {
+        let question = case_question(Difficulty::Medium);
+        let skill_key = question.skill.as_ref().unwrap().key();
+        let strong = Mastery::new(HashMap::from([(skill_key.clone(), SkillRating { rating: 2.0, answers: 20 })]));
+        let weak = Mastery::new(HashMap::from([(skill_key, SkillRating { rating: -1.0, answers: 20 })]));
+        assert!(weak.weight(&question) > strong.weight(&question));
+        assert!(strong.weight(&question) > 0.0);
+    }
+
+    #[test]
+    fn the_questions_of_the_right_difficulty_weigh_more() {
+        // The strong learner gets little from the easy questions
+        let skill_key = case_question(Difficulty::Easy).skill.unwrap().key();
+        let mastery = Mastery::new(HashMap::from([(skill_key, SkillRating { rating: 2.0, answers: 20 })]));
+        assert!(mastery.weight(&case_question(Difficulty::Hard)) > mastery.weight(&case_question(Difficulty::Easy)));
+    }
+}
@@ -1,10 +1,49 @@
+pub mod ai_cache;
 pub mod ai_helper;
+pub mod blacklist;
+pub mod declension;
+pub mod kind;
+pub mod mastery;
 pub mod parts;
+pub mod registry;
 pub mod stress;
+pub mod stress_rules;
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use declension::NounCase;
+use kind::{Difficulty, QuizKind};
+use mastery::Skill;
 
 #[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
 pub struct Quiz {
+    // Tells the answer buttons of this quiz from the ones left from the previous quizzes
+    #[serde(default)]
+    pub id: u64,
     pub questions: Vec<Question>,
+    // The poll the current question is sent as, if the user answers with the Telegram quiz polls
+    #[serde(default)]
+    pub poll_id: Option<String>,
+    // The homework the quiz is taken for, the result goes to the teacher
+    #[serde(default)]
+    pub assignment_id: Option<i64>,
+    // The day (YYYY-MM-DD) of the daily challenge the quiz is, the result goes to the streak
+    #[serde(default)]
+    pub daily: Option<String>,
+    // Seconds to answer each question in, `None` if the quiz isn't timed
+    #[serde(default)]
+    pub time_limit: Option<u64>,
+    // When the current question was sent (Unix time in milliseconds)
+    #[serde(default)]
+    pub question_sent_at: Option<u64>,
+    // How long each answered question took to answer, in milliseconds
+    #[serde(default)]
+    pub response_times: Vec<u64>,
+    // Whether each question was answered correctly, in order; the skipped ones are the wrong ones
+    #[serde(default)]
+    pub results: Vec<bool>,
+    #[serde(default)]
+    pub mistakes: Vec<Mistake>,
     pub current_question: usize,
     pub score: u32,
 }
@@ -12,21 +51,206 @@ pub struct Quiz {
 impl Quiz {
     pub fn new(questions: Vec<Question>) -> Self {
         Self {
+            id: rand::random(),
             questions,
+            poll_id: None,
+            assignment_id: None,
+            daily: None,
+            time_limit: None,
+            question_sent_at: None,
+            response_times: Vec::new(),
+            results: Vec::new(),
+            mistakes: Vec::new(),
             current_question: 0,
             score: 0,
         }
     }
+
+    pub fn for_assignment(mut self, assignment_id: i64) -> Self {
+        self.assignment_id = Some(assignment_id);
+        self
+    }
+
+    pub fn for_daily(mut self, day: String) -> Self {
+        self.daily = Some(day);
+        self
+    }
+
+    pub fn with_time_limit(mut self, time_limit: Option<u64>) -> Self {
+        self.time_limit = time_limit;
+        self
+    }
+
+    /// Remembers how long the current question took to answer
+    pub fn record_response_time(&mut self) {
+        if let Some(sent_at) = self.question_sent_at {
+            self.response_times.push(now_millis().saturating_sub(sent_at));
+        }
+    }
+
+    /// Remembers how the question is answered, `None` if it is skipped or the time is up
+    pub fn record_answer(&mut self, question_number: usize, answer: Option<&Answer>) {
+        let correct = answer.is_some_and(|a| a.is_correct);
+        self.results.push(correct);
+        if !correct {
+            self.mistakes.push(Mistake {
+                question_number,
+                answer: answer.map(|a| a.text.clone()),
+            });
+        }
+    }
+
+    /// How many questions of each kind are answered correctly and how many of them there are,
+    /// for the kinds the quiz has, in the order of `QuizKind::ALL`
+    pub fn scores_by_kind(&self) -> Vec<(QuizKind, usize, usize)> {
+        QuizKind::ALL
+            .iter()
+            .filter_map(|kind| {
+                let (score, total) = self
+                    .questions
+                    .iter()
+                    .zip(self.results.iter().copied().chain(std::iter::repeat(false)))
+                    .filter(|(question, _)| question.kind == Some(*kind))
+                    .fold((0, 0), |(score, total), (_, correct)| (score + usize::from(correct), total + 1));
+                (total > 0).then_some((*kind, score, total))
+            })
+            .collect()
+    }
+
+    /// The key of the kind of all the questions, or "mixed" if there are several kinds
+    pub fn kind_key(&self) -> &'static str {
+        match self.scores_by_kind().as_slice() {
+            [(kind, _, _)] => kind.key(),
+            _ => "mixed",
+        }
+    }
+
+    /// `None` if no question is answered yet (e.g. all of them timed out)
+    pub fn average_response_time(&self) -> Option<Duration> {
+        let total = self.response_times.iter().sum::<u64>();
+        let count = u64::try_from(self.response_times.len()).ok().filter(|c| *c > 0)?;
+        Some(Duration::from_millis(total / count))
+    }
+}
+
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// The question answered wrongly, to review and redo it after the quiz
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Mistake {
+    pub question_number: usize,
+    // What the user has answered, `None` if the question is skipped or the time is up
+    pub answer: Option<String>,
 }
 
 #[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
 pub struct Question {
     pub text: String,
     pub answers: Vec<Answer>,
+    // Why the correct answer is correct, without the AI
+    #[serde(default)]
+    pub explanation: Option<String>,
+    // The quizzes of the different kinds are asked and explained differently, see the mixed quiz
+    #[serde(default)]
+    pub kind: Option<QuizKind>,
+    // What the question checks and how hard it is, to estimate the learner's mastery
+    #[serde(default)]
+    pub skill: Option<Skill>,
+    #[serde(default)]
+    pub level: Option<Difficulty>,
+    // The dictionary item the question is made of, to report it if it's wrong
+    #[serde(default)]
+    pub source: Option<QuestionSource>,
 }
 impl Question {
     pub fn new(text: String, answers: Vec<Answer>) -> Self {
-        Self { text, answers }
+        Self {
+            text,
+            answers,
+            explanation: None,
+            kind: None,
+            skill: None,
+            level: None,
+            source: None,
+        }
+    }
+
+    pub fn with_explanation(mut self, explanation: String) -> Self {
+        self.explanation = Some(explanation);
+        self
+    }
+
+    pub fn of_kind(mut self, kind: QuizKind) -> Self {
+        self.kind = Some(kind);
+        self
+    }
+
+    pub fn with_skill(mut self, skill: Skill) -> Self {
+        self.skill = Some(skill);
+        self
+    }
+
+    pub fn at_level(mut self, level: Difficulty) -> Self {
+        self.level = Some(level);
+        self
+    }
+
+    pub fn made_of(mut self, source: QuestionSource) -> Self {
+        self.source = Some(source);
+        self
+    }
+}
+
+/// Where in the dictionaries the question comes from
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum QuestionSource {
+    // The line of the dictionary of stressed words
+    StressWord(String),
+    // The word of the treebank sentence, the form is only to show it
+    Token {
+        sent_id: String,
+        token_id: String,
+        form: String,
+    },
+    // The form of the noun from the declension dictionary
+    NounForm {
+        lemma: String,
+        case: NounCase,
+        is_plural: bool,
+    },
+}
+
+impl QuestionSource {
+    // How the item is stored in the blacklist
+    pub fn key(&self) -> String {
+        match self {
+            QuestionSource::StressWord(word) => format!("stress:{}", word),
+            QuestionSource::Token { sent_id, token_id, .. } => format!("token:{}:{}", sent_id, token_id),
+            QuestionSource::NounForm { lemma, case, is_plural } => {
+                format!("noun:{}:{:?}:{}", lemma, case, if *is_plural { "plural" } else { "singular" })
+            }
+        }
+    }
+
+    /// What the admins see when reviewing the reports
+    pub fn describe(&self) -> String {
+        match self {
+            QuestionSource::StressWord(word) => format!("словник наголосів, рядок «{}»", word),
+            QuestionSource::Token { sent_id, token_id, form } => {
+                format!("трибанк, речення {}, слово №{} «{}»", sent_id, token_id, form)
+            }
+            QuestionSource::NounForm { lemma, case, is_plural } => format!(
+                "словник відмінювання, іменник «{}», {} відмінок {}",
+                lemma,
+                case.to_ukrainian_string(),
+                if *is_plural { "множини" } else { "однини" }
+            ),
+        }
     }
 }
 
@@ -1,6 +1,9 @@
 use std::fs::File;
+use std::io::Read;
 
 use crate::quiz;
+use crate::quiz::kind::DatasetError;
+use crate::quiz::mastery::Skill;
 use rand::prelude::*;
 use rand::Rng;
 
@@ -9,52 +12,83 @@ pub struct PartsSentences {
 }
 
 impl PartsSentences {
-    pub fn new(file: File) -> Self {
-        let conllu_doc: Vec<PartsSentence> = rs_conllu::parse_file(file)
-            .filter(|sentence| sentence.is_ok())
-            // We can unwrap safely here because we've already filtered out the errors
-            .map(|sentence| sentence.unwrap())
-            .map(|sentence| PartsSentence::new(sentence))
-            .collect();
-        Self {
-            sentenses: conllu_doc,
+    pub fn new(mut file: File) -> Result<Self, DatasetError> {
+        // The parser panics on the text which isn't UTF-8, so it's read (and checked) first
+        let mut text = String::new();
+        file.read_to_string(&mut text)?;
+        // A broken sentence is left out, the rest of the treebank is still fine
+        let conllu_doc = rs_conllu::parsers::Doc::new(text.as_bytes())
+            .filter_map(|sentence| {
+                let sentence = sentence
+                    .map_err(|e| DatasetError::Parse(e.to_string()))
+                    .and_then(PartsSentence::new);
+                if let Err(e) = &sentence {
+                    log::warn!("Skipping the sentence of the treebank: {}", e);
+                }
+                sentence.ok()
+            })
+            .collect::<Vec<_>>();
+        if conllu_doc.is_empty() {
+            return Err(DatasetError::Empty);
         }
+        Ok(Self {
+            sentenses: conllu_doc,
+        })
     }
-    pub fn get_random_sentence(&self) -> &PartsSentence {
-        let rand = rand::thread_rng().gen_range(0..self.sentenses.len());
+    pub fn get_random_sentence<R: Rng + ?Sized>(&self, rng: &mut R) -> &PartsSentence {
+        let rand = rng.gen_range(0..self.sentenses.len());
         let rand_sentence = self.sentenses.get(rand).unwrap();
-        return rand_sentence;
+        rand_sentence
     }
 }
 
 pub struct PartsSentence {
     pub sentence: rs_conllu::Sentence,
+    // The original text of the sentence, from the metadata
+    pub text: String,
 }
 
 impl PartsSentence {
-    pub fn new(sentence: rs_conllu::Sentence) -> Self {
-        Self { sentence }
+    // Every sentence needs its text and a word to ask about
+    pub fn new(sentence: rs_conllu::Sentence) -> Result<Self, DatasetError> {
+        let sent_id = sentence.meta.iter().find_map(|m| m.strip_prefix("sent_id = ")).unwrap_or("?");
+        let text = sentence
+            .meta
+            .iter()
+            .find_map(|m| m.strip_prefix("text = "))
+            .ok_or_else(|| DatasetError::Parse(format!("the sentence {} has no 'text' metadata field", sent_id)))?
+            .to_string();
+        if !sentence.tokens.iter().any(|t| t.upos != Some(rs_conllu::UPOS::PUNCT)) {
+            return Err(DatasetError::Parse(format!("the sentence {} has no words", sent_id)));
+        }
+        Ok(Self { sentence, text })
     }
-    pub fn generate_question(&self) -> quiz::Question {
-        generate_question_out_of_sentence(&self.sentence)
+    pub fn generate_question<R: Rng + ?Sized>(&self, rng: &mut R) -> quiz::Question {
+        generate_question_out_of_sentence(&self.sentence, &self.text, rng)
+    }
+
+    // Without the punctuation
+    pub fn words_count(&self) -> usize {
+        self.sentence
+            .tokens
+            .iter()
+            .filter(|t| t.upos != Some(rs_conllu::UPOS::PUNCT))
+            .count()
     }
 }
-fn generate_question_out_of_sentence(sentence: &rs_conllu::Sentence) -> quiz::Question {
+fn generate_question_out_of_sentence<R: Rng + ?Sized>(
+    sentence: &rs_conllu::Sentence,
+    text_sentence: &str,
+    rng: &mut R,
+) -> quiz::Question {
     let words_to_be_asked_about = sentence
         .tokens
         .iter()
         .filter(|t| t.upos != Some(rs_conllu::UPOS::PUNCT))
         .collect::<Vec<_>>();
     let random_word = words_to_be_asked_about
-        .choose(&mut rand::thread_rng())
+        .choose(rng)
         .unwrap();
-    let text_sentence = sentence
-        .meta
-        .iter()
-        .find(|m| m.starts_with("text = "))
-        .map(|m| m.replace("text = ", ""))
-        .expect("Original 'text' metadata field not found on the sentence");
-
     let correct_answer = match random_word.upos {
         Some(rs_conllu::UPOS::ADJ) => "прикметник",
         Some(rs_conllu::UPOS::ADV) => "прислівник",
@@ -104,7 +138,7 @@ fn generate_question_out_of_sentence(sentence: &rs_conllu::Sentence) -> quiz::Qu
     let incorrect_answer = possible_answers
         .iter()
         .filter(|a| a != &&correct_answer)
-        .choose(&mut rand::thread_rng())
+        .choose(rng)
         .unwrap();
 
     let answers = {
@@ -112,7 +146,7 @@ fn generate_question_out_of_sentence(sentence: &rs_conllu::Sentence) -> quiz::Qu
             quiz::Answer::new(correct_answer.to_string(), true),
             quiz::Answer::new(incorrect_answer.to_string(), false),
         ];
-        shuffled_answers.shuffle(&mut rand::thread_rng());
+        shuffled_answers.shuffle(rng);
         // returns
         shuffled_answers
     };
@@ -129,7 +163,7 @@ fn generate_question_out_of_sentence(sentence: &rs_conllu::Sentence) -> quiz::Qu
             if no_sep == random_word.form {
                 return format!("<b><u>{}</u></b>", word);
             }
-            return word.to_string();
+            word.to_string()
         })
         .collect::<Vec<String>>()
         .join(" ");
@@ -138,5 +172,91 @@ fn generate_question_out_of_sentence(sentence: &rs_conllu::Sentence) -> quiz::Qu
         "У реченні:\n\"{}\"\n\nЯкою частиною мови є підкреслене слово \"{}\"?",
         text_sentence, random_word.form
     );
-    return quiz::Question::new(question_text, answers);
+    let mut question = quiz::Question::new(question_text, answers).with_skill(Skill::PartOfSpeech(correct_answer.to_string()));
+    let sent_id = sentence.meta.iter().find_map(|m| m.strip_prefix("sent_id = "));
+    if let Some(sent_id) = sent_id {
+        question = question.made_of(quiz::QuestionSource::Token {
+            sent_id: sent_id.to_string(),
+            token_id: token_id(&random_word.id),
+            form: random_word.form.clone(),
+        });
+    }
+    match part_of_speech_hint(&random_word.upos) {
+        Some(hint) => question.with_explanation(hint.to_string()),
+        None => question,
+    }
+}
+
+// As it's written in the CoNLL-U file
+fn token_id(id: &rs_conllu::TokenID) -> String {
+    match id {
+        rs_conllu::TokenID::Single(id) => id.to_string(),
+        rs_conllu::TokenID::Range(start, end) => format!("{}-{}", start, end),
+        rs_conllu::TokenID::Subordinate { major, minor } => format!("{}.{}", major, minor),
+    }
+}
+
+// What the part of speech is and which questions it answers
+fn part_of_speech_hint(upos: &Option<rs_conllu::UPOS>) -> Option<&'static str> {
+    let hint = match upos {
+        Some(rs_conllu::UPOS::ADJ) => "Прикметник називає ознаку предмета і відповідає на питання який? чий?",
+        Some(rs_conllu::UPOS::ADV) => "Прислівник називає ознаку дії чи іншої ознаки і відповідає на питання як? де? коли?",
+        Some(rs_conllu::UPOS::INTJ) => "Вигук виражає почуття чи волевиявлення, але нічого не називає",
+        Some(rs_conllu::UPOS::NOUN) => "Іменник називає предмет і відповідає на питання хто? що?",
+        Some(rs_conllu::UPOS::PROPN) => "Власний іменник -- це назва окремого предмета: ім'я, прізвище, географічна назва",
+        Some(rs_conllu::UPOS::VERB) => "Дієслово називає дію чи стан і відповідає на питання що робити? що зробити?",
+
+        Some(rs_conllu::UPOS::PRON) => "Займенник вказує на предмет, ознаку чи кількість, не називаючи їх",
+        Some(rs_conllu::UPOS::ADP) => "Прийменник -- службове слово, що пов'язує слова в реченні: у, на, з, до",
+        Some(rs_conllu::UPOS::CCONJ) => "Сурядний сполучник поєднує рівноправні частини речення: і, а, але",
+        Some(rs_conllu::UPOS::SCONJ) => "Підрядний сполучник приєднує залежну частину речення: що, як, бо, коли",
+        Some(rs_conllu::UPOS::AUX) => "Допоміжне дієслово допомагає утворити форму іншого дієслова: буду читати",
+        Some(rs_conllu::UPOS::DET) => "Детермінатив уточнює іменник, як-от вказівні та присвійні слова: цей, мій, кожен",
+        Some(rs_conllu::UPOS::NUM) => "Числівник називає кількість чи порядок при лічбі і відповідає на питання скільки? котрий?",
+        Some(rs_conllu::UPOS::PART) => "Частка надає слову чи реченню додаткового відтінку: не, лише, навіть",
+
+        _ => return None,
+    };
+    Some(hint)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(text: &[u8]) -> Result<PartsSentences, DatasetError> {
+        let path = std::env::temp_dir().join(format!("parts-{}.conllu", rand::random::<u64>()));
+        std::fs::write(&path, text).unwrap();
+        let result = PartsSentences::new(File::open(&path).unwrap());
+        std::fs::remove_file(&path).unwrap();
+        result
+    }
+
+    #[test]
+    fn the_treebank_loads() {
+        let parts = PartsSentences::new(File::open("uk_iu-ud-dev.conllu").unwrap()).unwrap();
+        assert!(!parts.sentenses.is_empty());
+    }
+
+    #[test]
+    fn the_broken_sentences_are_skipped() {
+        let good = "# sent_id = 1\n# text = Слово.\n1\tСлово\tслово\tNOUN\t_\t_\t0\troot\t_\t_\n2\t.\t.\tPUNCT\t_\t_\t1\tpunct\t_\t_\n\n";
+        let without_text = "# sent_id = 2\n1\tСлово\tслово\tNOUN\t_\t_\t0\troot\t_\t_\n\n";
+        let only_punctuation = "# sent_id = 3\n# text = .\n1\t.\t.\tPUNCT\t_\t_\t0\troot\t_\t_\n\n";
+        let unparsable = "# sent_id = 4\n# text = Слово\nодин\tрядок\n\n";
+
+        let text = format!("{}{}{}{}", without_text, unparsable, good, only_punctuation);
+        let parts = load(text.as_bytes()).unwrap();
+        assert_eq!(parts.sentenses.len(), 1);
+        assert_eq!(parts.sentenses[0].text, "Слово.");
+    }
+
+    #[test]
+    fn the_treebank_without_sentences_is_an_error() {
+        let without_text = "# sent_id = 1\n1\tСлово\tслово\tNOUN\t_\t_\t0\troot\t_\t_\n\n";
+        assert!(matches!(load(without_text.as_bytes()), Err(DatasetError::Empty)));
+        assert!(matches!(load(b""), Err(DatasetError::Empty)));
+        // Not UTF-8
+        assert!(matches!(load(&[0xff, 0xfe, 0x00]), Err(DatasetError::Io(_))));
+    }
 }
@@ -0,0 +1,73 @@
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use arc_swap::ArcSwap;
+
+use crate::config::PathsConfig;
+use crate::quiz::kind::{DatasetError, Datasets};
+
+const WATCH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// All the dictionaries the bot is using now. They are swapped as a whole, atomically,
+/// when the files change or an admin asks to (`/reload`): the handlers running already keep the ones
+/// they have started with, the quizzes going on keep their questions, the new ones use the new dictionaries.
+pub struct DataRegistry {
+    datasets: ArcSwap<Datasets>,
+    // The files the dictionaries are read from
+    paths: PathsConfig,
+}
+
+impl DataRegistry {
+    pub fn new(datasets: Datasets, paths: PathsConfig) -> Self {
+        Self {
+            datasets: ArcSwap::from_pointee(datasets),
+            paths,
+        }
+    }
+
+    pub fn datasets(&self) -> Datasets {
+        Datasets::clone(&self.datasets.load())
+    }
+
+    /// Reads the dictionaries anew, the blacklist stays the same.
+    /// If a file can't be read, the dictionaries in use are kept.
+    pub async fn reload(&self) -> Result<(), DatasetError> {
+        let blacklist = self.datasets.load().blacklist.clone();
+        // The files are read on this worker, the others go on with the updates meanwhile
+        let datasets = tokio::task::block_in_place(|| Datasets::load(&self.paths, blacklist))?;
+        self.datasets.store(Arc::new(datasets));
+        Ok(())
+    }
+
+    /// Reloads the dictionaries when the files change, while the bot is running.
+    /// The files are reloaded once they haven't changed for a whole interval, so a file being written isn't read.
+    pub fn watch_files(self: Arc<Self>) {
+        tokio::spawn(async move {
+            let mut loaded = modification_times(&self.paths);
+            let mut previous = loaded.clone();
+            loop {
+                tokio::time::sleep(WATCH_INTERVAL).await;
+                let current = modification_times(&self.paths);
+                if current != loaded && current == previous {
+                    log::info!("The dictionary files have changed, reloading them");
+                    match self.reload().await {
+                        Ok(()) => log::info!("The dictionaries are reloaded"),
+                        Err(e) => log::error!("Failed to reload the dictionaries: {}", e),
+                    }
+                    // The broken files aren't retried until they change again
+                    loaded = current.clone();
+                }
+                previous = current;
+            }
+        });
+    }
+}
+
+// `None` for the missing files (e.g. the optional ones)
+fn modification_times(paths: &PathsConfig) -> Vec<Option<SystemTime>> {
+    paths
+        .dictionaries()
+        .iter()
+        .map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+        .collect()
+}
@@ -1,47 +1,44 @@
 use rand::seq::SliceRandom;
-use rand::thread_rng;
 use rand::Rng;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
 
 use crate::quiz;
+use crate::quiz::kind::DatasetError;
+use crate::quiz::mastery::Skill;
+use crate::quiz::stress_rules;
 
 #[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
 pub struct StressWords {
     pub words: Vec<StressWord>,
 }
 
+// The random words are drawn until an askable one, the dictionary only has a few of the others
+const MAX_RANDOM_WORD_ATTEMPTS: usize = 100;
+
 impl StressWords {
-    pub fn new(file: File) -> Self {
+    pub fn new(file: File) -> Result<Self, DatasetError> {
         let mut words: Vec<StressWord> = Vec::new();
         let reader = BufReader::new(file);
 
         for line in reader.lines() {
-            let word = line.expect("Failed to read line");
-            words.push(StressWord::new(word));
+            words.push(StressWord::new(line?));
+        }
+        if !words.iter().any(StressWord::is_askable) {
+            return Err(DatasetError::Empty);
         }
 
-        return Self { words };
+        Ok(Self { words })
     }
-    pub fn get_random_word(&self) -> &StressWord {
-        let rand = rand::thread_rng().gen_range(0..self.words.len());
-        let rand_word = self.words.get(rand).unwrap();
-        // To avoid words with less than 2 vowels
-        // since we need to have at least 2 vowels to stress one of them (duh!)
-        let vowels_count = rand_word
-            .word_without_stress_symbol
-            .chars()
-            .filter(|c| is_vowel(*c).unwrap())
-            .count();
-        if vowels_count < 2 {
-            return self.get_random_word();
-        }
-        // To avoid phrases
-        // TODO: filter out phrases our of the dictionary source file itself
-        if rand_word.word_without_stress_symbol.contains(" ") {
-            return self.get_random_word();
+
+    /// `None` if no askable word is drawn in `MAX_RANDOM_WORD_ATTEMPTS`
+    pub fn get_random_word<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<&StressWord> {
+        if self.words.is_empty() {
+            return None;
         }
-        return rand_word;
+        (0..MAX_RANDOM_WORD_ATTEMPTS)
+            .map(|_| &self.words[rng.gen_range(0..self.words.len())])
+            .find(|word| word.is_askable())
     }
 }
 
@@ -53,94 +50,174 @@ pub struct StressWord {
 
 // Голосні букви
 const UKRAINIAN_VOWELS: [char; 10] = ['А', 'Е', 'Є', 'И', 'І', 'Ї', 'О', 'У', 'Ю', 'Я'];
-fn is_vowel(c: char) -> Result<bool, ()> {
+pub fn is_vowel(c: char) -> Result<bool, ()> {
     let c = c.to_uppercase().next();
     if c.is_none() {
         return Err(());
     }
-    return Ok(UKRAINIAN_VOWELS.contains(&c.unwrap()));
+    Ok(UKRAINIAN_VOWELS.contains(&c.unwrap()))
 }
 impl StressWord {
     fn new(word_with_stress_symbol: String) -> Self {
         let word_without_stress_symbol = Self::get_word_without_stress(&word_with_stress_symbol);
-        return Self {
+        Self {
             word_with_stress_symbol,
             word_without_stress_symbol,
-        };
+        }
     }
 
     fn get_word_without_stress(word: &str) -> String {
-        return word.chars().filter(|c| c != &'\u{0301}').collect();
+        remove_stress(word)
+    }
+
+    fn is_askable(&self) -> bool {
+        // At least 2 vowels are needed to stress one of them (duh!), and the phrases aren't asked
+        // TODO: filter out phrases our of the dictionary source file itself
+        vowels_count(&self.word_without_stress_symbol) >= 2 && !self.word_without_stress_symbol.contains(' ')
     }
 
-    pub fn generate_question(&self) -> quiz::Question {
-        let correct_stress = self.word_with_stress_symbol.clone();
+    pub fn generate_question<R: Rng + ?Sized>(&self, rng: &mut R) -> quiz::Question {
+        // `StressWords::get_random_word` only gives out words with at least 2 vowels,
+        // so there is always a place for an incorrect stress
+        let answers = generate_stress_answers(&self.word_with_stress_symbol, rng).unwrap();
+        let question = format_stress_variants(&answers);
+
+        quiz::Question::new(question, answers)
+            .with_explanation(stress_rules::explain_stress(&self.word_with_stress_symbol))
+            .with_skill(Skill::of_stress(&self.word_with_stress_symbol))
+            .made_of(quiz::QuestionSource::StressWord(self.word_with_stress_symbol.clone()))
+    }
+}
+
+pub fn remove_stress(word: &str) -> String {
+    word.chars().filter(|c| c != &'\u{0301}').collect()
+}
+
+pub fn vowels_count(word: &str) -> usize {
+    word.chars().filter(|c| is_vowel(*c).unwrap()).count()
+}
+
+/// Whether there is a vowel left to put the incorrect stress on
+pub fn can_have_incorrect_stress(word_with_stress_symbol: &str) -> bool {
+    let stresses_count = word_with_stress_symbol.chars().filter(|c| c == &'\u{0301}').count();
+    vowels_count(word_with_stress_symbol) > stresses_count
+}
+
+pub fn has_stress(word: &str) -> bool {
+    word.contains('\u{0301}')
+}
 
-        // Getting all of the indexes of the stress symbol's position (if there are multiple vowels in the word)
-        // e.g. for "програмі́ст" it would be [7], sinse the 'і' is the only stressed vowel in the word
-        // the actual \u{0301} symbol is at next, 8th position
-        let stressed_idxs = correct_stress
+/// Returns the shuffled pair of answers (correct and incorrect stress) for the stressed word,
+/// or `None` if there is no other vowel to put the incorrect stress on.
+pub fn generate_stress_answers<R: Rng + ?Sized>(
+    word_with_stress_symbol: &str,
+    rng: &mut R,
+) -> Option<Vec<quiz::Answer>> {
+    let correct_stress = word_with_stress_symbol.to_string();
+    let word_without_stress_symbol = remove_stress(word_with_stress_symbol);
+
+    // Getting all of the indexes of the stress symbol's position (if there are multiple vowels in the word)
+    // e.g. for "програмі́ст" it would be [7], sinse the 'і' is the only stressed vowel in the word
+    // the actual \u{0301} symbol is at next, 8th position
+    let stressed_idxs = correct_stress
+        .chars()
+        .enumerate()
+        .filter(|(_, c)| *c == '\u{0301}')
+        .enumerate()
+        // So here we subtract 1 from the index to get the actual position of the stressed vowel,
+        // and the stress symbols before it, since they aren't in the word without the stress
+        .map(|(seen, (i, _))| i - 1 - seen)
+        .collect::<Vec<_>>();
+
+    // Generating a word with incorrect stress
+    // Algorithm:
+    // 1. Get all of the possible locations for the stress symbol (vowels positions)
+    // 2. Remove the correct stress symbol's position from the list
+    // 3. Choose one of the remaining positions randomly
+    let incorrect_stress = {
+        let possible_locations = word_without_stress_symbol
             .chars()
             .enumerate()
-            .filter(|(_, c)| *c == '\u{0301}')
-            // So here we subtract 1 from the index to get the actual position of the stressed vowel
-            .map(|(i, _)| i - 1)
+            .filter_map(|(i, c)| {
+                if stressed_idxs.contains(&i) {
+                    return None;
+                }
+
+                if let Ok(false) = is_vowel(c) {
+                    return None;
+                }
+
+                Some(i)
+            })
             .collect::<Vec<_>>();
 
-        // Generating a word with incorrect stress
-        // Algorithm:
-        // 1. Get all of the possible locations for the stress symbol (vowels positions)
-        // 2. Remove the correct stress symbol's position from the list
-        // 3. Choose one of the remaining positions randomly
-        let incorrect_stress = {
-            let possible_locations = self
-                .word_without_stress_symbol
-                .clone()
-                .chars()
-                .enumerate()
-                .map(|(i, c)| {
-                    if stressed_idxs.contains(&i) {
-                        return None;
-                    }
-
-                    if let Ok(false) = is_vowel(c) {
-                        return None;
-                    }
-
-                    return Some(i);
-                })
-                .filter(|x| x.is_some())
-                .map(|x| x.unwrap())
-                .collect::<Vec<_>>();
-
-            let one_incorrect_stress = possible_locations.choose(&mut thread_rng()).unwrap();
-
-            let mut incorrect_stress = self
-                .word_without_stress_symbol
-                .clone()
-                .chars()
-                .collect::<Vec<char>>();
-            incorrect_stress.insert(*one_incorrect_stress + 1, '\u{0301}');
-            incorrect_stress.iter().collect::<String>()
-        };
-
-        // We shuffle the answers so the correct one isn't always the first one
-        let answers = {
-            let mut shuffled_answers = vec![
-                quiz::Answer::new(correct_stress, true),
-                quiz::Answer::new(incorrect_stress, false),
-            ];
-            shuffled_answers.shuffle(&mut rand::thread_rng());
-            // returns
-            shuffled_answers
-        };
-
-        //
-        let question = format!(
-            "<b><i>{}</i></b> чи <b><i>{}</i></b> ?",
-            answers[0].text, answers[1].text
-        );
-
-        return quiz::Question::new(question, answers);
+        let one_incorrect_stress = possible_locations.choose(rng)?;
+
+        let mut incorrect_stress = word_without_stress_symbol.chars().collect::<Vec<char>>();
+        incorrect_stress.insert(*one_incorrect_stress + 1, '\u{0301}');
+        incorrect_stress.iter().collect::<String>()
+    };
+
+    // We shuffle the answers so the correct one isn't always the first one
+    let answers = {
+        let mut shuffled_answers = vec![
+            quiz::Answer::new(correct_stress, true),
+            quiz::Answer::new(incorrect_stress, false),
+        ];
+        shuffled_answers.shuffle(rng);
+        // returns
+        shuffled_answers
+    };
+
+    Some(answers)
+}
+
+pub fn format_stress_variants(answers: &[quiz::Answer]) -> String {
+    answers
+        .iter()
+        .map(|a| format!("<b><i>{}</i></b>", a.text))
+        .collect::<Vec<_>>()
+        .join(" чи ")
+        + " ?"
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    fn words(lines: &[&str]) -> StressWords {
+        StressWords {
+            words: lines.iter().map(|line| StressWord::new(line.to_string())).collect(),
+        }
+    }
+
+    #[test]
+    fn only_askable_words_are_drawn() {
+        let words = words(&["кі\u{301}т", "моло\u{301}ко", "за\u{301}мок на две\u{301}рях", "лі\u{301}с"]);
+        let mut rng = StdRng::seed_from_u64(7);
+        for _ in 0..50 {
+            let word = words.get_random_word(&mut rng).unwrap();
+            assert_eq!(word.word_without_stress_symbol, "молоко");
+        }
+    }
+
+    #[test]
+    fn incorrect_stress_is_never_one_of_the_correct_ones() {
+        let mut rng = StdRng::seed_from_u64(7);
+        for _ in 0..50 {
+            let answers = generate_stress_answers("ві\u{301}дпові\u{301}дь", &mut rng).unwrap();
+            let incorrect = answers.iter().find(|a| !a.is_correct).unwrap();
+            // The only vowel without the stress
+            assert_eq!(incorrect.text, "відпо\u{301}відь");
+        }
+    }
+
+    #[test]
+    fn no_askable_words_give_none() {
+        let mut rng = StdRng::seed_from_u64(7);
+        assert!(words(&["кі\u{301}т", "лі\u{301}с"]).get_random_word(&mut rng).is_none());
+        assert!(words(&[]).get_random_word(&mut rng).is_none());
     }
 }
@@ -0,0 +1,218 @@
+use crate::quiz::stress;
+
+// Used when none of the rules explains the stress of the word
+pub const NO_RULE_EXPLANATION: &str = "Наголос у цьому слові не пояснюється загальним правилом, тож це варто запам'ятати.";
+
+/// Where the stress is relative to the ending the rule is about
+pub enum StressPlace {
+    // N-th (starting with 0) vowel of the ending is stressed
+    OnEnding(usize),
+    // The last vowel before the ending is stressed
+    RightBefore,
+    // Any vowel before the ending is stressed
+    Before,
+}
+
+pub struct StressRule {
+    // The ending of the word (without the stress symbol) the rule is about
+    pub ending: &'static str,
+    // What may go right before the ending, anything if it's empty;
+    // e.g. the loanwords can't be told by the ending alone
+    pub after: &'static [&'static str],
+    // The vowels needed before the ending, e.g. the -и- of жити is the root, not the suffix
+    pub min_vowels_before: usize,
+    pub place: StressPlace,
+    pub explanation: &'static str,
+}
+
+// The first rule the spelling of the word fits is the one about the word, so the more specific ones go first
+pub static STRESS_RULES: [StressRule; 9] = [
+    StressRule {
+        ending: "надцять",
+        after: &[],
+        min_vowels_before: 1,
+        place: StressPlace::OnEnding(0),
+        explanation: "У числівниках на -надцять наголос завжди падає на -на́-: одина́дцять, чотирна́дцять, п'ятна́дцять.",
+    },
+    StressRule {
+        ending: "ння",
+        after: &[],
+        min_vowels_before: 1,
+        place: StressPlace::RightBefore,
+        explanation: "Віддієслівні іменники на -ння здебільшого зберігають наголос дієслова, від якого утворені: чита́ти — чита́ння, завда́ти — завда́ння.",
+    },
+    StressRule {
+        ending: "ач",
+        after: &[],
+        min_vowels_before: 1,
+        place: StressPlace::OnEnding(0),
+        explanation: "Іменники з суфіксом -ач, що називають особу за дією, наголошуємо на суфіксі: слуха́ч, гляда́ч, сурма́ч.",
+    },
+    StressRule {
+        ending: "ер",
+        after: &["шоф", "партн", "інжен", "офіц", "режис", "костюм", "суфл", "монт", "грав"],
+        min_vowels_before: 1,
+        place: StressPlace::OnEnding(0),
+        explanation: "Слова на -ер, запозичені з французької, мають наголос на останньому складі: шофе́р, партне́р, інжене́р.",
+    },
+    StressRule {
+        ending: "ер",
+        after: &["менедж", "дил", "бухгалт", "лід", "брок", "дизайн", "принт", "тост", "комп'ют", "блог", "спік"],
+        min_vowels_before: 1,
+        place: StressPlace::Before,
+        explanation: "Слова на -ер, запозичені з англійської чи німецької, зберігають наголос мови-джерела, тобто не на -ер: ме́неджер, ди́лер, бухга́лтер.",
+    },
+    StressRule {
+        ending: "метр",
+        after: &["кіло", "санти", "мілі", "деци"],
+        min_vowels_before: 1,
+        place: StressPlace::OnEnding(0),
+        explanation: "Назви одиниць виміру на -метр наголошуємо на -ме́тр: кіломе́тр, сантиме́тр, міліме́тр.",
+    },
+    StressRule {
+        ending: "метр",
+        after: &["о"],
+        min_vowels_before: 1,
+        place: StressPlace::RightBefore,
+        explanation: "Назви приладів на -ометр мають наголос перед -метр: термо́метр, баро́метр, спідо́метр.",
+    },
+    StressRule {
+        ending: "логія",
+        after: &[],
+        min_vowels_before: 1,
+        place: StressPlace::OnEnding(0),
+        explanation: "Назви наук на -логія наголошуємо на -ло́-: біоло́гія, філоло́гія, психоло́гія.",
+    },
+    StressRule {
+        ending: "ити",
+        after: &[],
+        min_vowels_before: 1,
+        place: StressPlace::OnEnding(0),
+        explanation: "У багатьох дієсловах на -ити наголос падає на суфікс -и-: ходи́ти, носи́ти, вози́ти, проси́ти.",
+    },
+];
+
+impl StressRule {
+    // The ending alone doesn't tell the rules apart, e.g. the French and the English -ер
+    pub fn key(&self) -> String {
+        let place = match self.place {
+            StressPlace::OnEnding(n) => format!("ending{}", n),
+            StressPlace::RightBefore => "right_before".to_string(),
+            StressPlace::Before => "before".to_string(),
+        };
+        format!("{}:{}", self.ending, place)
+    }
+
+    // Whether the rule is about the word, whatever its stress is
+    fn fits(&self, word_without_stress_symbol: &[char]) -> bool {
+        let word = word_without_stress_symbol.iter().collect::<String>();
+        let Some(before_ending) = word.strip_suffix(self.ending) else {
+            return false;
+        };
+        let vowels_before = stress::vowels_count(before_ending);
+        vowels_before >= self.min_vowels_before
+            && (self.after.is_empty() || self.after.iter().any(|after| before_ending.ends_with(after)))
+    }
+
+    // Whether the stress is where the rule says it is
+    fn predicts(&self, word_without_stress_symbol: &[char], stressed_idx: usize) -> bool {
+        let ending_start = word_without_stress_symbol.len() - self.ending.chars().count();
+
+        let mut vowel_idxs = word_without_stress_symbol
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| stress::is_vowel(**c).unwrap_or(false))
+            .map(|(i, _)| i);
+
+        match self.place {
+            StressPlace::OnEnding(n) => vowel_idxs
+                .filter(|i| *i >= ending_start)
+                .nth(n)
+                .is_some_and(|i| i == stressed_idx),
+            StressPlace::RightBefore => vowel_idxs
+                .rfind(|i| *i < ending_start)
+                .is_some_and(|i| i == stressed_idx),
+            StressPlace::Before => stressed_idx < ending_start,
+        }
+    }
+}
+
+/// Finds the rule about the word (with the stress symbol), `None` if there is none
+/// or the stress of the word is an exception to it
+pub fn find_rule(word_with_stress_symbol: &str) -> Option<&'static StressRule> {
+    let word_without_stress_symbol = stress::remove_stress(word_with_stress_symbol)
+        .to_lowercase()
+        .chars()
+        .collect::<Vec<_>>();
+
+    // Same as in `stress::generate_stress_answers`, the vowel is right before the stress symbol
+    let stressed_idx = word_with_stress_symbol
+        .chars()
+        .position(|c| c == '\u{0301}')?
+        .checked_sub(1)?;
+
+    let rule = STRESS_RULES.iter().find(|rule| rule.fits(&word_without_stress_symbol))?;
+    rule.predicts(&word_without_stress_symbol, stressed_idx).then_some(rule)
+}
+
+pub fn explain_stress(word_with_stress_symbol: &str) -> String {
+    find_rule(word_with_stress_symbol)
+        .map(|rule| rule.explanation)
+        .unwrap_or(NO_RULE_EXPLANATION)
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule_key(word: &str) -> Option<String> {
+        find_rule(word).map(StressRule::key)
+    }
+
+    #[test]
+    fn rules_match_the_stress_on_the_ending() {
+        assert_eq!(rule_key("одина\u{301}дцять").as_deref(), Some("надцять:ending0"));
+        assert_eq!(rule_key("слуха\u{301}ч").as_deref(), Some("ач:ending0"));
+        assert_eq!(rule_key("кіломе\u{301}тр").as_deref(), Some("метр:ending0"));
+        assert_eq!(rule_key("біоло\u{301}гія").as_deref(), Some("логія:ending0"));
+    }
+
+    #[test]
+    fn rules_match_the_stress_before_the_ending() {
+        assert_eq!(rule_key("чита\u{301}ння").as_deref(), Some("ння:right_before"));
+        assert_eq!(rule_key("термо\u{301}метр").as_deref(), Some("метр:right_before"));
+    }
+
+    #[test]
+    fn the_same_ending_is_told_apart_by_the_stress() {
+        assert_eq!(rule_key("шофе\u{301}р").as_deref(), Some("ер:ending0"));
+        assert_eq!(rule_key("ме\u{301}неджер").as_deref(), Some("ер:before"));
+        assert_eq!(rule_key("кіломе\u{301}тр").as_deref(), Some("метр:ending0"));
+        assert_eq!(rule_key("баро\u{301}метр").as_deref(), Some("метр:right_before"));
+    }
+
+    #[test]
+    fn rules_dont_explain_the_exceptions() {
+        // The -и- of жити is the root, and робити is stressed on the root
+        assert_eq!(rule_key("жи\u{301}ти"), None);
+        assert_eq!(rule_key("ро\u{301}бити"), None);
+        assert_eq!(rule_key("ходи\u{301}ти").as_deref(), Some("ити:ending0"));
+        // Not every word on -ер is a loanword the rules are about
+        assert_eq!(rule_key("ма\u{301}йстер"), None);
+        assert_eq!(rule_key("ка\u{301}тер"), None);
+        // The other rule about the same ending isn't taken instead
+        assert_eq!(rule_key("шо\u{301}фер"), None);
+        assert_eq!(rule_key("менедже\u{301}р"), None);
+    }
+
+    #[test]
+    fn words_no_rule_explains() {
+        // The ending matches, the stress doesn't
+        assert_eq!(rule_key("чи\u{301}тання"), None);
+        assert_eq!(rule_key("моло\u{301}ко"), None);
+        // No stress symbol at all
+        assert_eq!(rule_key("слухач"), None);
+        assert_eq!(explain_stress("моло\u{301}ко"), NO_RULE_EXPLANATION);
+    }
+}
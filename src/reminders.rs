@@ -0,0 +1,183 @@
+use std::{sync::Arc, time::Duration};
+
+use chrono::{Timelike, Utc};
+use chrono_tz::Tz;
+use teloxide::{
+    prelude::*,
+    types::{InlineKeyboardButton, InlineKeyboardMarkup},
+};
+
+use crate::db::{Database, Profile, Reminder};
+use crate::mistakes::REDO_CALLBACK_PREFIX;
+use crate::HandlerResult;
+
+// Most of the learners are in Ukraine
+const DEFAULT_TIME_ZONE: Tz = chrono_tz::Europe::Kyiv;
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+// The reminder is still sent this late, e.g. if the bot has been restarting at the time
+const MAX_DELAY_MINUTES: u32 = 15;
+
+fn time_zone(name: Option<&str>) -> Tz {
+    name.and_then(|name| name.parse().ok()).unwrap_or(DEFAULT_TIME_ZONE)
+}
+
+// "19:00" or "9.30" to the minutes from the midnight
+fn parse_time(text: &str) -> Option<u32> {
+    let (hours, minutes) = text.trim().split_once([':', '.'])?;
+    let (hours, minutes) = (hours.parse::<u32>().ok()?, minutes.parse::<u32>().ok()?);
+    (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+}
+
+fn format_time(minutes: u32) -> String {
+    format!("{:02}:{:02}", minutes / 60, minutes % 60)
+}
+
+/// How the reminder is set in the profile
+pub fn describe(profile: &Profile) -> String {
+    match profile.remind_at {
+        Some(remind_at) => format!(
+            "щодня о {} ({})",
+            format_time(remind_at),
+            time_zone(profile.time_zone.as_deref()).name()
+        ),
+        None => "вимкнено".to_string(),
+    }
+}
+
+fn remind_usage() -> String {
+    "Напиши, о котрій нагадувати про тренування, наприклад: /remind 19:00\n\
+    Вимкнути нагадування -- /remind off\n\
+    Змінити часовий пояс -- /timezone"
+        .to_string()
+}
+
+/// `/remind 19:00` or `/remind off`
+pub async fn set_reminder(bot: &Bot, chat_id: ChatId, args: &str, db: &Database) -> HandlerResult {
+    let args = args.trim();
+    let text = if args.is_empty() {
+        format!("Нагадування: {}\n\n{}", describe(&db.get_profile(chat_id).await?), remind_usage())
+    } else if ["off", "вимк", "вимкнути"].contains(&args.to_lowercase().as_str()) {
+        db.set_remind_at(chat_id, None).await?;
+        "Нагадування вимкнено. Увімкнути знову -- /remind 19:00".to_string()
+    } else if let Some(remind_at) = parse_time(args) {
+        db.set_remind_at(chat_id, Some(remind_at)).await?;
+        format!(
+            "Нагадуватиму про тренування {}. Змінити часовий пояс -- /timezone",
+            describe(&db.get_profile(chat_id).await?)
+        )
+    } else {
+        format!("Не вдалося розібрати час.\n\n{}", remind_usage())
+    };
+    bot.send_message(chat_id, text).await?;
+    Ok(())
+}
+
+/// `/timezone Europe/Warsaw`
+pub async fn set_time_zone(bot: &Bot, chat_id: ChatId, args: &str, db: &Database) -> HandlerResult {
+    let args = args.trim();
+    let usage = format!(
+        "Напиши часовий пояс, як його називає база IANA, наприклад: /timezone Europe/Warsaw\n\
+        Якщо пояс не вказано, нагадування надходять за київським часом ({})",
+        DEFAULT_TIME_ZONE.name()
+    );
+    let text = if args.is_empty() {
+        let profile = db.get_profile(chat_id).await?;
+        format!("Твій часовий пояс: {}\n\n{}", time_zone(profile.time_zone.as_deref()).name(), usage)
+    } else if let Ok(tz) = args.parse::<Tz>() {
+        db.set_time_zone(chat_id, tz.name()).await?;
+        let now = Utc::now().with_timezone(&tz);
+        format!("Часовий пояс змінено на {}, там зараз {}", tz.name(), now.format("%H:%M"))
+    } else {
+        format!("Не знаю такого часового поясу.\n\n{}", usage)
+    };
+    bot.send_message(chat_id, text).await?;
+    Ok(())
+}
+
+/// Checks the reminders every minute while the bot is running
+pub fn spawn_scheduler(bot: Bot, db: Arc<Database>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = send_due_reminders(&bot, &db).await {
+                log::error!("Failed to send the reminders: {}", e);
+            }
+        }
+    });
+}
+
+async fn send_due_reminders(bot: &Bot, db: &Database) -> HandlerResult {
+    let now = Utc::now();
+    for Reminder {
+        chat_id,
+        remind_at,
+        time_zone: tz,
+        reminded_on,
+    } in db.reminders().await?
+    {
+        let local = now.with_timezone(&time_zone(tz.as_deref()));
+        let today = local.format("%Y-%m-%d").to_string();
+        let minutes = local.hour() * 60 + local.minute();
+        if reminded_on.as_deref() == Some(today.as_str()) || !(remind_at..remind_at + MAX_DELAY_MINUTES).contains(&minutes)
+        {
+            continue;
+        }
+
+        // Marked before sending, so the chat the message can't be sent to (e.g. the bot is blocked)
+        // isn't tried every minute
+        db.set_reminded_on(chat_id, &today).await?;
+        if let Err(e) = send_reminder(bot, chat_id, db).await {
+            log::warn!("Failed to remind {}: {}", chat_id, e);
+        }
+    }
+    Ok(())
+}
+
+async fn send_reminder(bot: &Bot, chat_id: ChatId, db: &Database) -> HandlerResult {
+    let mut text = "Час потренуватися! 📚 Пройди виклик дня (/daily) або обери тест у меню (/menu)".to_string();
+    let pending = db.pending_mistakes(chat_id).await?.filter(|(_, count)| *count > 0);
+    if let Some((_, count)) = pending {
+        text.push_str(&format!("\n\nНа повторення чекають питання з помилками: {}", count));
+    }
+    text.push_str("\n\nВимкнути нагадування -- /remind off");
+
+    let message = bot.send_message(chat_id, text);
+    match pending {
+        Some((quiz_id, _)) => {
+            message
+                .reply_markup(InlineKeyboardMarkup::new(vec![vec![InlineKeyboardButton::callback(
+                    "Повторити помилки",
+                    format!("{}{}", REDO_CALLBACK_PREFIX, quiz_id),
+                )]]))
+                .await?
+        }
+        None => message.await?,
+    };
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn times_are_parsed_to_minutes() {
+        assert_eq!(parse_time("19:00"), Some(19 * 60));
+        assert_eq!(parse_time("7.05"), Some(7 * 60 + 5));
+        assert_eq!(parse_time(" 00:00 "), Some(0));
+        assert_eq!(parse_time("23:59"), Some(23 * 60 + 59));
+    }
+
+    #[test]
+    fn wrong_times_are_rejected() {
+        for text in ["", "19", "24:00", "12:60", "-1:00", "ab:cd", "19:00:00", "off"] {
+            assert_eq!(parse_time(text), None, "{}", text);
+        }
+    }
+
+    #[test]
+    fn parsed_time_is_formatted_back() {
+        assert_eq!(parse_time("7:05").map(format_time).as_deref(), Some("07:05"));
+    }
+}
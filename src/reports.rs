@@ -0,0 +1,198 @@
+use std::sync::Arc;
+
+use teloxide::{
+    prelude::*,
+    types::{ForceReply, InlineKeyboardButton, InlineKeyboardMarkup, ParseMode},
+};
+
+use crate::admin::{ensure_admin, is_admin};
+use crate::db::{Database, Report};
+use crate::quiz::registry::DataRegistry;
+use crate::{HandlerResult, QuizDialogue, State};
+
+pub const REPORT_CALLBACK_PREFIX: &str = "report:";
+pub const REVIEW_CALLBACK_PREFIX: &str = "review:";
+// The reports are reviewed a few at a time, the oldest first
+const REPORTS_PAGE_SIZE: usize = 10;
+
+/// The button under every question to tell the question is wrong
+pub fn report_button(quiz_id: u64, question_number: usize) -> InlineKeyboardButton {
+    InlineKeyboardButton::callback(
+        "⚠️ Помилка в питанні",
+        format!("{}{}:{}", REPORT_CALLBACK_PREFIX, quiz_id, question_number),
+    )
+}
+
+/// Saves the reported question of the quiz going on and asks what is wrong with it
+pub async fn report_question(bot: Bot, dialogue: QuizDialogue, q: CallbackQuery, db: Arc<Database>) -> HandlerResult {
+    let callback = q
+        .data
+        .as_deref()
+        .and_then(|d| d.strip_prefix(REPORT_CALLBACK_PREFIX))
+        .and_then(|d| d.split_once(':'))
+        .and_then(|(quiz_id, question_number)| Some((quiz_id.parse::<u64>().ok()?, question_number.parse::<usize>().ok()?)));
+
+    let state = dialogue.get_or_default().await?;
+    let quiz = match &state {
+        State::Battle { battle } => Some(&battle.quiz),
+        state => state.quiz_progress().map(|(quiz, _, _)| quiz),
+    };
+    // The questions are only kept while the quiz is going on
+    let question = callback.and_then(|(quiz_id, question_number)| {
+        quiz.filter(|quiz| quiz.id == quiz_id)?.questions.get(question_number)
+    });
+    let Some(question) = question else {
+        bot.answer_callback_query(q.id)
+            .text("Повідомити про помилку можна лише під час тесту")
+            .await?;
+        return Ok(());
+    };
+
+    let chat_id = dialogue.chat_id();
+    let report_id = db.create_report(chat_id, question).await?;
+    bot.answer_callback_query(q.id)
+        .text("Дякую! Питання передано на перевірку")
+        .await?;
+    let prompt = bot
+        .send_message(
+            chat_id,
+            "Що саме не так з питанням? Напиши у відповідь на це повідомлення, це допоможе його виправити",
+        )
+        .reply_markup(ForceReply::new().input_field_placeholder(Some("Що не так?".to_string())))
+        .await?;
+    db.set_report_prompt(report_id, prompt.id.0).await?;
+    Ok(())
+}
+
+/// The message replying to the prompt of `report_question` is the comment to the report
+pub async fn is_report_comment(msg: &Message, db: &Database) -> bool {
+    let Some(prompt) = msg.reply_to_message() else {
+        return false;
+    };
+    matches!(db.report_by_prompt(msg.chat.id, prompt.id.0).await, Ok(Some(_)))
+}
+
+pub async fn receive_report_comment(bot: Bot, msg: Message, db: Arc<Database>) -> HandlerResult {
+    let (Some(prompt), Some(comment)) = (msg.reply_to_message(), msg.text()) else {
+        bot.send_message(msg.chat.id, "Опиши, будь ласка, помилку текстом").await?;
+        return Ok(());
+    };
+    if let Some(report_id) = db.report_by_prompt(msg.chat.id, prompt.id.0).await? {
+        db.set_report_comment(report_id, comment).await?;
+        bot.send_message(msg.chat.id, "Дякую, коментар додано!").await?;
+    }
+    Ok(())
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+fn report_text(report: &Report) -> String {
+    let correct_answer = report
+        .question
+        .answers
+        .iter()
+        .find(|a| a.is_correct)
+        .map(|a| escape_html(&a.text))
+        .unwrap_or_default();
+    format!(
+        "#{} від {} (чат {})\n\n{}\nПравильна відповідь: <b>{}</b>\n\nДжерело: {}\nКоментар: {}",
+        report.id,
+        report.created_on,
+        report.chat_id,
+        // The question's text is HTML already
+        report.question.text,
+        correct_answer,
+        report
+            .question
+            .source
+            .as_ref()
+            .map(|source| escape_html(&source.describe()))
+            .unwrap_or_else(|| "невідоме".to_string()),
+        report.comment.as_deref().map(escape_html).unwrap_or_else(|| "немає".to_string())
+    )
+}
+
+fn review_keyboard(report: &Report) -> InlineKeyboardMarkup {
+    let mut buttons = Vec::new();
+    // Only the item of the question can be blacklisted
+    if report.question.source.is_some() {
+        buttons.push(InlineKeyboardButton::callback(
+            "🚫 У чорний список",
+            format!("{}blacklist:{}", REVIEW_CALLBACK_PREFIX, report.id),
+        ));
+    }
+    buttons.push(InlineKeyboardButton::callback(
+        "Відхилити",
+        format!("{}dismiss:{}", REVIEW_CALLBACK_PREFIX, report.id),
+    ));
+    InlineKeyboardMarkup::new(vec![buttons])
+}
+
+/// `/reports` -- the reports to review, for the admins
+pub async fn list_reports(bot: &Bot, chat_id: ChatId, db: &Database) -> HandlerResult {
+    if !ensure_admin(bot, chat_id).await? {
+        return Ok(());
+    }
+    let (reports, total) = db.open_reports(REPORTS_PAGE_SIZE).await?;
+    if reports.is_empty() {
+        bot.send_message(chat_id, "Нових повідомлень про помилки немає").await?;
+        return Ok(());
+    }
+
+    bot.send_message(
+        chat_id,
+        format!("Нерозглянутих повідомлень про помилки: {}, ось найстаріші з них", total),
+    )
+    .await?;
+    for report in reports {
+        bot.send_message(chat_id, report_text(&report))
+            .parse_mode(ParseMode::Html)
+            .reply_markup(review_keyboard(&report))
+            .await?;
+    }
+    Ok(())
+}
+
+/// The admin's decision on the report: blacklist the item of the question or dismiss the report
+pub async fn review_report(bot: Bot, q: CallbackQuery, db: Arc<Database>, registry: Arc<DataRegistry>) -> HandlerResult {
+    let Some(message) = q.message.as_ref() else {
+        return Ok(());
+    };
+    if !is_admin(message.chat.id) {
+        bot.answer_callback_query(q.id).text("Лише для адміністраторів").await?;
+        return Ok(());
+    }
+    let review = q
+        .data
+        .as_deref()
+        .and_then(|d| d.strip_prefix(REVIEW_CALLBACK_PREFIX))
+        .and_then(|d| d.split_once(':'))
+        .and_then(|(action, id)| Some((action, id.parse::<i64>().ok()?)));
+
+    let reviewed = match review {
+        Some(("blacklist", report_id)) => db.blacklist_report(report_id).await?.map(|source| {
+            registry.datasets().blacklist.add(source);
+            ("Питання з цього джерела більше не питатимуться", "🚫 У чорному списку")
+        }),
+        Some(("dismiss", report_id)) => db
+            .dismiss_report(report_id)
+            .await?
+            .then_some(("Повідомлення відхилено", "Відхилено")),
+        _ => None,
+    };
+    let Some((text, status)) = reviewed else {
+        bot.answer_callback_query(q.id).text("Це повідомлення вже розглянуто").await?;
+        return Ok(());
+    };
+    bot.answer_callback_query(q.id).text(text).await?;
+    // The button only tells how the report is reviewed
+    bot.edit_message_reply_markup(message.chat.id, message.id)
+        .reply_markup(InlineKeyboardMarkup::new(vec![vec![InlineKeyboardButton::callback(
+            status,
+            format!("{}done", REVIEW_CALLBACK_PREFIX),
+        )]]))
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,55 @@
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use axum::{extract::State, http::StatusCode, routing::get, Router};
+use teloxide::{prelude::*, update_listeners::{webhooks, UpdateListener}};
+
+use crate::config::WebhookConfig;
+use crate::db::Database;
+
+/// Registers the webhook and starts the HTTP server Telegram sends the updates to.
+/// The server also answers `/healthz`, for the reverse proxy and the monitoring.
+/// The webhook is removed when the dispatcher stops.
+pub async fn listen(
+    bot: Bot,
+    config: &WebhookConfig,
+    url: url::Url,
+    db: Arc<Database>,
+) -> Result<impl UpdateListener<Err = Infallible>, Box<dyn std::error::Error + Send + Sync>> {
+    // The address is taken first, so the webhook isn't set if the bot can't listen there
+    let server = axum::Server::try_bind(&config.listen_address)
+        .map_err(|e| format!("Failed to listen on {}: {}", config.listen_address, e))?;
+
+    let mut options = webhooks::Options::new(config.listen_address, url);
+    if !config.secret_token.is_empty() {
+        options = options.secret_token(config.secret_token.clone());
+    }
+    let (mut listener, stop_flag, router) = webhooks::axum_to_router(bot, options)
+        .await
+        .map_err(|e| format!("Failed to set the webhook: {}", e))?;
+    let router = router.merge(Router::new().route("/healthz", get(health).with_state(db)));
+
+    let stop_token = listener.stop_token();
+    tokio::spawn(async move {
+        if let Err(e) = server
+            .serve(router.into_make_service())
+            .with_graceful_shutdown(stop_flag)
+            .await
+        {
+            log::error!("The webhook server failed: {}", e);
+            stop_token.stop();
+        }
+    });
+    Ok(listener)
+}
+
+// The bot is healthy as long as it can reach the database
+async fn health(State(db): State<Arc<Database>>) -> (StatusCode, &'static str) {
+    match db.ping().await {
+        Ok(()) => (StatusCode::OK, "ok"),
+        Err(e) => {
+            log::error!("The health check failed: {}", e);
+            (StatusCode::SERVICE_UNAVAILABLE, "database unavailable")
+        }
+    }
+}